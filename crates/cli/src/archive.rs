@@ -0,0 +1,414 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    compress::{self, GzipEncoder},
+    with_suffix,
+};
+
+/// The kind of archive to write every generated file into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ArchiveFormat {
+    /// An uncompressed tar archive
+    Tar,
+    /// A gzip compressed tar archive
+    TarGz,
+    /// A zstd compressed tar archive
+    TarZst,
+    /// A zip archive with stored members, they're compressed already
+    Zip,
+    /// A directory of pack files, one per top-level directory of the output and of every tier
+    /// (or first character of the prefix with --layout flat), each with an index of where its
+    /// files are. For other tools to read, the server doesn't serve from them
+    Pack,
+}
+
+impl ArchiveFormat {
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Tar => ".tar",
+            Self::TarGz => ".tar.gz",
+            Self::TarZst => ".tar.zst",
+            Self::Zip => ".zip",
+            Self::Pack => ".pack",
+        }
+    }
+}
+
+/// The file a tar archive is written to, compressed as a whole or not.
+enum TarFile {
+    Plain(BufWriter<File>),
+    Gzip(GzipEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl TarFile {
+    fn finish(self) -> std::io::Result<BufWriter<File>> {
+        match self {
+            Self::Plain(file) => Ok(file),
+            Self::Gzip(enc) => enc.finish(),
+            Self::Zstd(enc) => enc.finish(),
+        }
+    }
+}
+
+impl Write for TarFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(buf),
+            Self::Gzip(enc) => enc.write(buf),
+            Self::Zstd(enc) => enc.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Gzip(enc) => enc.flush(),
+            Self::Zstd(enc) => enc.flush(),
+        }
+    }
+}
+
+enum Writer {
+    Tar(tar::Builder<TarFile>),
+    Zip(zip::ZipWriter<BufWriter<File>>),
+    Pack(Packs),
+}
+
+/// The pack files of an output, written one after the other in large sequential writes instead
+/// of creating millions of small files, which is dominated by their metadata on ext4 or NFS.
+///
+/// `A.pack` holds the files within `A/` back to back, and `A.idx` has a `path\toffset\tsize`
+/// line for each of them, so a server can read any of them with a single ranged read. The tiers
+/// get packs of their own within their directory, e.g. `tier-100/A.pack`. Files outside of any
+/// pack, like the metadata, are copied into the directory as they are.
+struct Packs {
+    dir: PathBuf,
+    packs: BTreeMap<String, Pack>,
+}
+
+struct Pack {
+    data: BufWriter<File>,
+    index: BufWriter<File>,
+    size: u64,
+}
+
+impl Packs {
+    fn create(dir: PathBuf) -> std::io::Result<Self> {
+        // left over from an interrupted run
+        match std::fs::remove_dir_all(&dir) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            packs: BTreeMap::new(),
+        })
+    }
+
+    /// Appends the file named `name` within the output to its pack.
+    fn append(&mut self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        let Some(key) = pack_key(name) else {
+            return std::fs::write(self.dir.join(name), data);
+        };
+
+        let pack = match self.packs.entry(key.into()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                if let Some((tier, _)) = key.split_once('/') {
+                    std::fs::create_dir_all(self.dir.join(tier))?;
+                }
+                let create = |ext| File::create(self.dir.join(format!("{key}.{ext}")));
+                entry.insert(Pack {
+                    data: BufWriter::with_capacity(1 << 20, create("pack")?),
+                    index: BufWriter::new(create("idx")?),
+                    size: 0,
+                })
+            }
+        };
+        pack.data.write_all(data)?;
+        writeln!(pack.index, "{name}\t{}\t{}", pack.size, data.len())?;
+        pack.size += data.len() as u64;
+        Ok(())
+    }
+
+    fn finish(self) -> std::io::Result<()> {
+        for pack in self.packs.into_values() {
+            for file in [pack.data, pack.index] {
+                file.into_inner()
+                    .map_err(std::io::IntoInnerError::into_error)?
+                    .sync_all()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The pack the file named `name` within the output goes into: its top-level directory, or the
+/// first character of its prefix with --layout flat. Files of a tier go into the packs of its
+/// own tree, like `tier-100/A`. None for files outside of any prefix.
+fn pack_key(name: &str) -> Option<&str> {
+    let tree = match name.split_once('/') {
+        Some((dir, _))
+            if dir
+                .strip_prefix("tier-")
+                .is_some_and(|t| t.parse::<u64>().is_ok()) =>
+        {
+            dir.len() + 1
+        }
+        _ => 0,
+    };
+
+    let len = match name[tree..].split_once('/') {
+        Some((dir, _)) => dir.len(),
+        None => name[tree..]
+            .get(..1)
+            .filter(|first| matches!(first.as_bytes(), [b'0'..=b'9' | b'A'..=b'F']))?
+            .len(),
+    };
+    Some(&name[..tree + len])
+}
+
+/// A single archive the parallel workers append the generated files to, instead of writing
+/// them into the output directory.
+///
+/// It's written under a temporary name and renamed into place by [`Archive::finish`], like an
+/// [`AtomicFile`](crate::generate::AtomicFile).
+pub struct Archive {
+    dist: PathBuf,
+    path: PathBuf,
+    tmp: PathBuf,
+    /// Modification time of every entry, when generation started
+    mtime: u64,
+    writer: Mutex<Writer>,
+}
+
+impl Archive {
+    /// Creates the archive for the output directory `dist` next to it, e.g. `dist.tar.gz`.
+    ///
+    /// When `reproducible` is set, every entry gets the earliest modification time the format has.
+    pub fn create(dist: &Path, format: ArchiveFormat, reproducible: bool) -> Result<Self> {
+        let path = with_suffix(dist, format.suffix());
+        let tmp = with_suffix(&path, ".tmp");
+        if format == ArchiveFormat::Pack {
+            return Ok(Self {
+                dist: dist.into(),
+                path,
+                writer: Mutex::new(Writer::Pack(Packs::create(tmp.clone())?)),
+                tmp,
+                mtime: 0,
+            });
+        }
+
+        let file = BufWriter::new(File::create(&tmp)?);
+        let tar = |file| Writer::Tar(tar::Builder::new(file));
+        let writer = match format {
+            ArchiveFormat::Tar => tar(TarFile::Plain(file)),
+            // at zlib's default level
+            ArchiveFormat::TarGz => tar(TarFile::Gzip(compress::gzip_encoder(file, 6))),
+            ArchiveFormat::TarZst => tar(TarFile::Zstd(zstd::Encoder::new(file, 3)?)),
+            ArchiveFormat::Zip => Writer::Zip(zip::ZipWriter::new(file)),
+            ArchiveFormat::Pack => unreachable!("pack files are created above"),
+        };
+
+        let mtime = if reproducible {
+            0
+        } else {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        };
+
+        Ok(Self {
+            dist: dist.into(),
+            path,
+            tmp,
+            mtime,
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Appends the file that would have been written to `path` within the output directory.
+    pub fn append(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let name = path.strip_prefix(&self.dist).unwrap_or(path);
+
+        match &mut *self.writer.lock().unwrap() {
+            Writer::Tar(builder) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o644);
+                header.set_mtime(self.mtime);
+                builder.append_data(&mut header, name, data)
+            }
+            Writer::Pack(packs) => packs.append(&slash_name(name), data),
+            Writer::Zip(writer) => {
+                let mut options = zip::write::FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored)
+                    .unix_permissions(0o644)
+                    .large_file(data.len() as u64 >= u32::MAX as u64);
+                // entries are stamped with the current time otherwise
+                if self.mtime == 0 {
+                    options = options.last_modified_time(zip::DateTime::default());
+                }
+                writer.start_file(slash_name(name), options)?;
+                writer.write_all(data)
+            }
+        }
+    }
+
+    /// Appends the file at `path` within the output directory as it is on disk.
+    pub fn append_file(&self, path: &Path) -> std::io::Result<()> {
+        self.append(path, &std::fs::read(path)?)
+    }
+
+    /// Writes the end of the archive and renames it into place.
+    pub fn finish(self) -> Result<PathBuf> {
+        let file = match self.writer.into_inner().unwrap() {
+            Writer::Tar(builder) => builder.into_inner()?.finish()?,
+            Writer::Zip(mut writer) => writer.finish()?,
+            Writer::Pack(packs) => {
+                packs.finish()?;
+                // a directory can't be renamed over another one
+                match std::fs::remove_dir_all(&self.path) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(err.into())
+                    }
+                    _ => {}
+                }
+                std::fs::rename(&self.tmp, &self.path)?;
+                return Ok(self.path);
+            }
+        };
+        file.into_inner()
+            .map_err(std::io::IntoInnerError::into_error)?
+            .sync_all()?;
+
+        std::fs::rename(&self.tmp, &self.path)?;
+        Ok(self.path)
+    }
+}
+
+/// `name` with forward slashes, as zip names and pack indexes always use them.
+fn slash_name(name: &Path) -> String {
+    name.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Archives a prefix and the metadata of an output directory unique to the test `name`.
+    fn archive(name: &str, format: ArchiveFormat) -> PathBuf {
+        let dist = std::env::temp_dir().join(format!("hibp-json-{name}-{}", std::process::id()));
+        let archive = Archive::create(&dist, format, true).unwrap();
+        archive.append(&dist.join("A/B/C/D/E.json"), b"[]").unwrap();
+        archive.append(&dist.join("dataset.json"), b"{}").unwrap();
+        archive.finish().unwrap()
+    }
+
+    #[test]
+    fn tar_contents() {
+        let path = archive("tar", ArchiveFormat::Tar);
+        assert!(path.ends_with(format!("hibp-json-tar-{}.tar", std::process::id())));
+
+        let mut tar = tar::Archive::new(File::open(&path).unwrap());
+        let entries: Vec<_> = tar
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                assert_eq!(entry.header().mtime().unwrap(), 0);
+                let mut data = String::new();
+                std::io::Read::read_to_string(&mut entry, &mut data).unwrap();
+                (entry.path().unwrap().display().to_string(), data)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("A/B/C/D/E.json".to_owned(), "[]".to_owned()),
+                ("dataset.json".to_owned(), "{}".to_owned()),
+            ]
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn zip_contents() {
+        let path = archive("zip", ArchiveFormat::Zip);
+        let mut zip = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(zip.len(), 2);
+        for (i, (name, data)) in [("A/B/C/D/E.json", "[]"), ("dataset.json", "{}")]
+            .into_iter()
+            .enumerate()
+        {
+            let mut file = zip.by_index(i).unwrap();
+            assert_eq!(file.name(), name);
+            // compressed already, so they're stored as they are
+            assert_eq!(file.compression(), zip::CompressionMethod::Stored);
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut file, &mut content).unwrap();
+            assert_eq!(content, data);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pack_keys() {
+        assert_eq!(pack_key("A/B/C/D/E.json"), Some("A"));
+        assert_eq!(pack_key("AB/CDE.json.gz"), Some("AB"));
+        assert_eq!(pack_key("ABCDE.json"), Some("A"));
+        assert_eq!(pack_key("tier-100/A/B/C/D/E.json"), Some("tier-100/A"));
+        assert_eq!(pack_key("tier-100/ABCDE.json.br"), Some("tier-100/A"));
+        assert_eq!(pack_key("dataset.json"), None);
+        assert_eq!(pack_key("tier-100/dataset.json"), None);
+    }
+
+    #[test]
+    fn packs_per_tree() {
+        let dist = std::env::temp_dir().join(format!("hibp-json-pack-{}", std::process::id()));
+        let archive = Archive::create(&dist, ArchiveFormat::Pack, false).unwrap();
+        archive
+            .append(&dist.join("A/B/C/D/E.json"), b"[1]")
+            .unwrap();
+        archive
+            .append(&dist.join("A/B/C/D/F.json"), b"[22]")
+            .unwrap();
+        archive
+            .append(&dist.join("tier-100/A/B/C/D/E.json"), b"[]")
+            .unwrap();
+        archive.append(&dist.join("dataset.json"), b"{}").unwrap();
+        let packs = archive.finish().unwrap();
+
+        let read = |name: &str| std::fs::read_to_string(packs.join(name)).unwrap();
+        assert_eq!(read("A.pack"), "[1][22]");
+        assert_eq!(
+            read("A.idx"),
+            "A/B/C/D/E.json\t0\t3\nA/B/C/D/F.json\t3\t4\n"
+        );
+        assert_eq!(read("tier-100/A.pack"), "[]");
+        assert_eq!(read("tier-100/A.idx"), "tier-100/A/B/C/D/E.json\t0\t2\n");
+        assert_eq!(read("dataset.json"), "{}");
+
+        std::fs::remove_dir_all(packs).unwrap();
+    }
+}
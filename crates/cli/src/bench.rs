@@ -0,0 +1,171 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use indicatif::HumanBytes;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use tracing::info;
+
+use crate::{
+    compress::{self, GzipEngine},
+    error::GenerateError,
+    find_all_hash_files,
+    generate::{parse_hash_file, prefix_name, CountOverflow, HashType, ParseOptions},
+    input,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Path to existing hashes
+    #[arg(long, env = "HIBP_JSON_HASHES", default_value = "hashes")]
+    hashes: PathBuf,
+
+    /// How many prefixes to sample, spread evenly over all of them
+    #[arg(long, default_value_t = 200)]
+    sample: usize,
+
+    /// The gzip levels to compare
+    #[arg(long, value_delimiter = ',', default_value = "1,6,9", value_parser = clap::value_parser!(u32).range(0..=9))]
+    gzip_levels: Vec<u32>,
+
+    /// The brotli qualities to compare
+    #[arg(long, value_delimiter = ',', default_value = "5,9,11", value_parser = clap::value_parser!(u32).range(0..=11))]
+    brotli_qualities: Vec<u32>,
+
+    /// The zstd levels to compare
+    #[arg(long, value_delimiter = ',', default_value = "3,9,19", value_parser = clap::value_parser!(i32).range(1..=22))]
+    zstd_levels: Vec<i32>,
+}
+
+/// A compression setting to benchmark.
+#[derive(Debug, Clone, Copy)]
+enum Setting {
+    Gzip(u32),
+    Brotli(u32),
+    Zstd(i32),
+}
+
+impl Setting {
+    fn name(self) -> String {
+        match self {
+            Self::Gzip(level) => format!("gzip {level}"),
+            Self::Brotli(quality) => format!("brotli {quality}"),
+            Self::Zstd(level) => format!("zstd {level}"),
+        }
+    }
+
+    fn compress(self, out: &mut Vec<u8>, data: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Gzip(level) => compress::gzip(out, data, level, GzipEngine::Flate2, false),
+            Self::Brotli(quality) => {
+                compress::brotli(out, data, &compress::brotli_params(quality, false))
+            }
+            Self::Zstd(level) => compress::zstd(out, data, level),
+        }
+    }
+}
+
+/// Serializes the hash file at `path` like the generated .json file of its prefix.
+fn serialize(path: &Path) -> Result<Vec<u8>, GenerateError> {
+    let prefix = prefix_name(path)?.to_uppercase();
+    let content = input::read_all(path).map_err(|source| GenerateError::Read {
+        path: path.into(),
+        source,
+    })?;
+
+    let options = ParseOptions {
+        min_count: 0,
+        strict: false,
+        hash_type: HashType::Sha1,
+        hash_order: false,
+        lenient: false,
+        merge_duplicates: false,
+        count_overflow: CountOverflow::Error,
+    };
+    let mut passwords = Vec::new();
+    parse_hash_file(&prefix, &content, options, &mut passwords).map_err(|(line, kind)| {
+        GenerateError::Parse {
+            path: path.into(),
+            line,
+            kind,
+        }
+    })?;
+
+    serde_json::to_vec(&passwords).map_err(|source| GenerateError::Serialize {
+        path: path.into(),
+        source,
+    })
+}
+
+/// Compresses every sampled file with `setting`, returning the total size and cpu time.
+fn measure(setting: Setting, samples: &[Vec<u8>]) -> io::Result<(u64, Duration)> {
+    samples
+        .par_iter()
+        .map_init(Vec::new, |out, data| {
+            out.clear();
+            let start = Instant::now();
+            setting.compress(out, data)?;
+            Ok((out.len() as u64, start.elapsed()))
+        })
+        .try_reduce(|| (0, Duration::ZERO), |a, b| Ok((a.0 + b.0, a.1 + b.1)))
+}
+
+pub fn run(args: &BenchArgs) -> Result<()> {
+    let paths = find_all_hash_files(&args.hashes)?;
+    let step = (paths.len() / args.sample.max(1)).max(1);
+    let sample: Vec<_> = paths.iter().step_by(step).take(args.sample).collect();
+    info!(
+        "Benchmarking compression on {} of {} hash files",
+        sample.len(),
+        paths.len()
+    );
+
+    let samples = sample
+        .par_iter()
+        .map(|path| serialize(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let json: u64 = samples.iter().map(|data| data.len() as u64).sum();
+
+    let settings = args
+        .gzip_levels
+        .iter()
+        .map(|level| Setting::Gzip(*level))
+        .chain(args.brotli_qualities.iter().map(|q| Setting::Brotli(*q)))
+        .chain(args.zstd_levels.iter().map(|level| Setting::Zstd(*level)));
+
+    // the time is summed over the workers, so it's the cpu time a full run would take per file
+    let per_file = |time: Duration| time / u32::try_from(samples.len().max(1)).unwrap_or(u32::MAX);
+    let extrapolate = |n: u64| {
+        let n = u128::from(n) * paths.len() as u128 / samples.len().max(1) as u128;
+        u64::try_from(n).unwrap_or(u64::MAX)
+    };
+
+    println!(
+        "{:<10} {:>10} {:>7} {:>12} {:>10}",
+        "setting", "size", "ratio", "per file", "estimate"
+    );
+    println!(
+        "{:<10} {:>10} {:>7} {:>12} {:>10}",
+        "json",
+        HumanBytes(json).to_string(),
+        "1.000",
+        "-",
+        HumanBytes(extrapolate(json)).to_string()
+    );
+    for setting in settings {
+        let (size, time) = measure(setting, &samples)?;
+        println!(
+            "{:<10} {:>10} {:>7.3} {:>12} {:>10}",
+            setting.name(),
+            HumanBytes(size).to_string(),
+            size as f64 / json.max(1) as f64,
+            format!("{:.2?}", per_file(time)),
+            HumanBytes(extrapolate(size)).to_string()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::{
+    collections::HashSet,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use hibp_json_util::parse_prefix;
+
+/// Append-only log of every prefix whose outputs have all been written.
+///
+/// Each completed prefix is appended as a single `ABCDE\n` write, so an interrupted run leaves at
+/// worst a truncated last line which is ignored when resuming.
+pub struct Checkpoint {
+    file: Mutex<File>,
+    done: HashSet<u32>,
+}
+
+impl Checkpoint {
+    pub const FILE_NAME: &'static str = ".hibp-json-checkpoint";
+
+    /// Opens the checkpoint inside `dist`, starting from scratch unless `resume` is set.
+    pub fn open(dist: &Path, resume: bool) -> Result<Self> {
+        let path = dist.join(Self::FILE_NAME);
+        let mut done = HashSet::new();
+
+        if resume && path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                if let Some(prefix) = parse_prefix(&line?) {
+                    done.insert(prefix);
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .truncate(false)
+            .open(&path)?;
+        if !resume {
+            file.set_len(0)?;
+        }
+
+        Ok(Self {
+            file: Mutex::new(file),
+            done,
+        })
+    }
+
+    pub fn is_done(&self, prefix: u32) -> bool {
+        self.done.contains(&prefix)
+    }
+
+    /// Amount of prefixes that were already completed when the checkpoint was opened.
+    pub fn len(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.done.is_empty()
+    }
+
+    /// Records that every output of `prefix` has been written.
+    pub fn complete(&self, prefix: &str) -> std::io::Result<()> {
+        let line = format!("{prefix}\n");
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(line.as_bytes())
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use hibp_json_util::PathTemplate;
+use indicatif::HumanBytes;
+use serde::Deserialize;
+use tracing::info;
+use walkdir::WalkDir;
+
+use crate::{
+    dataset::Dataset,
+    find_all_hash_files,
+    generate::{json_path, prefix_name},
+    manifest::ManifestWriter,
+    metadata_files, output_depth, tier_dir, Layout,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct CleanArgs {
+    /// Path to the generated files
+    #[arg(env = "HIBP_JSON_OUT", default_value = "dist")]
+    dist: PathBuf,
+
+    /// Also remove the outputs of prefixes without a hash file in this directory
+    #[arg(long)]
+    hashes: Option<PathBuf>,
+
+    /// The --depth the files were generated with
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(0..=4))]
+    depth: u8,
+
+    /// The --layout the files were generated with
+    #[arg(long, value_enum, default_value_t = Layout::Nested)]
+    layout: Layout,
+
+    /// The --path-template the files were generated with
+    #[arg(long, conflicts_with_all = ["depth", "layout"])]
+    path_template: Option<PathTemplate>,
+
+    /// List what would be removed without removing anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// The formats and tiers of the last run, read from its dataset.json.
+#[derive(Deserialize)]
+struct DatasetFormats {
+    formats: Vec<String>,
+    #[serde(default)]
+    tiers: Vec<u64>,
+}
+
+/// Why a file is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    /// Left behind by an interrupted run
+    Temporary,
+    /// A format that wasn't enabled in the last run
+    Disabled,
+    /// The prefix no longer has a hash file
+    Orphaned,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Temporary => "temporary",
+            Self::Disabled => "disabled format",
+            Self::Orphaned => "orphaned",
+        })
+    }
+}
+
+/// The generated format of an output file and the .json path it belongs to, or none for files
+/// that aren't outputs.
+fn classify(path: &Path) -> Option<(&'static str, PathBuf)> {
+    let compressed = match path.extension().and_then(OsStr::to_str) {
+        Some("gz") => Some("gzip"),
+        Some("br") => Some("brotli"),
+        Some("dcb") => Some("dcb"),
+        Some("zst") => Some("zstd"),
+        _ => None,
+    };
+    let base = match compressed {
+        Some(_) => path.with_extension(""),
+        None => path.into(),
+    };
+
+    match base.extension().and_then(OsStr::to_str)? {
+        "json" => Some((compressed.unwrap_or("json"), base)),
+        // converted outputs, they're kept until their prefix is gone
+        "ndjson" | "csv" => Some(("converted", base.with_extension("json"))),
+        _ => None,
+    }
+}
+
+/// The .json path of every prefix that still has a hash file, in the full tree and every tier.
+fn expected_paths(args: &CleanArgs, hashes: &Path, tiers: &[u64]) -> Result<HashSet<PathBuf>> {
+    let depth = output_depth(args.layout, args.depth, args.path_template.is_some());
+    let trees: Vec<_> = std::iter::once(args.dist.clone())
+        .chain(tiers.iter().map(|tier| tier_dir(&args.dist, *tier)))
+        .collect();
+
+    let mut expected = HashSet::new();
+    for path in find_all_hash_files(hashes)? {
+        // outputs are named in uppercase, whatever the case of the hash files
+        let prefix = prefix_name(&path)?.to_ascii_uppercase();
+        for tree in &trees {
+            expected.insert(json_path(tree, depth, args.path_template.as_ref(), &prefix));
+        }
+    }
+
+    Ok(expected)
+}
+
+/// Every file in `dist` that should be removed, and why.
+fn find_removable(args: &CleanArgs) -> Result<Vec<(PathBuf, Reason)>> {
+    let dataset = match std::fs::read(args.dist.join(Dataset::FILE_NAME)) {
+        Ok(content) => Some(serde_json::from_slice::<DatasetFormats>(&content)?),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    let tiers = dataset.as_ref().map_or(&[][..], |d| &d.tiers);
+    let formats = dataset.as_ref().map(|d| &d.formats);
+    let expected = args
+        .hashes
+        .as_deref()
+        .map(|hashes| expected_paths(args, hashes, tiers))
+        .transpose()?;
+    let metadata: Vec<_> = metadata_files(&args.dist).collect();
+
+    let mut removable = Vec::new();
+    for entry in WalkDir::new(&args.dist).min_depth(1) {
+        let entry = entry?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || metadata.iter().any(|m| m == path) {
+            continue;
+        }
+
+        if path.extension() == Some(OsStr::new("tmp")) {
+            removable.push((path.into(), Reason::Temporary));
+            continue;
+        }
+
+        let Some((format, json)) = classify(path) else {
+            continue;
+        };
+        let disabled = formats.is_some_and(|formats| {
+            ["json", "gzip", "brotli", "dcb"].contains(&format)
+                && !formats.iter().any(|f| f == format)
+        });
+        if disabled {
+            removable.push((path.into(), Reason::Disabled));
+        } else if expected.as_ref().is_some_and(|e| !e.contains(&json)) {
+            removable.push((path.into(), Reason::Orphaned));
+        }
+    }
+
+    Ok(removable)
+}
+
+/// Removes the empty directories within `dist`, deepest first. Returns how many were removed.
+fn remove_empty_directories(dist: &Path, dry_run: bool) -> Result<usize> {
+    let mut removed = 0;
+    for entry in WalkDir::new(dist).min_depth(1).contents_first(true) {
+        let entry = entry?;
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        if std::fs::read_dir(path)?.next().is_none() {
+            if dry_run {
+                println!("{} (empty directory)", path.display());
+            } else {
+                std::fs::remove_dir(path)
+                    .with_context(|| format!("unable to remove {}", path.display()))?;
+            }
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+pub fn run(args: &CleanArgs) -> Result<()> {
+    let removable = find_removable(args)?;
+
+    let manifest = if args.dry_run {
+        None
+    } else {
+        ManifestWriter::open_existing(&args.dist)?
+    };
+
+    let mut bytes = 0;
+    for (path, reason) in &removable {
+        bytes += std::fs::metadata(path).map_or(0, |m| m.len());
+        if args.dry_run {
+            println!("{} ({reason})", path.display());
+            continue;
+        }
+
+        std::fs::remove_file(path)
+            .with_context(|| format!("unable to remove {}", path.display()))?;
+        if let Some(manifest) = &manifest {
+            manifest.remove(path);
+        }
+    }
+
+    if let Some(manifest) = manifest {
+        manifest.save()?;
+    }
+
+    // a dry run can't see the directories its files would have emptied
+    let directories = remove_empty_directories(&args.dist, args.dry_run)?;
+
+    let verb = if args.dry_run {
+        "Would remove"
+    } else {
+        "Removed"
+    };
+    info!(
+        "{verb} {} files ({}) and {directories} empty directories in {}",
+        removable.len(),
+        HumanBytes(bytes),
+        args.dist.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A directory unique to the test `name`, with the `files` in it.
+    fn dir(name: &str, files: &[&str]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("hibp-json-clean-{name}-{}", std::process::id()));
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        dir
+    }
+
+    fn args(dist: &Path, hashes: Option<&Path>) -> CleanArgs {
+        CleanArgs {
+            dist: dist.into(),
+            hashes: hashes.map(Into::into),
+            depth: 4,
+            layout: Layout::Nested,
+            path_template: None,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn keeps_expected_files() {
+        let hashes = dir("keeps-hashes", &["00000.txt"]);
+        let dist = dir(
+            "keeps-dist",
+            &[
+                "0/0/0/0/0.json",
+                "0/0/0/0/0.json.gz",
+                "0/0/0/0/0.json.br",
+                "0/0/0/0/1.json.tmp",
+                "0/0/0/0/2.json",
+                "notes.txt",
+            ],
+        );
+        std::fs::write(
+            dist.join(Dataset::FILE_NAME),
+            r#"{"formats":["json","gzip"]}"#,
+        )
+        .unwrap();
+
+        let mut removable = find_removable(&args(&dist, None)).unwrap();
+        removable.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            removable,
+            [
+                (dist.join("0/0/0/0/0.json.br"), Reason::Disabled),
+                (dist.join("0/0/0/0/1.json.tmp"), Reason::Temporary),
+            ]
+        );
+
+        // only with the hash files are the outputs of other prefixes orphaned
+        let removable = find_removable(&args(&dist, Some(&hashes))).unwrap();
+        assert!(removable.contains(&(dist.join("0/0/0/0/2.json"), Reason::Orphaned)));
+        assert_eq!(removable.len(), 3);
+
+        std::fs::remove_dir_all(hashes).unwrap();
+        std::fs::remove_dir_all(dist).unwrap();
+    }
+
+    #[test]
+    fn lowercase_hash_files() {
+        let hashes = dir("lowercase-hashes", &["abcde.txt"]);
+        let dist = dir("lowercase-dist", &["A/B/C/D/E.json", "0/0/0/0/0.json"]);
+
+        let removable = find_removable(&args(&dist, Some(&hashes))).unwrap();
+        assert_eq!(removable, [(dist.join("0/0/0/0/0.json"), Reason::Orphaned)]);
+
+        std::fs::remove_dir_all(hashes).unwrap();
+        std::fs::remove_dir_all(dist).unwrap();
+    }
+}
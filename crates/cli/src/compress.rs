@@ -0,0 +1,325 @@
+//! The codecs of the compressed formats. Builds without the `gzip` or `brotli` feature don't link
+//! theirs, and fail on any use of them instead.
+
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[cfg(feature = "brotli")]
+pub use brotli_codec::{
+    brotli, brotli_decoder, brotli_encoder, brotli_params, brotli_with_dictionary,
+    unbrotli_with_dictionary, BrotliParams,
+};
+#[cfg(feature = "gzip")]
+pub use gzip_codec::{gzip, gzip_decoder, gzip_encoder, GzipEncoder};
+#[cfg(not(feature = "brotli"))]
+pub use without_brotli::{
+    brotli, brotli_decoder, brotli_encoder, brotli_params, brotli_with_dictionary,
+    unbrotli_with_dictionary, BrotliParams,
+};
+#[cfg(not(feature = "gzip"))]
+pub use without_gzip::{gzip, gzip_decoder, gzip_encoder, GzipEncoder};
+
+/// Whether this build can write and read .gz files, it needs the `gzip` feature.
+pub const GZIP: bool = cfg!(feature = "gzip");
+
+/// Whether this build can write and read .br and .dcb files, it needs the `brotli` feature.
+pub const BROTLI: bool = cfg!(feature = "brotli");
+
+/// The encoder used for the .gz files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GzipEngine {
+    /// zlib-ng through flate2, fast
+    Flate2,
+    /// Zopfli, a few percent smaller but orders of magnitude slower
+    Zopfli,
+}
+
+/// Zstandard compresses `data` into `out` at compression `level` (1 to 22).
+pub fn zstd(out: &mut impl Write, data: &[u8], level: i32) -> io::Result<()> {
+    zstd::stream::copy_encode(data, out, level)
+}
+
+#[cfg(not(all(feature = "gzip", feature = "brotli")))]
+fn unsupported(format: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("built without {format} support, enable the {format} feature"),
+    )
+}
+
+#[cfg(feature = "gzip")]
+mod gzip_codec {
+    use std::io::{self, Read, Write};
+
+    use flate2::Compression;
+
+    use super::GzipEngine;
+
+    pub type GzipEncoder<W> = flate2::write::GzEncoder<W>;
+
+    /// Gzip compresses `data` into `out` at compression `level` (0 to 9) with `engine`.
+    ///
+    /// When `reproducible` is set the pure rust deflate implementation is used instead of
+    /// zlib-ng, whose output depends on the SIMD features of the cpu, along with a fixed header.
+    /// Zopfli ignores `level` and is always reproducible.
+    pub fn gzip(
+        out: &mut impl Write,
+        data: &[u8],
+        level: u32,
+        engine: GzipEngine,
+        reproducible: bool,
+    ) -> io::Result<()> {
+        if engine == GzipEngine::Zopfli {
+            return zopfli::compress(zopfli::Options::default(), zopfli::Format::Gzip, data, out);
+        }
+
+        if reproducible {
+            // the default level keeps the bytes written before the level was configurable, with
+            // miniz's extra level 10 and the best compression flag
+            let (level, flags) = match level {
+                9.. => (10, 2),
+                level => (u8::try_from(level).unwrap_or(9), 0),
+            };
+            // magic, deflate, no flags, zero mtime, compression flags, unknown os
+            out.write_all(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, flags, 255])?;
+            out.write_all(&miniz_oxide::deflate::compress_to_vec(data, level))?;
+            out.write_all(&crc32fast::hash(data).to_le_bytes())?;
+            // the size is stored modulo 2^32
+            out.write_all(&(data.len() as u32).to_le_bytes())?;
+            return Ok(());
+        }
+
+        let mut enc = flate2::write::GzEncoder::new(out, Compression::new(level));
+        enc.write_all(data)?;
+        enc.finish().map(drop)
+    }
+
+    /// Gzip compresses everything written to it into `out` at compression `level`, until it's
+    /// finished.
+    pub fn gzip_encoder<W: Write>(out: W, level: u32) -> GzipEncoder<W> {
+        GzipEncoder::new(out, Compression::new(level))
+    }
+
+    /// Decodes every gzip member read from `input`.
+    pub fn gzip_decoder<R: Read>(input: R) -> flate2::read::MultiGzDecoder<R> {
+        flate2::read::MultiGzDecoder::new(input)
+    }
+}
+
+#[cfg(not(feature = "gzip"))]
+mod without_gzip {
+    use std::{
+        io::{self, Read, Write},
+        marker::PhantomData,
+    };
+
+    use super::{unsupported, GzipEngine};
+
+    pub fn gzip(
+        _out: &mut impl Write,
+        _data: &[u8],
+        _level: u32,
+        _engine: GzipEngine,
+        _reproducible: bool,
+    ) -> io::Result<()> {
+        Err(unsupported("gzip"))
+    }
+
+    pub struct GzipEncoder<W>(PhantomData<W>);
+
+    impl<W> GzipEncoder<W> {
+        pub fn finish(self) -> io::Result<W> {
+            Err(unsupported("gzip"))
+        }
+    }
+
+    impl<W> Write for GzipEncoder<W> {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(unsupported("gzip"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub fn gzip_encoder<W: Write>(_out: W, _level: u32) -> GzipEncoder<W> {
+        GzipEncoder(PhantomData)
+    }
+
+    pub struct GzipDecoder;
+
+    impl Read for GzipDecoder {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(unsupported("gzip"))
+        }
+    }
+
+    pub fn gzip_decoder(_input: impl Read) -> GzipDecoder {
+        GzipDecoder
+    }
+}
+
+#[cfg(feature = "brotli")]
+mod brotli_codec {
+    use std::io::{self, Read, Write};
+
+    pub use brotli::enc::BrotliEncoderParams as BrotliParams;
+
+    /// Brotli compresses `data` into `out`.
+    pub fn brotli(out: &mut impl Write, data: &[u8], params: &BrotliParams) -> io::Result<()> {
+        brotli::BrotliCompress(&mut &data[..], out, params).map(drop)
+    }
+
+    /// Brotli compresses everything written to it into `out`, until it's taken back out.
+    pub fn brotli_encoder<W: Write>(out: W, params: &BrotliParams) -> brotli::CompressorWriter<W> {
+        brotli::CompressorWriter::with_params(out, 4096, params)
+    }
+
+    /// Decodes the brotli stream read from `input`.
+    pub fn brotli_decoder<R: Read>(input: R) -> brotli::Decompressor<R> {
+        brotli::Decompressor::new(input, 4096)
+    }
+
+    /// Brotli compresses `data` into `out` with a shared `dictionary`, which decoders need as
+    /// well.
+    pub fn brotli_with_dictionary(
+        out: &mut impl Write,
+        data: &[u8],
+        params: &BrotliParams,
+        dictionary: &[u8],
+    ) -> io::Result<()> {
+        let mut input_buffer = [0; 4096];
+        let mut output_buffer = [0; 4096];
+        brotli::enc::BrotliCompressCustomIoCustomDict(
+            &mut brotli::IoReaderWrapper(&mut &data[..]),
+            &mut brotli::IoWriterWrapper(out),
+            &mut input_buffer,
+            &mut output_buffer,
+            params,
+            brotli::enc::StandardAlloc::default(),
+            &mut |_, _, _, _| (),
+            dictionary,
+            io::Error::from(io::ErrorKind::UnexpectedEof),
+        )
+        .map(drop)
+    }
+
+    /// Decodes `data` written by [`brotli_with_dictionary`] with the same `dictionary`.
+    pub fn unbrotli_with_dictionary(data: &[u8], dictionary: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        brotli::BrotliDecompressCustomIoCustomDict(
+            &mut brotli::IoReaderWrapper(&mut &data[..]),
+            &mut brotli::IoWriterWrapper(&mut out),
+            &mut [0; 4096],
+            &mut [0; 4096],
+            brotli::enc::StandardAlloc::default(),
+            brotli::enc::StandardAlloc::default(),
+            brotli::enc::StandardAlloc::default(),
+            dictionary.to_vec().into(),
+            io::Error::from(io::ErrorKind::UnexpectedEof),
+        )?;
+        Ok(out)
+    }
+
+    /// Encoder parameters at `quality` (0 to 11), pinned to explicit values when `reproducible`
+    /// is set so that a change of the brotli crate's defaults can't change the output.
+    pub fn brotli_params(quality: u32, reproducible: bool) -> BrotliParams {
+        let mut params = brotli::enc::BrotliEncoderInitParams();
+        params.quality = i32::try_from(quality).unwrap_or(11);
+        if reproducible {
+            params.lgwin = 22;
+            params.size_hint = 0;
+        }
+
+        params
+    }
+}
+
+#[cfg(not(feature = "brotli"))]
+mod without_brotli {
+    use std::io::{self, Read, Write};
+
+    use super::unsupported;
+
+    pub struct BrotliParams;
+
+    pub fn brotli(_out: &mut impl Write, _data: &[u8], _params: &BrotliParams) -> io::Result<()> {
+        Err(unsupported("brotli"))
+    }
+
+    pub struct BrotliEncoder<W>(W);
+
+    impl<W> BrotliEncoder<W> {
+        pub fn into_inner(self) -> W {
+            self.0
+        }
+    }
+
+    impl<W> Write for BrotliEncoder<W> {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(unsupported("brotli"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub fn brotli_encoder<W: Write>(out: W, _params: &BrotliParams) -> BrotliEncoder<W> {
+        BrotliEncoder(out)
+    }
+
+    pub struct BrotliDecoder;
+
+    impl Read for BrotliDecoder {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(unsupported("brotli"))
+        }
+    }
+
+    pub fn brotli_decoder(_input: impl Read) -> BrotliDecoder {
+        BrotliDecoder
+    }
+
+    pub fn brotli_with_dictionary(
+        _out: &mut impl Write,
+        _data: &[u8],
+        _params: &BrotliParams,
+        _dictionary: &[u8],
+    ) -> io::Result<()> {
+        Err(unsupported("brotli"))
+    }
+
+    pub fn unbrotli_with_dictionary(_data: &[u8], _dictionary: &[u8]) -> io::Result<Vec<u8>> {
+        Err(unsupported("brotli"))
+    }
+
+    pub fn brotli_params(_quality: u32, _reproducible: bool) -> BrotliParams {
+        BrotliParams
+    }
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    #[test]
+    fn reproducible_gzip_keeps_best_compression_header() {
+        let data = br#"[{"hash":"0018A45C4D1DEF81644B54AB7F969B88D65","count":1}]"#;
+        for (level, flags) in [(9, 2), (6, 0)] {
+            let mut out = Vec::new();
+            gzip(&mut out, data, level, GzipEngine::Flate2, true).unwrap();
+            assert_eq!(out[8], flags);
+
+            let mut plain = Vec::new();
+            gzip_decoder(&out[..]).read_to_end(&mut plain).unwrap();
+            assert_eq!(plain, data);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{parser::ValueSource, ArgMatches};
+use serde::Deserialize;
+
+use crate::{
+    archive::ArchiveFormat,
+    compress::GzipEngine,
+    generate::{CountOverflow, HashType},
+    input::{IoBackend, RemoveSource},
+    memory,
+    ndjson::{self, StdoutFormat},
+    progress::ProgressMode,
+    stdin, uring, Args, Case, Layout, OnExisting, SortBy,
+};
+
+/// The `hibp-json.toml` configuration file, shared with the server which reads its own section.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub generate: GenerateConfig,
+}
+
+/// Generator settings, each of them only applies if it wasn't set by a flag or environment
+/// variable.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct GenerateConfig {
+    hashes: Option<PathBuf>,
+    hashes_file: Option<PathBuf>,
+    out: Option<PathBuf>,
+    version_label: Option<String>,
+    stdout_format: Option<StdoutFormat>,
+    tmp_dir: Option<PathBuf>,
+    strict: Option<bool>,
+    lenient: Option<bool>,
+    merge_duplicates: Option<bool>,
+    count_overflow: Option<CountOverflow>,
+    hash_type: Option<HashType>,
+    input_extensions: Option<Vec<String>>,
+    glob: Option<String>,
+    gzip: Option<bool>,
+    brotli: Option<bool>,
+    json: Option<bool>,
+    gzip_level: Option<u32>,
+    gzip_engine: Option<GzipEngine>,
+    brotli_quality: Option<u32>,
+    brotli_dictionary: Option<PathBuf>,
+    min_count: Option<u64>,
+    exclude_hashes: Option<PathBuf>,
+    only_hashes: Option<PathBuf>,
+    top: Option<usize>,
+    tiers: Option<Vec<u64>>,
+    sort_by: Option<SortBy>,
+    case: Option<Case>,
+    hash_field: Option<String>,
+    count_field: Option<String>,
+    with_rank: Option<bool>,
+    on_existing: Option<OnExisting>,
+    depth: Option<u8>,
+    layout: Option<Layout>,
+    path_template: Option<String>,
+    manifest: Option<bool>,
+    summary: Option<bool>,
+    report: Option<PathBuf>,
+    space_check: Option<bool>,
+    static_site: Option<bool>,
+    keep_going: Option<bool>,
+    retries: Option<u32>,
+    reproducible: Option<bool>,
+    self_check: Option<bool>,
+    skip_larger_compressed: Option<bool>,
+    fast_json: Option<bool>,
+    remove_source: Option<RemoveSource>,
+    progress: Option<ProgressMode>,
+    threads: Option<usize>,
+    nice: Option<bool>,
+    mmap: Option<bool>,
+    stream: Option<bool>,
+    io_backend: Option<IoBackend>,
+    archive: Option<ArchiveFormat>,
+    previous: Option<PathBuf>,
+    upload_concurrency: Option<usize>,
+    upload_retries: Option<usize>,
+    upload_retry_timeout: Option<u64>,
+    io_threads: Option<usize>,
+    read_ahead: Option<usize>,
+    max_memory: Option<String>,
+    encrypt: Option<String>,
+}
+
+impl Config {
+    pub const FILE_NAME: &'static str = "hibp-json.toml";
+
+    /// Reads the config at `path`, or `hibp-json.toml` in the working directory if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(Self::FILE_NAME).exists() => Path::new(Self::FILE_NAME),
+            None => return Ok(Self::default()),
+        };
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read config {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid config {}", path.display()))
+    }
+}
+
+impl GenerateConfig {
+    /// Layers the config below the flags and environment variables in `matches`.
+    pub(crate) fn apply(self, args: &mut Args, matches: &ArgMatches) -> Result<()> {
+        let unset = |id: &str| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+
+        macro_rules! layer {
+            ($($field:ident),* $(,)?) => {$(
+                if let Some(value) = self.$field {
+                    if unset(stringify!($field)) {
+                        args.$field = value.into();
+                    }
+                }
+            )*};
+        }
+
+        layer!(
+            hashes,
+            hashes_file,
+            out,
+            version_label,
+            stdout_format,
+            tmp_dir,
+            strict,
+            lenient,
+            merge_duplicates,
+            count_overflow,
+            hash_type,
+            input_extensions,
+            gzip,
+            brotli,
+            json,
+            gzip_level,
+            gzip_engine,
+            brotli_quality,
+            brotli_dictionary,
+            min_count,
+            exclude_hashes,
+            only_hashes,
+            top,
+            tiers,
+            sort_by,
+            case,
+            hash_field,
+            count_field,
+            with_rank,
+            on_existing,
+            depth,
+            layout,
+            manifest,
+            summary,
+            report,
+            space_check,
+            static_site,
+            keep_going,
+            retries,
+            reproducible,
+            self_check,
+            skip_larger_compressed,
+            fast_json,
+            remove_source,
+            progress,
+            threads,
+            nice,
+            mmap,
+            stream,
+            io_backend,
+            archive,
+            previous,
+            upload_concurrency,
+            upload_retries,
+            upload_retry_timeout,
+            io_threads,
+            read_ahead,
+        );
+
+        if let Some(template) = self.path_template {
+            if unset("path_template") {
+                args.path_template = Some(template.parse()?);
+            }
+        }
+        if let Some(glob) = self.glob {
+            if unset("glob") {
+                args.glob = Some(glob.parse()?);
+            }
+        }
+        if let Some(spec) = self.encrypt {
+            if unset("encrypt") {
+                args.encrypt = Some(spec.parse().map_err(anyhow::Error::msg)?);
+            }
+        }
+        if let Some(size) = self.max_memory {
+            if unset("max_memory") {
+                args.max_memory = Some(memory::parse_size(&size).map_err(anyhow::Error::msg)?);
+            }
+        }
+
+        anyhow::ensure!(
+            !args.stream
+                || (args.top.is_none()
+                    && args.tiers.is_empty()
+                    && !args.reproducible
+                    && args.sort_by == SortBy::Hash),
+            "stream can't be combined with top, tiers, reproducible, or sort-by"
+        );
+        if args.io_backend == IoBackend::Uring {
+            anyhow::ensure!(
+                uring::SUPPORTED,
+                "io-backend uring needs a Linux build with the io-uring feature"
+            );
+            anyhow::ensure!(
+                !args.mmap && !args.stream,
+                "io-backend uring can't be combined with mmap or stream"
+            );
+        }
+        anyhow::ensure!(
+            args.archive.is_none()
+                || (!args.stream && args.io_backend != IoBackend::Uring && !args.incremental),
+            "archive can't be combined with stream, io-backend uring, or incremental"
+        );
+        anyhow::ensure!(
+            args.previous.is_none()
+                || (args.manifest
+                    && args.archive.is_none()
+                    && !args.stream
+                    && args.io_backend != IoBackend::Uring),
+            "previous needs the manifest and can't be combined with archive, stream, or io-backend uring"
+        );
+        anyhow::ensure!(
+            !args.stream || (!args.self_check && !args.merge_duplicates),
+            "stream can't be combined with self-check or merge-duplicates"
+        );
+        anyhow::ensure!(
+            !args.skip_larger_compressed || (args.json && !args.stream),
+            "skip-larger-compressed needs the json format to fall back to and can't be combined \
+             with stream"
+        );
+        anyhow::ensure!(
+            args.remove_source.is_none()
+                || (!args.stream
+                    && args.archive.is_none()
+                    && !args.incremental
+                    && !args.watch
+                    && !args.dry_run
+                    && !ndjson::is_stdout(&args.out)
+                    && (!stdin::is_stdin(&args.hashes) || args.hashes_file.is_some())),
+            "remove-source can't be combined with stream, archive, incremental, watch, dry-run, \
+             out -, or hashes -"
+        );
+        anyhow::ensure!(
+            !args.stream || args.gzip_engine == GzipEngine::Flate2,
+            "stream can't be combined with gzip-engine zopfli"
+        );
+        anyhow::ensure!(
+            !args.stream || args.brotli_dictionary.is_none(),
+            "stream can't be combined with brotli-dictionary"
+        );
+        anyhow::ensure!(
+            args.on_existing == OnExisting::Overwrite
+                || (args.archive.is_none()
+                    && !args.stream
+                    && args.io_backend != IoBackend::Uring
+                    && !args.incremental
+                    && !args.watch),
+            "on-existing skip or error can't be combined with archive, stream, io-backend uring, \
+             incremental, or watch"
+        );
+        anyhow::ensure!(
+            args.encrypt.is_none()
+                || (!args.stream
+                    && args.io_backend != IoBackend::Uring
+                    && !args.reproducible
+                    && !args.static_site),
+            "encrypt can't be combined with stream, io-backend uring, reproducible, or static-site"
+        );
+        anyhow::ensure!(
+            !args.with_rank || (!args.incremental && !args.watch),
+            "with-rank can't be combined with incremental or watch, as every rank changes with \
+             the hash files"
+        );
+        anyhow::ensure!(
+            !ndjson::is_stdout(&args.out)
+                || (!args.stream
+                    && args.tiers.is_empty()
+                    && args.archive.is_none()
+                    && args.previous.is_none()
+                    && !args.static_site
+                    && !args.incremental
+                    && !args.resume
+                    && !args.watch
+                    && !args.dry_run
+                    && args.sign_key.is_none()
+                    && args.encrypt.is_none()
+                    && !args.summary),
+            "out - can't be combined with stream, tiers, archive, previous, static-site, \
+             incremental, resume, watch, dry-run, sign-key, encrypt, or summary"
+        );
+        anyhow::ensure!(
+            !stdin::is_stdin(&args.hashes)
+                || args.hashes_file.is_some()
+                || (!args.incremental
+                    && !args.watch
+                    && !args.with_rank
+                    && !args.dry_run
+                    && !args.keep_going
+                    && args.train_brotli_dictionary.is_none()),
+            "hashes - can't be combined with incremental, watch, with-rank, dry-run, keep-going, \
+             or train-brotli-dictionary, as stdin can only be read once"
+        );
+        anyhow::ensure!(
+            args.json || !args.static_site,
+            "static-site needs the json format, which the page checks passwords against"
+        );
+        anyhow::ensure!(
+            !args.hash_field.is_empty()
+                && !args.count_field.is_empty()
+                && args.hash_field != args.count_field,
+            "hash-field and count-field must be different, non-empty names"
+        );
+        anyhow::ensure!(args.threads != Some(0), "threads must be at least 1");
+        anyhow::ensure!(args.io_threads != Some(0), "io-threads must be at least 1");
+        anyhow::ensure!(
+            args.upload_concurrency > 0,
+            "upload-concurrency must be at least 1"
+        );
+        anyhow::ensure!(args.depth <= 4, "depth must be between 0 and 4");
+        anyhow::ensure!(args.gzip_level <= 9, "gzip-level must be between 0 and 9");
+        anyhow::ensure!(
+            args.brotli_quality <= 11,
+            "brotli-quality must be between 0 and 11"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+
+    #[test]
+    fn flags_override_the_config() {
+        let config: Config = toml::from_str(
+            "[generate]\ndepth = 2\nmin-count = 5\ngzip = false\nsort-by = \"count\"\n",
+        )
+        .unwrap();
+        let matches = Args::command()
+            .try_get_matches_from(["hibp-json", "--depth", "3", "--gzip", "true"])
+            .unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        config.generate.apply(&mut args, &matches).unwrap();
+
+        // the flags win over the config, which wins over the defaults
+        assert_eq!((args.depth, args.gzip), (3, true));
+        assert_eq!((args.min_count, args.sort_by), (5, SortBy::Count));
+        assert!(toml::from_str::<Config>("[generate]\ndeph = 2\n").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use tracing::info;
+
+use crate::{find_generated_files, generate::write_atomic, manifest::ManifestWriter, Password};
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    /// Path to the generated files
+    #[arg(long, env = "HIBP_JSON_OUT", default_value = "dist")]
+    dist: PathBuf,
+
+    /// Format of the existing files
+    #[arg(long, value_enum)]
+    from: Format,
+
+    /// Format to convert them to, written next to them
+    #[arg(long, value_enum)]
+    to: Format,
+
+    /// Delete the existing files once they're converted
+    #[arg(long)]
+    delete: bool,
+}
+
+/// A format the entries of a prefix can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// A JSON array of `{"hash":"...","count":N}` objects, as generated
+    Json,
+    /// One `{"hash":"...","count":N}` object per line
+    Ndjson,
+    /// `hash,count` lines after a header line
+    Csv,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+            Self::Csv => "csv",
+        }
+    }
+
+    fn read(self, content: &[u8]) -> Result<Vec<Password>> {
+        match self {
+            Self::Json => Ok(serde_json::from_slice(content)?),
+            Self::Ndjson => content
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+                .map(|line| Ok(serde_json::from_str(&line?)?))
+                .collect(),
+            Self::Csv => content
+                .lines()
+                .skip(1)
+                .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+                .map(|line| {
+                    let line = line?;
+                    let (hash, count) = line
+                        .split_once(',')
+                        .with_context(|| format!("expected hash,count but got {line:?}"))?;
+                    Ok(Password {
+                        hash: hash.try_into()?,
+                        count: count.trim().parse()?,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn write(self, out: &mut impl Write, passwords: &[Password]) -> std::io::Result<()> {
+        match self {
+            Self::Json => serde_json::to_writer(&mut *out, passwords)?,
+            Self::Ndjson => {
+                for password in passwords {
+                    serde_json::to_writer(&mut *out, password)?;
+                    out.write_all(b"\n")?;
+                }
+            }
+            Self::Csv => {
+                out.write_all(b"hash,count\n")?;
+                for password in passwords {
+                    writeln!(out, "{},{}", password.hash, password.count)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts the file at `path` from `args.from` to `args.to`.
+fn convert_file(args: &ConvertArgs, path: &Path, manifest: Option<&ManifestWriter>) -> Result<()> {
+    let content =
+        std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+    let passwords = args
+        .from
+        .read(&content)
+        .with_context(|| format!("invalid {:?} in {}", args.from, path.display()))?;
+
+    let out = path.with_extension(args.to.extension());
+    write_atomic(&out, manifest, |file| args.to.write(file, &passwords))
+        .with_context(|| format!("unable to write {}", out.display()))?;
+
+    if args.delete {
+        std::fs::remove_file(path)
+            .with_context(|| format!("unable to delete {}", path.display()))?;
+        if let Some(manifest) = manifest {
+            manifest.remove(path);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn run(args: &ConvertArgs) -> Result<()> {
+    anyhow::ensure!(
+        args.from != args.to,
+        "--from and --to must be different formats"
+    );
+
+    let paths = find_generated_files(&args.dist, args.from.extension())?;
+    info!(
+        "Converting {} .{} files in {} to .{}",
+        paths.len(),
+        args.from.extension(),
+        args.dist.display(),
+        args.to.extension()
+    );
+
+    let manifest = ManifestWriter::open_existing(&args.dist)?;
+
+    paths
+        .par_iter()
+        .progress_count(paths.len() as u64)
+        .try_for_each(|path| convert_file(args, path, manifest.as_ref()))?;
+
+    if let Some(manifest) = manifest {
+        manifest.save()?;
+    }
+
+    info!("Converted {} files", paths.len());
+    Ok(())
+}
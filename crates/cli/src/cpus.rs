@@ -0,0 +1,78 @@
+//! The amount of cpus a run can actually use, so a container limited to 2 cpus on a 64 core
+//! host doesn't spawn 64 worker threads and thrash.
+
+use std::num::NonZeroUsize;
+
+#[cfg(target_os = "linux")]
+use std::path::Path;
+
+/// The cpus available to this process, the fewest of its cpu affinity and the cgroup cpu quota
+/// rounded up, at least 1.
+pub fn available() -> usize {
+    let cpus = std::thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    match quota() {
+        Some(quota) => cpus.min(quota),
+        None => cpus,
+    }
+}
+
+/// The cpu quota of the cgroup of this process and its ancestors in whole cpus, if any of them
+/// has one.
+#[cfg(target_os = "linux")]
+fn quota() -> Option<usize> {
+    let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+    cgroups.lines().find_map(|line| {
+        let mut fields = line.splitn(3, ':');
+        let (id, controllers, path) = (fields.next()?, fields.next()?, fields.next()?);
+        if id == "0" && controllers.is_empty() {
+            v2_quota(path)
+        } else if controllers.split(',').any(|c| c == "cpu") {
+            v1_quota(path)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn quota() -> Option<usize> {
+    None
+}
+
+/// Reads `cpu.max`, `<quota> <period>` or `max <period>`, of the unified hierarchy.
+#[cfg(target_os = "linux")]
+fn v2_quota(path: &str) -> Option<usize> {
+    let root = Path::new("/sys/fs/cgroup");
+    let path = root.join(path.trim_start_matches('/'));
+    path.ancestors()
+        .take_while(|dir| dir.starts_with(root))
+        .filter_map(|dir| {
+            let max = std::fs::read_to_string(dir.join("cpu.max")).ok()?;
+            let (quota, period) = max.trim().split_once(' ')?;
+            cpus(quota.parse().ok()?, period.parse().ok()?)
+        })
+        .min()
+}
+
+/// Reads `cpu.cfs_quota_us` and `cpu.cfs_period_us` of the cpu controller, -1 meaning none.
+#[cfg(target_os = "linux")]
+fn v1_quota(path: &str) -> Option<usize> {
+    let path = path.trim_start_matches('/');
+    ["/sys/fs/cgroup/cpu", "/sys/fs/cgroup/cpu,cpuacct"]
+        .into_iter()
+        .flat_map(|mount| [Path::new(mount).join(path), mount.into()])
+        .find_map(|dir| {
+            let read = |name| std::fs::read_to_string(dir.join(name)).ok();
+            let quota: i64 = read("cpu.cfs_quota_us")?.trim().parse().ok()?;
+            let period = read("cpu.cfs_period_us")?.trim().parse().ok()?;
+            cpus(u64::try_from(quota).ok()?, period)
+        })
+}
+
+/// A quota of `quota` microseconds per `period` in whole cpus, rounded up.
+#[cfg(target_os = "linux")]
+fn cpus(quota: u64, period: u64) -> Option<usize> {
+    (period > 0)
+        .then(|| quota.div_ceil(period).max(1))
+        .and_then(|cpus| usize::try_from(cpus).ok())
+}
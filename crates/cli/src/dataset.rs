@@ -0,0 +1,106 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Describes a generated dataset, written to `dataset.json` at the root of the output directory.
+#[derive(Debug, Serialize)]
+pub struct Dataset {
+    /// Unix timestamp in seconds of when generation finished
+    pub generated_at: u64,
+    pub version: &'static str,
+    pub hash_type: &'static str,
+    /// Names of the fields of every entry, with --hash-field and --count-field
+    pub fields: FieldNames,
+    pub formats: Vec<&'static str>,
+    /// Count thresholds of the trees in tier-N directories next to the full one
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tiers: Vec<u64>,
+    pub entries: u64,
+    pub bytes: FormatBytes,
+    /// How the generated files are encrypted at rest, if they are
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<&'static str>,
+}
+
+/// The names of the hash and count fields of the entries of a dataset.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FieldNames {
+    pub hash: String,
+    pub count: String,
+}
+
+impl Default for FieldNames {
+    fn default() -> Self {
+        Self {
+            hash: "hash".into(),
+            count: "count".into(),
+        }
+    }
+}
+
+impl FieldNames {
+    /// The names recorded in the `dataset.json` of `dist`, or the default ones if there's none
+    /// or it predates recording them.
+    pub fn read(dist: &Path) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Recorded {
+            #[serde(default)]
+            fields: FieldNames,
+        }
+
+        let path = dist.join(Dataset::FILE_NAME);
+        match std::fs::read(&path) {
+            Ok(content) => Ok(serde_json::from_slice::<Recorded>(&content)
+                .with_context(|| format!("invalid {}", path.display()))?
+                .fields),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("unable to read {}", path.display())),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FormatBytes {
+    pub json: u64,
+    pub gzip: u64,
+    pub brotli: u64,
+    pub dcb: u64,
+}
+
+impl Dataset {
+    pub const FILE_NAME: &'static str = "dataset.json";
+
+    pub fn new(
+        formats: Vec<&'static str>,
+        tiers: Vec<u64>,
+        entries: u64,
+        bytes: FormatBytes,
+    ) -> Self {
+        let generated_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        Self {
+            generated_at,
+            version: env!("CARGO_PKG_VERSION"),
+            hash_type: "sha1",
+            fields: FieldNames::default(),
+            formats,
+            tiers,
+            entries,
+            bytes,
+            encryption: None,
+        }
+    }
+
+    pub fn write(&self, dist: &Path) -> Result<()> {
+        let content = serde_json::to_vec_pretty(self)?;
+        std::fs::write(dist.join(Self::FILE_NAME), content)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    convert::Infallible,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    compress::{self, BrotliParams},
+    error::GenerateError,
+    generate::{parse_lines, ParseError, ParseOptions},
+    input,
+};
+
+/// Upper bound of a trained dictionary, past this the rare counts barely save anything.
+const MAX_SIZE: usize = 64 * 1024;
+
+/// Magic of the `dcb` content encoding, brotli compressed with a shared dictionary (RFC 9842).
+const DCB_MAGIC: [u8; 4] = [0xff, 0x44, 0x43, 0x42];
+
+/// A shared brotli dictionary, along with the `dcb` header identifying it.
+pub struct Dictionary {
+    content: Vec<u8>,
+    header: Vec<u8>,
+}
+
+impl Dictionary {
+    /// Name of the copy of the dictionary in the output directory, served to clients.
+    pub const FILE_NAME: &'static str = "dictionary.bin";
+
+    pub fn new(content: Vec<u8>) -> Self {
+        let mut header = DCB_MAGIC.to_vec();
+        header.extend_from_slice(&Sha256::digest(&content));
+        Self { content, header }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read(path)
+            .with_context(|| format!("unable to read dictionary {}", path.display()))?;
+        Ok(Self::new(content))
+    }
+
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Compresses `data` into `out` as `dcb`, the dictionary hash followed by the brotli stream.
+    pub fn compress(
+        &self,
+        out: &mut impl Write,
+        data: &[u8],
+        params: &BrotliParams,
+    ) -> std::io::Result<()> {
+        out.write_all(&self.header)?;
+        compress::brotli_with_dictionary(out, data, params, &self.content)
+    }
+
+    /// Decompresses `dcb` data written by [`Dictionary::compress`], failing if it was compressed
+    /// with another dictionary.
+    pub fn decompress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let stream = data.strip_prefix(self.header.as_slice()).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "dcb header doesn't match the dictionary",
+            )
+        })?;
+
+        compress::unbrotli_with_dictionary(stream, &self.content)
+    }
+}
+
+/// Trains a dictionary from `samples` hash files spread evenly over `paths`.
+///
+/// Hashes are random, so all outputs share is the JSON around them, which only differs in the
+/// count. The dictionary holds the separator around the most common counts, the most common
+/// last as brotli references the end of the dictionary most cheaply.
+pub fn train(paths: &[PathBuf], samples: usize, options: ParseOptions) -> Result<Vec<u8>> {
+    let step = (paths.len() / samples.max(1)).max(1);
+    let mut seen: HashMap<u64, u64> = HashMap::new();
+    for path in paths.iter().step_by(step).take(samples) {
+        let content =
+            input::read_all(path).with_context(|| format!("unable to read {}", path.display()))?;
+        parse_lines(&content, options, |_, count| {
+            *seen.entry(count).or_default() += 1;
+            Ok::<_, Infallible>(())
+        })
+        .map_err(|err| match err {
+            ParseError::Line(line, kind) => GenerateError::Parse {
+                path: path.clone(),
+                line,
+                kind,
+            },
+            ParseError::Sink(never) => match never {},
+        })?;
+    }
+
+    let mut counts: Vec<_> = seen.into_iter().collect();
+    // ties broken by count so the same samples always train the same dictionary
+    counts.sort_unstable_by_key(|&(count, seen)| (Reverse(seen), count));
+
+    let mut segments = Vec::new();
+    let mut size = 0;
+    for (count, _) in counts {
+        let segment = format!(r#"","count":{count}}},{{"hash":""#);
+        if size + segment.len() > MAX_SIZE {
+            break;
+        }
+        size += segment.len();
+        segments.push(segment);
+    }
+
+    let mut dictionary = br#"[{"hash":""#.to_vec();
+    for segment in segments.iter().rev() {
+        dictionary.extend_from_slice(segment.as_bytes());
+    }
+
+    Ok(dictionary)
+}
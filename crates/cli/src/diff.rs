@@ -0,0 +1,399 @@
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    convert::Infallible,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use hibp_json_util::format_prefix;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+use tracing::error;
+
+use crate::{
+    error::GenerateError,
+    find_all_hash_files,
+    generate::{parse_lines, CountOverflow, HashType, ParseError, ParseOptions},
+    input, prefix_of,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// Hash files of the old release
+    old: PathBuf,
+
+    /// Hash files of the new release
+    new: PathBuf,
+
+    /// Write the changes of every changed prefix to PREFIX.diff in this directory
+    #[arg(short, long)]
+    out: Option<PathBuf>,
+
+    /// Also report the changes of every changed prefix, not just the summary
+    #[arg(long)]
+    per_prefix: bool,
+
+    /// Print the report as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+
+    /// Only print the changed prefixes, one per line, e.g. for --prefix-list
+    #[arg(long, conflicts_with_all = ["per_prefix", "json"])]
+    list: bool,
+
+    /// Fail if any hash vanished or its count decreased, as HIBP counts only ever grow and these
+    /// point at a corrupted or truncated download
+    #[arg(long)]
+    check_monotonic: bool,
+}
+
+/// Regressions of a prefix logged as examples with --check-monotonic, the rest are only counted.
+const MAX_EXAMPLES: usize = 5;
+
+/// Changes within a single prefix.
+#[derive(Debug, Default, Serialize)]
+struct PrefixDiff {
+    prefix: String,
+    added: u64,
+    removed: u64,
+    /// Hashes in both releases whose count changed
+    changed: u64,
+    /// Changed hashes whose count went down
+    decreased: u64,
+    /// Sum of the counts in the new release minus the sum in the old one
+    count_delta: i128,
+    /// The first removed or decreased hashes, with --check-monotonic
+    #[serde(skip)]
+    regressions: Vec<String>,
+}
+
+impl PrefixDiff {
+    fn is_empty(&self) -> bool {
+        self.added == 0 && self.removed == 0 && self.changed == 0
+    }
+
+    /// Records the regression of `suffix` as an example, while there's room for one.
+    fn regression(&mut self, suffix: &str, message: std::fmt::Arguments) {
+        if self.regressions.len() < MAX_EXAMPLES {
+            self.regressions
+                .push(format!("{}{suffix} {message}", self.prefix));
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Summary {
+    prefixes: u64,
+    changed_prefixes: u64,
+    added: u64,
+    removed: u64,
+    changed: u64,
+    decreased: u64,
+    count_delta: i128,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    summary: Summary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prefixes: Option<&'a [PrefixDiff]>,
+}
+
+/// The lines of a hash file, sorted by suffix.
+fn read_lines<'a>(path: Option<&Path>, content: &'a mut Vec<u8>) -> Result<Vec<(&'a str, u64)>> {
+    let Some(path) = path else {
+        return Ok(Vec::new());
+    };
+
+    *content = input::read_all(path).map_err(|source| GenerateError::Read {
+        path: path.into(),
+        source,
+    })?;
+    let content: &'a [u8] = content;
+
+    let options = ParseOptions {
+        min_count: 0,
+        strict: false,
+        hash_type: HashType::Sha1,
+        hash_order: false,
+        lenient: false,
+        merge_duplicates: false,
+        count_overflow: CountOverflow::Error,
+    };
+    let mut lines = Vec::new();
+    parse_lines(content, options, |suffix, count| {
+        lines.push((suffix, count));
+        Ok::<_, Infallible>(())
+    })
+    .map_err(|err| match err {
+        ParseError::Line(line, kind) => GenerateError::Parse {
+            path: path.into(),
+            line,
+            kind,
+        },
+        ParseError::Sink(never) => match never {},
+    })?;
+
+    // hash files are sorted already, unless they were edited by hand
+    lines.sort_unstable();
+    Ok(lines)
+}
+
+/// Compares the hash files of `prefix`, writing its changes to `out` if there are any.
+fn diff_prefix(
+    prefix: u32,
+    old: Option<&Path>,
+    new: Option<&Path>,
+    out: Option<&Path>,
+    check_monotonic: bool,
+) -> Result<PrefixDiff> {
+    let (mut old_content, mut new_content) = (Vec::new(), Vec::new());
+    let old = read_lines(old, &mut old_content)?;
+    let new = read_lines(new, &mut new_content)?;
+
+    let mut diff = PrefixDiff {
+        prefix: format_prefix(prefix),
+        ..PrefixDiff::default()
+    };
+    let mut changes = Vec::new();
+    let (mut old, mut new) = (old.into_iter().peekable(), new.into_iter().peekable());
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (Some((a, _)), Some((b, _))) => a.cmp(b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match order {
+            Ordering::Less => {
+                let Some((suffix, count)) = old.next() else {
+                    break;
+                };
+                diff.removed += 1;
+                diff.count_delta -= i128::from(count);
+                if check_monotonic {
+                    diff.regression(suffix, format_args!("vanished, its count was {count}"));
+                }
+                writeln!(changes, "-{suffix}:{count}")?;
+            }
+            Ordering::Greater => {
+                let Some((suffix, count)) = new.next() else {
+                    break;
+                };
+                diff.added += 1;
+                diff.count_delta += i128::from(count);
+                writeln!(changes, "+{suffix}:{count}")?;
+            }
+            Ordering::Equal => {
+                let (Some((suffix, before)), Some((_, after))) = (old.next(), new.next()) else {
+                    break;
+                };
+                if before != after {
+                    diff.changed += 1;
+                    diff.count_delta += i128::from(after) - i128::from(before);
+                    if after < before {
+                        diff.decreased += 1;
+                        if check_monotonic {
+                            diff.regression(
+                                suffix,
+                                format_args!("decreased from {before} to {after}"),
+                            );
+                        }
+                    }
+                    writeln!(changes, "~{suffix}:{before}:{after}")?;
+                }
+            }
+        }
+    }
+
+    if let (Some(out), false) = (out, diff.is_empty()) {
+        let path = out.join(format!("{}.diff", diff.prefix));
+        std::fs::write(&path, changes)
+            .with_context(|| format!("unable to write {}", path.display()))?;
+    }
+
+    Ok(diff)
+}
+
+/// Every prefix of either release, with its hash file in each of them.
+fn pair_hash_files(old: &Path, new: &Path) -> Result<Vec<(u32, [Option<PathBuf>; 2])>> {
+    let mut pairs: BTreeMap<u32, [Option<PathBuf>; 2]> = BTreeMap::new();
+    for (i, hashes) in [old, new].into_iter().enumerate() {
+        for path in find_all_hash_files(hashes)? {
+            if let Some(prefix) = prefix_of(&path) {
+                pairs.entry(prefix).or_default()[i] = Some(path);
+            }
+        }
+    }
+
+    Ok(pairs.into_iter().collect())
+}
+
+pub fn run(args: &DiffArgs) -> Result<()> {
+    if let Some(out) = &args.out {
+        std::fs::create_dir_all(out)?;
+    }
+
+    let mut diffs: Vec<PrefixDiff> = pair_hash_files(&args.old, &args.new)?
+        .into_par_iter()
+        .map(|(prefix, [old, new])| {
+            diff_prefix(
+                prefix,
+                old.as_deref(),
+                new.as_deref(),
+                args.out.as_deref(),
+                args.check_monotonic,
+            )
+        })
+        .collect::<Result<_>>()?;
+
+    let mut summary = Summary::default();
+    for diff in &diffs {
+        summary.prefixes += 1;
+        summary.changed_prefixes += u64::from(!diff.is_empty());
+        summary.added += diff.added;
+        summary.removed += diff.removed;
+        summary.changed += diff.changed;
+        summary.decreased += diff.decreased;
+        summary.count_delta += diff.count_delta;
+    }
+    diffs.retain(|diff| !diff.is_empty());
+
+    let (removed, decreased) = (summary.removed, summary.decreased);
+    print_report(args, summary, &diffs)?;
+
+    if args.check_monotonic && removed + decreased > 0 {
+        for regression in diffs.iter().flat_map(|diff| &diff.regressions) {
+            error!("{regression}");
+        }
+        anyhow::bail!(
+            "{removed} hashes vanished and {decreased} decreased in count, {} may be corrupted or \
+             truncated",
+            args.new.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the `summary` and the `diffs` of the changed prefixes as `args` ask for.
+fn print_report(args: &DiffArgs, summary: Summary, diffs: &[PrefixDiff]) -> Result<()> {
+    if args.list {
+        for diff in diffs {
+            println!("{}", diff.prefix);
+        }
+        return Ok(());
+    }
+
+    if args.json {
+        let report = Report {
+            summary,
+            prefixes: args.per_prefix.then_some(diffs),
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    if args.per_prefix {
+        println!(
+            "{:<6} {:>10} {:>10} {:>10} {:>10} {:>14}",
+            "prefix", "added", "removed", "changed", "decreased", "count delta"
+        );
+        for diff in diffs {
+            println!(
+                "{:<6} {:>10} {:>10} {:>10} {:>10} {:>+14}",
+                diff.prefix,
+                diff.added,
+                diff.removed,
+                diff.changed,
+                diff.decreased,
+                diff.count_delta
+            );
+        }
+        println!();
+    }
+
+    println!(
+        "{} of {} prefixes changed",
+        summary.changed_prefixes, summary.prefixes
+    );
+    println!("added        {}", summary.added);
+    println!("removed      {}", summary.removed);
+    println!("changed      {}", summary.changed);
+    println!("decreased    {}", summary.decreased);
+    println!("count delta  {:+}", summary.count_delta);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "0005AD76BD555C1D6D771DE417A4B87E4B4";
+    const B: &str = "00A8DAE4228F821FB418F59826079BF368D";
+    const C: &str = "00D4F6E8FA6EECAD2A3AA415EEC418D38EC";
+
+    /// Writes `content` to a hash file unique to the test `name`.
+    fn hash_file(name: &str, content: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("hibp-json-diff-{name}-{}.txt", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn changes_of_a_prefix() {
+        let old = hash_file("changes-old", &format!("{B}:2\r\n{A}:5\r\n{C}:7\r\n"));
+        let new = hash_file("changes-new", &format!("{A}:9\r\n{C}:7\r\n"));
+        let out = std::env::temp_dir().join(format!("hibp-json-diff-out-{}", std::process::id()));
+        std::fs::create_dir_all(&out).unwrap();
+
+        let diff = diff_prefix(0xABCDE, Some(&old), Some(&new), Some(&out), false).unwrap();
+        assert_eq!(diff.prefix, "ABCDE");
+        assert_eq!((diff.added, diff.removed, diff.changed), (0, 1, 1));
+        assert_eq!(diff.count_delta, 2);
+        assert_eq!(
+            std::fs::read_to_string(out.join("ABCDE.diff")).unwrap(),
+            format!("~{A}:5:9\n-{B}:2\n")
+        );
+
+        // a new prefix only adds, and an unchanged one writes no .diff
+        let diff = diff_prefix(1, None, Some(&new), Some(&out), false).unwrap();
+        assert_eq!((diff.added, diff.count_delta), (2, 16));
+        let diff = diff_prefix(2, Some(&new), Some(&new), Some(&out), false).unwrap();
+        assert!(diff.is_empty());
+        assert!(!out.join("00002.diff").exists());
+
+        std::fs::remove_dir_all(out).unwrap();
+        std::fs::remove_file(old).unwrap();
+        std::fs::remove_file(new).unwrap();
+    }
+
+    #[test]
+    fn monotonic_regressions() {
+        let old = hash_file("monotonic-old", &format!("{A}:5\r\n{B}:{}\r\n", u64::MAX));
+        let new = hash_file("monotonic-new", &format!("{A}:3\r\n{C}:1\r\n"));
+
+        let diff = diff_prefix(0, Some(&old), Some(&new), None, true).unwrap();
+        assert_eq!((diff.removed, diff.decreased), (1, 1));
+        // beyond what an i64 holds
+        assert_eq!(diff.count_delta, 1 - 2 - i128::from(u64::MAX));
+        assert_eq!(
+            diff.regressions,
+            [
+                format!("00000{A} decreased from 5 to 3"),
+                format!("00000{B} vanished, its count was {}", u64::MAX),
+            ]
+        );
+
+        let diff = diff_prefix(0, Some(&old), Some(&new), None, false).unwrap();
+        assert!(diff.regressions.is_empty());
+
+        std::fs::remove_file(old).unwrap();
+        std::fs::remove_file(new).unwrap();
+    }
+}
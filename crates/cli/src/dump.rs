@@ -0,0 +1,152 @@
+use std::{
+    fs::File,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use hibp_json_util::{format_prefix, parse_prefix, PREFIX_COUNT, PREFIX_LEN};
+#[cfg(not(target_os = "wasi"))]
+use memmap2::Mmap;
+
+use crate::{generate::lines, prefix_of};
+
+#[cfg(not(target_os = "wasi"))]
+type Map = Mmap;
+/// wasi can't map files, the dump is read into memory instead
+#[cfg(target_os = "wasi")]
+type Map = Vec<u8>;
+
+/// A single file with the `HASH:COUNT` lines of every prefix ordered by hash, like
+/// `pwnedpasswords.txt`, split into the prefixes the generator works on.
+///
+/// Every prefix gets a virtual path within the file, e.g. `pwnedpasswords.txt/ABCDE`, so that it's
+/// processed like a hash file of its own.
+pub struct Dump {
+    path: PathBuf,
+    map: Map,
+    /// Byte range of the lines of every prefix, empty for prefixes without any
+    ranges: Vec<Range<usize>>,
+}
+
+impl Dump {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+        // SAFETY: like hash files, the dump is not expected to be modified while generating
+        #[cfg(not(target_os = "wasi"))]
+        let map = unsafe { Mmap::map(&file)? };
+        #[cfg(target_os = "wasi")]
+        let map = {
+            let mut map = Vec::new();
+            io::Read::read_to_end(&mut &file, &mut map)?;
+            map
+        };
+        let ranges = index(&map).with_context(|| format!("invalid dump {}", path.display()))?;
+
+        Ok(Self {
+            path: path.into(),
+            map,
+            ranges,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The virtual path of every prefix with at least one hash.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        (0..PREFIX_COUNT)
+            .filter(|prefix| !self.ranges[*prefix as usize].is_empty())
+            .map(|prefix| self.path.join(format_prefix(prefix)))
+            .collect()
+    }
+
+    /// Size of the lines of the prefix at the virtual `path` within the dump.
+    pub fn size(&self, path: &Path) -> u64 {
+        prefix_of(path).map_or(0, |prefix| self.ranges[prefix as usize].len() as u64)
+    }
+
+    /// Reads the lines of the prefix at the virtual `path` into `buf`, as the `SUFFIX:COUNT` lines
+    /// of its hash file.
+    pub fn read(&self, path: &Path, buf: &mut Vec<u8>) -> io::Result<()> {
+        let prefix = prefix_of(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not a prefix of the dump"))?;
+
+        let range = self.ranges[prefix as usize].clone();
+        suffix_lines(&self.map[range], buf);
+        Ok(())
+    }
+}
+
+/// Writes the `HASH:COUNT` lines of a prefix in `content` into `buf` as `SUFFIX:COUNT` lines,
+/// skipping the blank lines [`index`] kept within the range of the prefix.
+fn suffix_lines(content: &[u8], buf: &mut Vec<u8>) {
+    buf.clear();
+    for line in lines(content).filter(|line| !line.trim_ascii().is_empty()) {
+        buf.extend_from_slice(&line[PREFIX_LEN..]);
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Finds the byte range of the lines of every prefix in `content`, which must be ordered by hash.
+fn index(content: &[u8]) -> Result<Vec<Range<usize>>> {
+    let mut ranges = vec![0..0; PREFIX_COUNT as usize];
+    let mut previous: Option<u32> = None;
+    let mut offset = 0;
+
+    for (i, line) in content.split_inclusive(|b| *b == b'\n').enumerate() {
+        let start = offset;
+        offset += line.len();
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+
+        let prefix = line
+            .get(..PREFIX_LEN)
+            .and_then(|p| std::str::from_utf8(p).ok())
+            .and_then(parse_prefix)
+            .with_context(|| format!("line {}: expected a HASH:COUNT line", i + 1))?;
+        match previous {
+            Some(previous) if previous == prefix => ranges[prefix as usize].end = offset,
+            Some(previous) if previous > prefix => anyhow::bail!(
+                "line {}: {} comes after {}, the dump must be ordered by hash",
+                i + 1,
+                format_prefix(prefix),
+                format_prefix(previous)
+            ),
+            _ => ranges[prefix as usize] = start..offset,
+        }
+        previous = Some(prefix);
+    }
+
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &[u8] = b"00000AAAA:1\r\n00000BBBB:2\r\n  \r\n00000CCCC:3\r\n\r\n00002AAAA:4\n";
+
+    #[test]
+    fn index_ranges() {
+        let ranges = index(DUMP).unwrap();
+        assert_eq!(ranges[0], 0..43);
+        assert!(ranges[1].is_empty());
+        assert_eq!(&DUMP[ranges[2].clone()], b"00002AAAA:4\n");
+
+        assert!(index(b"00002AAAA:1\n00000AAAA:2\n").is_err());
+        assert!(index(b"0000").is_err());
+    }
+
+    #[test]
+    fn blank_lines_within_a_prefix() {
+        let ranges = index(DUMP).unwrap();
+        let mut buf = Vec::new();
+        suffix_lines(&DUMP[ranges[0].clone()], &mut buf);
+        assert_eq!(buf, b"AAAA:1\r\nBBBB:2\r\nCCCC:3\r\n");
+    }
+}
@@ -0,0 +1,113 @@
+//! Encrypts the generated files at rest, for internal breach corpora that can't be stored in
+//! plaintext. The server decrypts them again with the matching identity or keyfile.
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key,
+};
+use anyhow::{Context, Result};
+
+/// Size of an AES-256-GCM keyfile.
+const KEY_LEN: usize = 32;
+
+/// An `--encrypt` value, `age:<recipient>` or `aes-gcm:<keyfile>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptSpec {
+    Age(String),
+    AesGcm(PathBuf),
+}
+
+impl FromStr for EncryptSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("age", recipient)) => Ok(Self::Age(recipient.into())),
+            Some(("aes-gcm", keyfile)) => Ok(Self::AesGcm(keyfile.into())),
+            _ => Err(format!("{s:?} isn't age:<recipient> or aes-gcm:<keyfile>")),
+        }
+    }
+}
+
+/// The key every generated file is encrypted with.
+pub enum Encryption {
+    Age(age::x25519::Recipient),
+    /// Each file is a random 12 byte nonce followed by the ciphertext and its tag, boxed as its
+    /// expanded key schedule is large
+    AesGcm(Box<Aes256Gcm>),
+}
+
+impl Encryption {
+    /// Parses the recipient or reads the keyfile of `spec`.
+    pub fn new(spec: &EncryptSpec) -> Result<Self> {
+        match spec {
+            EncryptSpec::Age(recipient) => recipient
+                .parse()
+                .map(Self::Age)
+                .map_err(|err| anyhow::anyhow!("invalid age recipient {recipient}: {err}")),
+            EncryptSpec::AesGcm(keyfile) => {
+                let key = std::fs::read(keyfile)
+                    .with_context(|| format!("unable to read keyfile {}", keyfile.display()))?;
+                anyhow::ensure!(
+                    key.len() == KEY_LEN,
+                    "keyfile {} must hold exactly {KEY_LEN} bytes, e.g. from head -c {KEY_LEN} \
+                     /dev/urandom",
+                    keyfile.display()
+                );
+                let key = Key::<Aes256Gcm>::from_slice(&key);
+                Ok(Self::AesGcm(Box::new(Aes256Gcm::new(key))))
+            }
+        }
+    }
+
+    /// How the files are encrypted, as recorded in `dataset.json`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Age(_) => "age",
+            Self::AesGcm(_) => "aes-gcm",
+        }
+    }
+
+    /// Encrypts `data` into `out`. AES-GCM authenticates the `relative` path of the file along
+    /// with it, so a file swapped for another one doesn't decrypt.
+    pub fn encrypt(&self, out: &mut impl Write, data: &[u8], relative: &Path) -> io::Result<()> {
+        match self {
+            Self::Age(recipient) => {
+                let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient.clone())])
+                    .expect("there is a recipient");
+                let mut writer = encryptor.wrap_output(out).map_err(io::Error::other)?;
+                writer.write_all(data)?;
+                writer.finish().map(drop)
+            }
+            Self::AesGcm(cipher) => {
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                let aad = associated_data(relative);
+                let payload = Payload {
+                    msg: data,
+                    aad: aad.as_bytes(),
+                };
+                let ciphertext = cipher
+                    .encrypt(&nonce, payload)
+                    .map_err(|_| io::Error::other("AES-GCM encryption failed"))?;
+                out.write_all(&nonce)?;
+                out.write_all(&ciphertext)
+            }
+        }
+    }
+}
+
+/// The associated data of the file at `relative` within the output directory, its path with `/`
+/// separators, e.g. `A/B/C/D/E.json.gz`.
+pub fn associated_data(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,91 @@
+use std::{io, path::PathBuf};
+
+/// Failure while generating the outputs of a single hash file.
+#[derive(Debug, thiserror::Error)]
+pub enum GenerateError {
+    #[error("{} is not named after a 5 character hex prefix", .0.display())]
+    InvalidName(PathBuf),
+
+    #[error("unable to read {}", .path.display())]
+    Read { path: PathBuf, source: io::Error },
+
+    #[error("{}:{line}: {kind}", .path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        kind: LineError,
+    },
+
+    #[error("unable to serialize {}", .path.display())]
+    Serialize {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("unable to write {}", .path.display())]
+    Write { path: PathBuf, source: io::Error },
+
+    #[error("{} already exists, pass --on-existing overwrite or skip to allow this", .0.display())]
+    Exists(PathBuf),
+
+    #[error("{} doesn't decode back to the JSON it was written from", .0.display())]
+    SelfCheck(PathBuf),
+
+    #[error("unable to remove the hash file {}", .path.display())]
+    RemoveSource { path: PathBuf, source: io::Error },
+
+    #[error("unable to update the checkpoint")]
+    Checkpoint(#[source] io::Error),
+}
+
+impl GenerateError {
+    /// If the error may go away when trying again, e.g. an I/O error on a flaky network mount.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Read { .. }
+                | Self::Write { .. }
+                | Self::SelfCheck(_)
+                | Self::RemoveSource { .. }
+                | Self::Checkpoint(_)
+        )
+    }
+}
+
+/// What is wrong with a line of a hash file.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LineError {
+    #[error("line is not valid utf-8")]
+    Utf8,
+
+    #[error("missing `:` between the hash suffix and the count")]
+    MissingSeparator,
+
+    #[error("invalid count `{0}`")]
+    InvalidCount(String),
+
+    #[error("count `{0}` doesn't fit in 64 bits, pass --count-overflow saturate to clamp it")]
+    CountOverflow(String),
+
+    #[error("suffix `{0}` is longer than the 35 characters of a SHA-1 suffix")]
+    SuffixTooLong(String),
+
+    #[error("suffix `{0}` is not {1} uppercase hex characters, see --hash-type")]
+    InvalidSuffix(String, usize),
+
+    #[error("suffix `{0}` is not in ascending order")]
+    OutOfOrder(String),
+
+    #[error("suffix `{0}` appears more than once")]
+    Duplicate(String),
+}
+
+impl LineError {
+    /// If the line is only rejected by `--strict`.
+    pub fn is_strict(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidSuffix(..) | Self::OutOfOrder(_) | Self::Duplicate(_)
+        )
+    }
+}
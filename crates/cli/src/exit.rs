@@ -0,0 +1,83 @@
+//! Exit codes for every class of failure, so scripts can branch on them instead of parsing
+//! stderr.
+
+use std::fmt;
+
+use crate::error::GenerateError;
+
+/// The exit code of a failed run. Usage errors caught by clap exit with 2 as well.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    /// Any failure without a more specific code
+    Failure = 1,
+    /// Invalid flags, environment variables, or config file
+    InvalidArguments = 2,
+    /// A `--strict` check failed, a hash file is missing or has an invalid or out of order line
+    Strict = 3,
+    /// Some prefixes failed in `--keep-going` mode, the others were generated
+    Partial = 4,
+    /// The inputs or outputs couldn't be set up before generating, e.g. the output directory
+    /// can't be created or the hash files can't be listed
+    Setup = 5,
+}
+
+/// An error that exits with a specific code, displayed as the error it wraps.
+#[derive(Debug)]
+struct Classified {
+    exit: Exit,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for Classified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for Classified {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Makes `error` exit with `exit`.
+pub fn classify(exit: Exit, error: anyhow::Error) -> anyhow::Error {
+    Classified { exit, error }.into()
+}
+
+pub trait ResultExt<T> {
+    /// Makes the error exit with `exit`.
+    fn exit(self, exit: Exit) -> anyhow::Result<T>;
+}
+
+impl<T, E: Into<anyhow::Error>> ResultExt<T> for Result<T, E> {
+    fn exit(self, exit: Exit) -> anyhow::Result<T> {
+        self.map_err(|error| classify(exit, error.into()))
+    }
+}
+
+/// Like [`anyhow::ensure`], but the error exits with the given [`Exit`].
+macro_rules! ensure {
+    ($exit:expr, $condition:expr, $($message:tt)+) => {
+        if !$condition {
+            return Err($crate::exit::classify($exit, anyhow::anyhow!($($message)+)));
+        }
+    };
+}
+pub(crate) use ensure;
+
+/// The exit code `error` should exit with.
+pub fn exit_code(error: &anyhow::Error) -> u8 {
+    for cause in error.chain() {
+        if let Some(classified) = cause.downcast_ref::<Classified>() {
+            return classified.exit as u8;
+        }
+        if let Some(GenerateError::Parse { kind, .. }) = cause.downcast_ref() {
+            if kind.is_strict() {
+                return Exit::Strict as u8;
+            }
+        }
+    }
+
+    Exit::Failure as u8
+}
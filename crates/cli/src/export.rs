@@ -0,0 +1,366 @@
+use std::{
+    convert::Infallible,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use clap::ValueEnum;
+use indicatif::{HumanBytes, ParallelProgressIterator, ProgressBar};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    compress::{self, GzipEngine},
+    error::GenerateError,
+    find_all_hash_files,
+    generate::{
+        parse_lines, prefix_name, AtomicFile, CountOverflow, HashType, ParseError, ParseOptions,
+    },
+    hash_buf::HashBuf,
+    input, Password,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Path to existing hashes
+    #[arg(long, env = "HIBP_JSON_HASHES", default_value = "hashes")]
+    hashes: PathBuf,
+
+    /// Format to export to
+    #[arg(long, value_enum)]
+    format: ExportFormat,
+
+    /// File to write the export to, or directory with --format workers-kv
+    #[arg(short, long)]
+    out: PathBuf,
+
+    /// Only export hashes seen at least this many times
+    #[arg(long, default_value_t = 0)]
+    min_count: u64,
+
+    /// Prefix of the keys, defaults to pw: with --format redis and none with --format workers-kv
+    #[arg(long)]
+    key_prefix: Option<String>,
+
+    /// Store every prefix as a Redis hash of its suffixes, e.g. HSET pw:ABCDE <suffix> <count>,
+    /// instead of a key per hash, which takes far less memory in Redis
+    #[arg(long)]
+    redis_buckets: bool,
+
+    /// Name of the table in the CREATE TABLE statement written next to a --format clickhouse
+    /// export
+    #[arg(long, default_value = "passwords")]
+    table: String,
+
+    /// Gzip the values of a --format workers-kv export, base64 encoded as the API needs for
+    /// binary values
+    #[arg(long)]
+    kv_gzip: bool,
+}
+
+impl ExportArgs {
+    fn key_prefix(&self) -> &str {
+        match (&self.key_prefix, self.format) {
+            (Some(prefix), _) => prefix,
+            (None, ExportFormat::Redis) => "pw:",
+            (None, _) => "",
+        }
+    }
+}
+
+/// A format for bulk-loading every hash into another system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    /// Redis protocol commands, for `redis-cli --pipe`
+    Redis,
+    /// ClickHouse TabSeparatedWithNamesAndTypes rows, along with a CREATE TABLE statement
+    Clickhouse,
+    /// Cloudflare Workers KV bulk upload payloads, a key per prefix with the body of its .json
+    WorkersKv,
+}
+
+/// The most pairs and bytes the Workers KV bulk API takes in a single request.
+const KV_MAX_PAIRS: usize = 10_000;
+const KV_MAX_BYTES: u64 = 100_000_000;
+
+/// A key-value pair of the Workers KV bulk API.
+#[derive(Serialize)]
+struct KvPair {
+    key: String,
+    value: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    base64: bool,
+}
+
+/// Writes the pairs of a Workers KV export into numbered payload files, e.g. `00000.json`,
+/// starting a new one before exceeding the limits of a single request.
+struct KvPayloads {
+    dir: PathBuf,
+    count: usize,
+    current: Option<(AtomicFile, usize, u64)>,
+    size: u64,
+}
+
+impl KvPayloads {
+    fn new(dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.into(),
+            count: 0,
+            current: None,
+            size: 0,
+        })
+    }
+
+    /// Appends a pair serialized as JSON.
+    fn push(&mut self, pair: &[u8]) -> std::io::Result<()> {
+        let len = pair.len() as u64 + 1;
+        if let Some((_, pairs, size)) = &self.current {
+            if *pairs == KV_MAX_PAIRS || *size + len + 1 > KV_MAX_BYTES {
+                self.finish_payload()?;
+            }
+        }
+
+        if let Some((file, ..)) = &mut self.current {
+            file.write_all(b",")?;
+        } else {
+            let path = self.dir.join(format!("{:05}.json", self.count));
+            let mut file = AtomicFile::create(path, false)?;
+            file.write_all(b"[")?;
+            self.count += 1;
+            self.current = Some((file, 0, 1));
+        }
+
+        if let Some((file, pairs, size)) = &mut self.current {
+            file.write_all(pair)?;
+            *pairs += 1;
+            *size += len;
+        }
+        Ok(())
+    }
+
+    fn finish_payload(&mut self) -> std::io::Result<()> {
+        if let Some((mut file, ..)) = self.current.take() {
+            file.write_all(b"]")?;
+            self.size += file.commit(None)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the last payload, returning the amount of payloads and their total size.
+    fn finish(mut self) -> std::io::Result<(usize, u64)> {
+        self.finish_payload()?;
+        Ok((self.count, self.size))
+    }
+}
+
+/// The columns of a ClickHouse export and their types.
+const CLICKHOUSE_COLUMNS: [(&str, &str); 2] = [("hash", "FixedString(40)"), ("count", "UInt64")];
+
+/// The statement creating the table a ClickHouse export is inserted into, ordered by hash for
+/// lookups.
+fn clickhouse_schema(table: &str) -> String {
+    let columns: Vec<_> = CLICKHOUSE_COLUMNS
+        .iter()
+        .map(|(name, kind)| format!("    `{name}` {kind}"))
+        .collect();
+    format!(
+        "CREATE TABLE IF NOT EXISTS `{table}`\n(\n{}\n)\nENGINE = MergeTree\nORDER BY hash;\n",
+        columns.join(",\n")
+    )
+}
+
+/// Where the exported prefixes are written to.
+enum Sink {
+    File(AtomicFile),
+    Kv(KvPayloads),
+}
+
+/// How many prefixes are exported in parallel before writing them out in order.
+const BATCH: usize = 1024;
+
+/// Appends a command in the Redis protocol to `out`.
+fn redis_command(out: &mut Vec<u8>, args: &[&[u8]]) {
+    out.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+    for arg in args {
+        out.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Appends the `entries` of `prefix` to `out` in `args.format`.
+fn write_prefix(
+    args: &ExportArgs,
+    prefix: &str,
+    entries: &[Password],
+    out: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    let key_prefix = args.key_prefix();
+    match args.format {
+        // HSET needs at least one field
+        ExportFormat::Redis if args.redis_buckets && !entries.is_empty() => {
+            let key = format!("{key_prefix}{prefix}");
+            let counts: Vec<_> = entries.iter().map(|e| e.count.to_string()).collect();
+            // a single command per prefix, setting all of its fields at once
+            let mut command = vec![b"HSET".as_slice(), key.as_bytes()];
+            for (entry, count) in entries.iter().zip(&counts) {
+                command.extend([entry.hash[prefix.len()..].as_bytes(), count.as_bytes()]);
+            }
+            redis_command(out, &command);
+        }
+        ExportFormat::Redis if args.redis_buckets => {}
+        ExportFormat::Redis => {
+            for entry in entries {
+                let key = format!("{key_prefix}{}", entry.hash);
+                let count = entry.count.to_string();
+                redis_command(out, &[b"SET", key.as_bytes(), count.as_bytes()]);
+            }
+        }
+        ExportFormat::Clickhouse => {
+            for entry in entries {
+                writeln!(out, "{}\t{}", entry.hash, entry.count)?;
+            }
+        }
+        // a pair even without entries, so the key of every prefix exists like its .json
+        ExportFormat::WorkersKv => {
+            let body = serde_json::to_vec(entries)?;
+            let (value, base64) = if args.kv_gzip {
+                let mut gzipped = Vec::new();
+                compress::gzip(&mut gzipped, &body, 9, GzipEngine::Flate2, false)?;
+                (BASE64_STANDARD.encode(gzipped), true)
+            } else {
+                (String::from_utf8(body).expect("JSON is UTF-8"), false)
+            };
+            let pair = KvPair {
+                key: format!("{key_prefix}{prefix}"),
+                value,
+                base64,
+            };
+            serde_json::to_writer(out, &pair)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What the export starts with, before the entries of the first prefix.
+fn header(args: &ExportArgs) -> String {
+    match args.format {
+        ExportFormat::Redis | ExportFormat::WorkersKv => String::new(),
+        // the names and types rows of TabSeparatedWithNamesAndTypes
+        ExportFormat::Clickhouse => {
+            let (names, kinds): (Vec<_>, Vec<_>) = CLICKHOUSE_COLUMNS.into_iter().unzip();
+            format!("{}\n{}\n", names.join("\t"), kinds.join("\t"))
+        }
+    }
+}
+
+/// Exports the hashes of the hash file at `path`.
+fn export_prefix(args: &ExportArgs, path: &Path) -> Result<Vec<u8>, GenerateError> {
+    let prefix = prefix_name(path)?.to_ascii_uppercase();
+    let content = input::read_all(path).map_err(|source| GenerateError::Read {
+        path: path.into(),
+        source,
+    })?;
+
+    let options = ParseOptions {
+        min_count: args.min_count,
+        strict: false,
+        hash_type: HashType::Sha1,
+        hash_order: false,
+        lenient: false,
+        merge_duplicates: false,
+        count_overflow: CountOverflow::Error,
+    };
+    let mut entries = Vec::new();
+    parse_lines(&content, options, |suffix, count| {
+        let mut hash = HashBuf::new(&prefix, suffix);
+        hash.make_ascii_uppercase();
+        entries.push(Password { hash, count });
+        Ok::<_, Infallible>(())
+    })
+    .map_err(|err| match err {
+        ParseError::Line(line, kind) => GenerateError::Parse {
+            path: path.into(),
+            line,
+            kind,
+        },
+        ParseError::Sink(never) => match never {},
+    })?;
+
+    let mut out = Vec::new();
+    write_prefix(args, &prefix, &entries, &mut out).map_err(|source| GenerateError::Write {
+        path: path.into(),
+        source,
+    })?;
+    Ok(out)
+}
+
+pub fn run(args: &ExportArgs) -> Result<()> {
+    let paths = find_all_hash_files(&args.hashes)?;
+    info!(
+        "Exporting {} hash files to {} as {:?}",
+        paths.len(),
+        args.out.display(),
+        args.format
+    );
+
+    let bar = ProgressBar::new(paths.len() as u64);
+    let mut sink = match args.format {
+        ExportFormat::WorkersKv => Sink::Kv(KvPayloads::new(&args.out)?),
+        _ => {
+            let mut file = AtomicFile::create(args.out.clone(), false)
+                .with_context(|| format!("unable to create {}", args.out.display()))?;
+            file.write_all(header(args).as_bytes())?;
+            Sink::File(file)
+        }
+    };
+    // exported in parallel, but written in the order of the prefixes
+    for batch in paths.chunks(BATCH) {
+        let exported = batch
+            .par_iter()
+            .progress_with(bar.clone())
+            .map(|path| export_prefix(args, path))
+            .collect::<Result<Vec<_>, _>>()?;
+        for out in exported {
+            match &mut sink {
+                Sink::File(file) => file.write_all(&out)?,
+                Sink::Kv(payloads) => payloads.push(&out)?,
+            }
+        }
+    }
+    bar.finish();
+
+    match sink {
+        Sink::File(file) => {
+            let size = file.commit(None)?;
+            info!("Exported {} to {}", HumanBytes(size), args.out.display());
+        }
+        Sink::Kv(payloads) => {
+            let (count, size) = payloads.finish()?;
+            info!(
+                "Exported {} in {count} bulk upload payloads to {}",
+                HumanBytes(size),
+                args.out.display()
+            );
+        }
+    }
+
+    if args.format == ExportFormat::Clickhouse {
+        let schema = args.out.with_extension("sql");
+        std::fs::write(&schema, clickhouse_schema(&args.table))
+            .with_context(|| format!("unable to write {}", schema.display()))?;
+        info!(
+            "Create the table with {}, then insert the rows with INSERT INTO {} FORMAT \
+             TabSeparatedWithNamesAndTypes",
+            schema.display(),
+            args.table
+        );
+    }
+
+    Ok(())
+}
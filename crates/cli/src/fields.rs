@@ -0,0 +1,226 @@
+use std::{fmt, io::Read, sync::Arc};
+
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    ser::SerializeMap,
+    Deserializer, Serialize, Serializer,
+};
+
+use crate::{dataset::FieldNames, hash_buf::HashBuf, rank::Ranks, Password};
+
+/// The names of the fields of every entry in the generated files, to match an existing API
+/// contract.
+#[derive(Debug, Clone)]
+pub struct Fields {
+    pub hash: String,
+    pub count: String,
+    /// Adds the global `rank` and `percentile` of the count to every entry with --with-rank
+    pub ranks: Option<Arc<Ranks>>,
+}
+
+impl Fields {
+    /// `passwords` as a JSON array of entries with these field names.
+    pub fn entries<'a>(&'a self, passwords: &'a [Password]) -> Entries<'a> {
+        Entries {
+            fields: self,
+            passwords,
+        }
+    }
+
+    /// `password` as an entry with these field names.
+    pub fn entry<'a>(&'a self, password: &'a Password) -> Entry<'a> {
+        Entry {
+            fields: self,
+            password,
+        }
+    }
+
+    /// Writes `passwords` into `out` byte for byte like serializing [`Self::entries`] with
+    /// serde_json, but pushing the bytes of the fixed-shape entries directly, with --fast-json.
+    pub fn write_entries(
+        &self,
+        passwords: &[Password],
+        out: &mut Vec<u8>,
+    ) -> serde_json::Result<()> {
+        // hashes are hex, only the configurable field names may need escaping
+        let hash_key = format!("{{{}:\"", serde_json::to_string(&self.hash)?);
+        let count_key = format!("\",{}:", serde_json::to_string(&self.count)?);
+        let mut itoa = itoa::Buffer::new();
+
+        out.reserve(passwords.len() * (hash_key.len() + count_key.len() + 50));
+        out.push(b'[');
+        for (i, password) in passwords.iter().enumerate() {
+            if i > 0 {
+                out.push(b',');
+            }
+            out.extend_from_slice(hash_key.as_bytes());
+            out.extend_from_slice(password.hash.as_bytes());
+            out.extend_from_slice(count_key.as_bytes());
+            out.extend_from_slice(itoa.format(password.count).as_bytes());
+            if let Some(ranks) = &self.ranks {
+                let rank = ranks.rank(password.count);
+                out.extend_from_slice(b",\"rank\":");
+                out.extend_from_slice(itoa.format(rank).as_bytes());
+                out.extend_from_slice(b",\"percentile\":");
+                serde_json::to_writer(&mut *out, &ranks.percentile(rank))?;
+            }
+            out.push(b'}');
+        }
+        out.push(b']');
+        Ok(())
+    }
+}
+
+pub struct Entries<'a> {
+    fields: &'a Fields,
+    passwords: &'a [Password],
+}
+
+impl Serialize for Entries<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.passwords.iter().map(|p| self.fields.entry(p)))
+    }
+}
+
+pub struct Entry<'a> {
+    fields: &'a Fields,
+    password: &'a Password,
+}
+
+impl Serialize for Entry<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // a map rather than a struct, whose field names have to be known at compile time
+        let len = if self.fields.ranks.is_some() { 4 } else { 2 };
+        let mut map = serializer.serialize_map(Some(len))?;
+        map.serialize_entry(&self.fields.hash, &self.password.hash)?;
+        map.serialize_entry(&self.fields.count, &self.password.count)?;
+        if let Some(ranks) = &self.fields.ranks {
+            let rank = ranks.rank(self.password.count);
+            map.serialize_entry("rank", &rank)?;
+            map.serialize_entry("percentile", &ranks.percentile(rank))?;
+        }
+        map.end()
+    }
+}
+
+/// Parses the entries of a generated .json file read from `reader`, whose fields are named like
+/// `names`. Further fields, like the rank, are skipped.
+pub fn parse_entries(names: &FieldNames, reader: impl Read) -> serde_json::Result<Vec<Password>> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let entries = EntriesSeed(names).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(entries)
+}
+
+struct EntriesSeed<'a>(&'a FieldNames);
+
+impl<'de> DeserializeSeed<'de> for EntriesSeed<'_> {
+    type Value = Vec<Password>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntriesSeed<'_> {
+    type Value = Vec<Password>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an array of entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(entry) = seq.next_element_seed(EntrySeed(self.0))? {
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+struct EntrySeed<'a>(&'a FieldNames);
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = Password;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = Password;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "an entry with `{}` and `{}`", self.0.hash, self.0.count)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut hash, mut count) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            if key == self.0.hash {
+                hash = Some(map.next_value::<HashBuf>()?);
+            } else if key == self.0.count {
+                count = Some(map.next_value::<u64>()?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+
+        let missing = |field: &str| de::Error::custom(format!("missing field `{field}`"));
+        Ok(Password {
+            hash: hash.ok_or_else(|| missing(&self.0.hash))?,
+            count: count.ok_or_else(|| missing(&self.0.count))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renamed_fields() {
+        let names = FieldNames {
+            hash: "sha1".into(),
+            count: "prevalence".into(),
+        };
+        let json = br#"[{"sha1":"ABCDE0","prevalence":3,"rank":1,"percentile":0.5}]"#;
+        let entries = parse_entries(&names, &json[..]).unwrap();
+        assert_eq!((entries[0].hash.as_str(), entries[0].count), ("ABCDE0", 3));
+
+        assert!(parse_entries(&FieldNames::default(), &json[..]).is_err());
+    }
+
+    #[test]
+    fn write_entries_like_serde_json() {
+        let passwords = [
+            Password {
+                hash: HashBuf::new("ABCDE", "0018A45C4D1DEF81644B54AB7F969B88D65"),
+                count: u64::MAX,
+            },
+            Password {
+                hash: HashBuf::new("abcde", "00d4f6e8fa6eecad2a3aa415eec418d38ec"),
+                count: 0,
+            },
+        ];
+        for (hash, count) in [("hash", "count"), ("h\"a\\sh", "c\u{7}ount</")] {
+            let fields = Fields {
+                hash: hash.into(),
+                count: count.into(),
+                ranks: None,
+            };
+            let mut fast = Vec::new();
+            fields.write_entries(&passwords, &mut fast).unwrap();
+            let serde = serde_json::to_vec(&fields.entries(&passwords)).unwrap();
+            assert_eq!(
+                String::from_utf8(fast).unwrap(),
+                String::from_utf8(serde).unwrap()
+            );
+
+            let mut empty = Vec::new();
+            fields.write_entries(&[], &mut empty).unwrap();
+            assert_eq!(empty, b"[]");
+        }
+    }
+}
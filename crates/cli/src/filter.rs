@@ -0,0 +1,77 @@
+//! Filters the generated entries against lists of full SHA-1 hashes, to strip known test hashes
+//! or restrict a dataset to a customer-provided scope.
+
+use std::{collections::HashSet, path::Path};
+
+use anyhow::{Context, Result};
+
+type Sha1 = [u8; 20];
+
+/// The hashes of `--only-hashes` and `--exclude-hashes`.
+pub struct HashFilter {
+    /// Keep nothing but these if set
+    only: Option<HashSet<Sha1>>,
+    exclude: HashSet<Sha1>,
+}
+
+impl HashFilter {
+    /// Reads the lists at `only` and `exclude`, or none if neither is set.
+    pub fn read(only: Option<&Path>, exclude: Option<&Path>) -> Result<Option<Self>> {
+        if only.is_none() && exclude.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            only: only.map(read_list).transpose()?,
+            exclude: exclude.map(read_list).transpose()?.unwrap_or_default(),
+        }))
+    }
+
+    /// Whether the entry of the full 40 character `hash` is kept, in either case.
+    pub fn keeps(&self, hash: &str) -> bool {
+        let Some(hash) = decode(hash) else {
+            return self.only.is_none();
+        };
+        self.only.as_ref().is_none_or(|only| only.contains(&hash)) && !self.exclude.contains(&hash)
+    }
+}
+
+/// Reads a list of full SHA-1 hashes in hex, one per line. Anything after a `:` is ignored, so
+/// HIBP-style `HASH:COUNT` lines work too, and empty lines and `#` comments are skipped.
+fn read_list(path: &Path) -> Result<HashSet<Sha1>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read hash list {}", path.display()))?;
+
+    let mut hashes = HashSet::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.split(':').next().unwrap_or_default().trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let hash = decode(line).with_context(|| {
+            format!(
+                "{}:{}: `{line}` is not a 40 character SHA-1 hash",
+                path.display(),
+                i + 1
+            )
+        })?;
+        hashes.insert(hash);
+    }
+
+    Ok(hashes)
+}
+
+/// Decodes a 40 character hex SHA-1 hash in either case.
+fn decode(hash: &str) -> Option<Sha1> {
+    let hash = hash.as_bytes();
+    if hash.len() != 40 {
+        return None;
+    }
+
+    let mut decoded = [0; 20];
+    for (byte, pair) in decoded.iter_mut().zip(hash.chunks_exact(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(decoded)
+}
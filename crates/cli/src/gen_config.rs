@@ -0,0 +1,231 @@
+use std::{fmt::Write, path::PathBuf};
+
+use anyhow::Result;
+use clap::{ArgAction, ValueEnum};
+use hibp_json_util::{PathTemplate, PREFIX_LEN};
+
+use crate::{generate::json_path_with, output_depth, Layout};
+
+#[derive(clap::Args, Debug)]
+pub struct GenConfigArgs {
+    /// The web server to configure
+    #[arg(value_enum)]
+    server: WebServer,
+
+    /// Path to the generated files
+    #[arg(long, env = "HIBP_JSON_OUT", default_value = "dist")]
+    dist: PathBuf,
+
+    /// The host name to serve the files at
+    #[arg(long, default_value = "localhost")]
+    server_name: String,
+
+    /// The URL path the ranges are served under, e.g. /range/ for /range/ABCDE
+    #[arg(long, default_value = "/")]
+    location: String,
+
+    /// How many seconds clients and CDNs may cache a range for
+    #[arg(long, default_value_t = 86400)]
+    max_age: u64,
+
+    /// Whether the files were generated with --gzip
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    gzip: bool,
+
+    /// Whether the files were generated with --brotli
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    brotli: bool,
+
+    /// The --depth the files were generated with
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(0..=4))]
+    depth: u8,
+
+    /// The --layout the files were generated with
+    #[arg(long, value_enum, default_value_t = Layout::Nested)]
+    layout: Layout,
+
+    /// The --path-template the files were generated with
+    #[arg(long, conflicts_with_all = ["depth", "layout"])]
+    path_template: Option<PathTemplate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum WebServer {
+    /// nginx, with the ngx_brotli module for the .br files
+    Nginx,
+    Caddy,
+    /// Apache httpd, with mod_rewrite and mod_headers
+    Apache,
+}
+
+/// Matches a prefix in the URL, one capture group per character. The outputs are always named
+/// in uppercase.
+const PREFIX_CHAR: &str = "([0-9A-F])";
+
+impl GenConfigArgs {
+    /// The URL path the ranges are served under, starting and ending with a slash.
+    fn location(&self) -> String {
+        let location = self.location.trim_matches('/');
+        if location.is_empty() {
+            "/".into()
+        } else {
+            format!("/{location}/")
+        }
+    }
+
+    /// The path of the .json file of a prefix relative to the output directory, with `chars`
+    /// standing in for its characters.
+    fn target(&self, chars: [&str; PREFIX_LEN]) -> String {
+        let depth = output_depth(self.layout, self.depth, self.path_template.is_some());
+        json_path_with(depth, self.path_template.as_ref(), chars)
+    }
+
+    fn root(&self) -> String {
+        let root = self
+            .dist
+            .canonicalize()
+            .unwrap_or_else(|_| self.dist.clone());
+        root.display().to_string()
+    }
+}
+
+fn nginx(args: &GenConfigArgs) -> Result<String> {
+    let pattern: String = (0..PREFIX_LEN)
+        .map(|i| format!("(?<p{i}>[0-9A-F])"))
+        .collect();
+    let target = args.target(["$p0", "$p1", "$p2", "$p3", "$p4"]);
+
+    let mut config = String::new();
+    writeln!(config, "server {{")?;
+    writeln!(config, "    listen 80;")?;
+    writeln!(config, "    server_name {};", args.server_name)?;
+    writeln!(config, "    root {};", args.root())?;
+    writeln!(config)?;
+    writeln!(
+        config,
+        "    location ~ \"^{}{pattern}$\" {{",
+        args.location()
+    )?;
+    writeln!(config, "        rewrite ^ /{target} break;")?;
+    writeln!(config, "        types {{ }}")?;
+    writeln!(config, "        default_type application/json;")?;
+    if args.brotli {
+        writeln!(config, "        brotli_static on;")?;
+    }
+    if args.gzip {
+        writeln!(config, "        gzip_static on;")?;
+        writeln!(config, "        gzip_vary on;")?;
+    }
+    writeln!(
+        config,
+        "        add_header Cache-Control \"public, max-age={}\";",
+        args.max_age
+    )?;
+    writeln!(config, "    }}")?;
+    writeln!(config, "}}")?;
+    Ok(config)
+}
+
+fn caddy(args: &GenConfigArgs) -> Result<String> {
+    let pattern = PREFIX_CHAR.repeat(PREFIX_LEN);
+    let chars = ["1", "2", "3", "4", "5"].map(|i| format!("{{re.range.{i}}}"));
+    let target = args.target(chars.each_ref().map(String::as_str));
+
+    let mut config = String::new();
+    writeln!(config, "{} {{", args.server_name)?;
+    writeln!(config, "\troot * {}", args.root())?;
+    writeln!(
+        config,
+        "\t@range path_regexp range ^{}{pattern}$",
+        args.location()
+    )?;
+    // matched before the rewrite, which runs after the header directive
+    writeln!(
+        config,
+        "\theader @range Cache-Control \"public, max-age={}\"",
+        args.max_age
+    )?;
+    writeln!(config, "\trewrite @range /{target}")?;
+    let precompressed: Vec<_> = [(args.brotli, "br"), (args.gzip, "gzip")]
+        .into_iter()
+        .filter_map(|(enabled, encoding)| enabled.then_some(encoding))
+        .collect();
+    if precompressed.is_empty() {
+        writeln!(config, "\tfile_server")?;
+    } else {
+        writeln!(config, "\tfile_server {{")?;
+        writeln!(config, "\t\tprecompressed {}", precompressed.join(" "))?;
+        writeln!(config, "\t}}")?;
+    }
+    writeln!(config, "}}")?;
+    Ok(config)
+}
+
+fn apache(args: &GenConfigArgs) -> Result<String> {
+    let pattern = format!("^{}{}$", args.location(), PREFIX_CHAR.repeat(PREFIX_LEN));
+    let target = args.target(["$1", "$2", "$3", "$4", "$5"]);
+    let root = args.root();
+
+    let mut config = String::new();
+    writeln!(config, "<VirtualHost *:80>")?;
+    writeln!(config, "    ServerName {}", args.server_name)?;
+    writeln!(config, "    DocumentRoot \"{root}\"")?;
+    writeln!(config, "    <Directory \"{root}\">")?;
+    writeln!(config, "        Require all granted")?;
+    writeln!(config, "    </Directory>")?;
+    writeln!(config)?;
+    writeln!(config, "    RewriteEngine On")?;
+    // the precompressed variants first, for the clients that accept them
+    for (enabled, encoding, suffix) in [(args.brotli, "br", ".br"), (args.gzip, "gzip", ".gz")] {
+        if !enabled {
+            continue;
+        }
+        writeln!(
+            config,
+            "    RewriteCond \"%{{HTTP:Accept-Encoding}}\" \"{encoding}\""
+        )?;
+        writeln!(
+            config,
+            "    RewriteCond \"%{{DOCUMENT_ROOT}}/{target}{suffix}\" -f"
+        )?;
+        writeln!(
+            config,
+            "    RewriteRule \"{pattern}\" \"/{target}{suffix}\" [L,T=application/json,E=no-gzip:1]"
+        )?;
+    }
+    writeln!(
+        config,
+        "    RewriteRule \"{pattern}\" \"/{target}\" [L,T=application/json]"
+    )?;
+    writeln!(config)?;
+    for (enabled, encoding, suffix) in [(args.brotli, "br", "br"), (args.gzip, "gzip", "gz")] {
+        if enabled {
+            writeln!(config, "    <FilesMatch \"\\.json\\.{suffix}$\">")?;
+            writeln!(config, "        Header set Content-Encoding {encoding}")?;
+            writeln!(config, "    </FilesMatch>")?;
+        }
+    }
+    writeln!(config, "    <FilesMatch \"\\.json(\\.br|\\.gz)?$\">")?;
+    writeln!(config, "        Header append Vary Accept-Encoding")?;
+    writeln!(
+        config,
+        "        Header set Cache-Control \"public, max-age={}\"",
+        args.max_age
+    )?;
+    writeln!(config, "    </FilesMatch>")?;
+    writeln!(config, "</VirtualHost>")?;
+    Ok(config)
+}
+
+/// Prints the configuration of `args.server` for serving the generated files at
+/// `args.location` followed by a prefix, e.g. /ABCDE.
+pub fn run(args: &GenConfigArgs) -> Result<()> {
+    let config = match args.server {
+        WebServer::Nginx => nginx(args)?,
+        WebServer::Caddy => caddy(args)?,
+        WebServer::Apache => apache(args)?,
+    };
+    print!("{config}");
+
+    Ok(())
+}
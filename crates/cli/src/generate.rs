@@ -0,0 +1,1946 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    num::IntErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use hibp_json_util::{directory_ends, prefix_path, PathTemplate, PREFIX_LEN};
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::{
+    archive::Archive,
+    by_count,
+    checkpoint::Checkpoint,
+    compress::{self, BrotliParams, GzipEngine},
+    dataset::FormatBytes,
+    dictionary::Dictionary,
+    dump::Dump,
+    encrypt::Encryption,
+    error::{GenerateError, LineError},
+    fields::Fields,
+    file_prefix,
+    filter::HashFilter,
+    find_duplicate,
+    hash_buf::HashBuf,
+    in_tier,
+    input::{self, Content, IoBackend, RemoveSource},
+    keep_top,
+    manifest::{self, HashWriter, ManifestEntry, ManifestWriter},
+    memory::{Memory, Reservation},
+    merge_duplicates,
+    ndjson::{self, Ndjson},
+    prefix_of,
+    previous::Previous,
+    progress::StageTimes,
+    remote::Remote,
+    state::{SourceState, Stamp},
+    summary::{Row, Summary},
+    tier_dir, uring, with_suffix, Case, OnExisting, Password, SortBy,
+};
+
+/// Everything needed to turn a hash file into its outputs, shared by the parallel workers.
+pub struct Generator<'a> {
+    pub dist: &'a Path,
+    pub depth: usize,
+    pub template: Option<&'a PathTemplate>,
+    pub json: bool,
+    pub gzip: bool,
+    pub brotli: bool,
+    pub parse: ParseOptions,
+    /// Drops the entries excluded by --only-hashes and --exclude-hashes
+    pub filter: Option<&'a HashFilter>,
+    pub top: Option<usize>,
+    pub sort_by: SortBy,
+    pub case: Case,
+    pub fields: Fields,
+    pub on_existing: OnExisting,
+    /// Count thresholds to also write a filtered tree for, ascending
+    pub tiers: Vec<u64>,
+    pub gzip_level: u32,
+    pub gzip_engine: GzipEngine,
+    pub reproducible: bool,
+    pub brotli_params: BrotliParams,
+    /// Also write .dcb files, brotli compressed with this shared dictionary
+    pub dictionary: Option<Dictionary>,
+    /// Encrypt every output at rest with --encrypt
+    pub encryption: Option<Encryption>,
+    /// Read every output back before committing it and check it decodes to the serialized JSON
+    pub self_check: bool,
+    /// Leave out compressed variants that are no smaller than the JSON
+    pub skip_larger_compressed: bool,
+    /// Serialize the entries without serde_json
+    pub fast_json: bool,
+    /// Removes every hash file once its outputs are written
+    pub remove_source: Option<RemoveSource>,
+    pub mmap: bool,
+    pub stream: bool,
+    pub io_backend: IoBackend,
+    /// Read the hash files out of a single file instead
+    pub dump: Option<&'a Dump>,
+    /// Append the generated files to an archive instead of writing them out
+    pub archive: Option<&'a Archive>,
+    /// Write the temporary files of the outputs here instead of next to them
+    pub tmp_dir: Option<&'a Path>,
+    /// Upload the generated files to object storage instead of writing them out
+    pub remote: Option<&'a Remote>,
+    /// Write the entries to stdout instead of any files, with --out -
+    pub ndjson: Option<&'a Ndjson>,
+    /// Records a row per generated prefix for summary.csv, with --summary
+    pub summary: Option<&'a Summary>,
+    /// Hardlink outputs identical to the previous version to its files
+    pub previous: Option<&'a Previous>,
+    /// Bounds the memory of the prefixes generated at once, with --max-memory
+    pub memory: Option<&'a Memory>,
+    /// Records completed prefixes, not kept on a dry run
+    pub checkpoint: Option<Checkpoint>,
+    pub state: Option<SourceState>,
+    pub manifest: Option<ManifestWriter>,
+    pub directories: Directories,
+    pub totals: Totals,
+}
+
+/// A hash file read ahead of time by the I/O stage of the pipeline.
+pub struct Loaded<'a> {
+    pub path: PathBuf,
+    stamp: Option<Stamp>,
+    content: Vec<u8>,
+    /// Held from reading it until it's generated
+    _reserved: Option<Reservation<'a>>,
+}
+
+impl Loaded<'_> {
+    /// Size of the content that was read.
+    pub fn size(&self) -> u64 {
+        self.content.len() as u64
+    }
+}
+
+/// The prefix a hash file is named after, as it appears in its file name.
+pub fn prefix_name(path: &Path) -> Result<&str, GenerateError> {
+    file_prefix(path)
+        .filter(|_| prefix_of(path).is_some())
+        .ok_or_else(|| GenerateError::InvalidName(path.into()))
+}
+
+/// Buffers reused by a worker across hash files, instead of reallocating them for every prefix.
+#[derive(Default)]
+pub struct Buffers {
+    content: Vec<u8>,
+    passwords: Vec<Password>,
+    serialized: Vec<u8>,
+    gzip: Vec<u8>,
+    brotli: Vec<u8>,
+    dcb: Vec<u8>,
+}
+
+impl Buffers {
+    /// Shrinks every buffer to at most `cap` bytes.
+    fn trim(&mut self, cap: usize) {
+        fn shrink<T>(buf: &mut Vec<T>, cap: usize) {
+            buf.shrink_to(cap / std::mem::size_of::<T>().max(1));
+        }
+
+        shrink(&mut self.content, cap);
+        shrink(&mut self.passwords, cap);
+        shrink(&mut self.serialized, cap);
+        shrink(&mut self.gzip, cap);
+        shrink(&mut self.brotli, cap);
+        shrink(&mut self.dcb, cap);
+    }
+}
+
+/// The output directories created so far. Each one is created by the first prefix written into
+/// it rather than all of them ahead of time, which costs a round trip per directory on network
+/// filesystems even when they already exist.
+#[derive(Debug, Default)]
+pub struct Directories(Mutex<HashSet<PathBuf>>);
+
+impl Directories {
+    /// Creates `dir` and its parents, unless it was created already.
+    pub fn ensure(&self, dir: &Path) -> std::io::Result<()> {
+        if self.0.lock().unwrap().contains(dir) {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(dir)?;
+        self.0.lock().unwrap().insert(dir.into());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Totals {
+    /// Hash files that were processed, successfully or not
+    pub done: AtomicU64,
+    /// Bytes of the hash files that were processed, as they're stored
+    pub read: AtomicU64,
+    pub entries: AtomicU64,
+    pub json: AtomicU64,
+    pub gzip: AtomicU64,
+    pub brotli: AtomicU64,
+    pub dcb: AtomicU64,
+    /// Malformed lines skipped with --lenient
+    pub skipped: AtomicU64,
+    pub times: StageTimes,
+}
+
+impl Totals {
+    /// Counts a processed hash file of `size` bytes.
+    pub fn finish(&self, size: u64) {
+        self.done.fetch_add(1, Ordering::SeqCst);
+        self.read.fetch_add(size, Ordering::SeqCst);
+    }
+
+    /// Bytes written so far per format.
+    pub fn bytes(&self) -> FormatBytes {
+        FormatBytes {
+            json: self.json.load(Ordering::SeqCst),
+            gzip: self.gzip.load(Ordering::SeqCst),
+            brotli: self.brotli.load(Ordering::SeqCst),
+            dcb: self.dcb.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl Generator<'_> {
+    /// Generates every enabled output of the hash file at `path`.
+    pub fn process(&self, path: &Path, buffers: &mut Buffers) -> Result<(), GenerateError> {
+        let prefix = prefix_name(path)?;
+        let _reserved = self.reserve(path);
+        let stamp = self.stamp(path)?;
+        // taken out of the buffers while it's read from, generating borrows the others
+        let mut buf = std::mem::take(&mut buffers.content);
+        let result = self
+            .read(path, self.mmap, &mut buf)
+            .and_then(|content| self.generate(path, prefix, stamp, &content, buffers));
+        buffers.content = buf;
+        self.trim(buffers);
+        result
+    }
+
+    /// Reads the hash file at `path` ahead of time, to be generated with [`Self::process_loaded`].
+    pub fn load(&self, path: PathBuf) -> Result<Loaded<'_>, (PathBuf, GenerateError)> {
+        let reserved = self.reserve(&path);
+        let loaded = self.stamp(&path).and_then(|stamp| {
+            let mut content = Vec::new();
+            self.read(&path, false, &mut content)?;
+            Ok((stamp, content))
+        });
+
+        match loaded {
+            Ok((stamp, content)) => Ok(Loaded {
+                path,
+                stamp,
+                content,
+                _reserved: reserved,
+            }),
+            Err(err) => Err((path, err)),
+        }
+    }
+
+    /// Holds the `content` of the virtual hash file at `path`, e.g. a prefix read from stdin, to
+    /// be generated with [`Self::process_loaded`].
+    pub fn loaded(&self, path: PathBuf, content: Vec<u8>) -> Loaded<'_> {
+        let reserved = self
+            .memory
+            .map(|memory| memory.reserve(content.len() as u64));
+        Loaded {
+            path,
+            stamp: None,
+            content,
+            _reserved: reserved,
+        }
+    }
+
+    /// Generates every enabled output of a hash file read by [`Self::load`].
+    pub fn process_loaded(
+        &self,
+        loaded: &Loaded,
+        buffers: &mut Buffers,
+    ) -> Result<(), GenerateError> {
+        let prefix = prefix_name(&loaded.path)?;
+        let result = self.generate(&loaded.path, prefix, loaded.stamp, &loaded.content, buffers);
+        self.trim(buffers);
+        result
+    }
+
+    /// Waits until there's memory to generate the hash file at `path` with --max-memory.
+    fn reserve(&self, path: &Path) -> Option<Reservation<'_>> {
+        self.memory
+            .map(|memory| memory.reserve(self.input_size(path)))
+    }
+
+    /// Frees what the buffers of a worker grew to beyond their share of --max-memory.
+    fn trim(&self, buffers: &mut Buffers) {
+        if let Some(memory) = self.memory {
+            buffers.trim(memory.buffer_cap(rayon::current_num_threads()));
+        }
+    }
+
+    /// Size of the hash file at `path` as it's stored, or of the prefix of the dump it stands for.
+    pub fn input_size(&self, path: &Path) -> u64 {
+        match self.dump {
+            Some(dump) => dump.size(path),
+            None => std::fs::metadata(path).map_or(0, |m| m.len()),
+        }
+    }
+
+    /// Reads the hash file at `path`, or the prefix of the dump it stands for.
+    fn read<'b>(
+        &self,
+        path: &Path,
+        mmap: bool,
+        buf: &'b mut Vec<u8>,
+    ) -> Result<Content<'b>, GenerateError> {
+        let read_err = |source: std::io::Error| GenerateError::Read {
+            path: path.into(),
+            source,
+        };
+        StageTimes::time(&self.totals.times.read, || match self.dump {
+            Some(dump) => {
+                dump.read(path, buf).map_err(read_err)?;
+                Ok(Content::Buffered(buf))
+            }
+            None => input::read(path, mmap, self.io_backend, buf).map_err(read_err),
+        })
+    }
+
+    fn stamp(&self, path: &Path) -> Result<Option<Stamp>, GenerateError> {
+        self.state
+            .as_ref()
+            .map(|_| Stamp::of(path))
+            .transpose()
+            .map_err(|source| GenerateError::Read {
+                path: path.into(),
+                source,
+            })
+    }
+
+    fn generate(
+        &self,
+        path: &Path,
+        prefix: &str,
+        stamp: Option<Stamp>,
+        content: &[u8],
+        buffers: &mut Buffers,
+    ) -> Result<(), GenerateError> {
+        // hash files may be named in lowercase, the outputs never are
+        let prefix = &prefix.to_ascii_uppercase();
+        let start = Instant::now();
+
+        let (entries, bytes) = if let Some(ndjson) = self.ndjson {
+            self.process_ndjson(path, prefix, content, ndjson, buffers)?
+        } else if self.stream {
+            let out = self.out_path(self.dist, prefix)?;
+            let written = self.process_streaming(path, prefix, content, &out)?;
+            self.totals.entries.fetch_add(written.0, Ordering::SeqCst);
+            written
+        } else {
+            let out = self.out_path(self.dist, prefix)?;
+            self.process_buffered(path, prefix, content, &out, buffers)?
+        };
+
+        if let Some(summary) = self.summary {
+            summary.record(Row {
+                prefix: prefix.clone(),
+                entries,
+                raw: content.len() as u64,
+                bytes,
+                time: start.elapsed(),
+            });
+        }
+
+        if let (Some(state), Some(stamp), Some(id)) = (&self.state, stamp, prefix_of(path)) {
+            state.record(id, stamp);
+        }
+
+        // only once every output is written, and read back with --self-check
+        if let Some(remove) = self.remove_source {
+            remove
+                .remove(path, content)
+                .map_err(|source| GenerateError::RemoveSource {
+                    path: path.into(),
+                    source,
+                })?;
+        }
+
+        match &self.checkpoint {
+            Some(checkpoint) => checkpoint
+                .complete(prefix)
+                .map_err(GenerateError::Checkpoint),
+            None => Ok(()),
+        }
+    }
+
+    /// Sizes every enabled output of the hash file at `path` without writing anything, adding
+    /// them to the totals as if they were written.
+    pub fn estimate(&self, path: &Path, buffers: &mut Buffers) -> Result<(), GenerateError> {
+        let prefix = &prefix_name(path)?.to_ascii_uppercase();
+        let content = self.read(path, self.mmap, &mut buffers.content)?;
+        self.serialize(
+            path,
+            prefix,
+            &content,
+            &mut buffers.passwords,
+            &mut buffers.serialized,
+        )?;
+        let serialized = buffers.serialized.as_slice();
+
+        let measure = |total: &AtomicU64, write: &dyn Fn(&mut Counter) -> std::io::Result<()>| {
+            let mut counter = Counter(0);
+            write(&mut counter).map_err(|source| GenerateError::Write {
+                path: path.into(),
+                source,
+            })?;
+            total.fetch_add(counter.0, Ordering::SeqCst);
+            Ok::<_, GenerateError>(())
+        };
+
+        if self.json {
+            measure(&self.totals.json, &|out| out.write_all(serialized))?;
+        }
+        if self.gzip {
+            measure(&self.totals.gzip, &|out| {
+                compress::gzip(
+                    out,
+                    serialized,
+                    self.gzip_level,
+                    self.gzip_engine,
+                    self.reproducible,
+                )
+            })?;
+        }
+        if self.brotli {
+            measure(&self.totals.brotli, &|out| {
+                compress::brotli(out, serialized, &self.brotli_params)
+            })?;
+        }
+        if let Some(dictionary) = &self.dictionary {
+            measure(&self.totals.dcb, &|out| {
+                dictionary.compress(out, serialized, &self.brotli_params)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds how many hashes of the hash file at `path` were seen how many times to `counts`,
+    /// regardless of --min-count.
+    pub fn count_histogram(
+        &self,
+        path: &Path,
+        buffers: &mut Buffers,
+        counts: &mut HashMap<u64, u64>,
+    ) -> Result<(), GenerateError> {
+        let content = self.read(path, self.mmap, &mut buffers.content)?;
+        let options = ParseOptions {
+            min_count: 0,
+            ..self.parse
+        };
+        parse_lines(&content, options, |_, count| {
+            *counts.entry(count).or_default() += 1;
+            Ok::<_, Infallible>(())
+        })
+        .map_err(|err| match err {
+            ParseError::Line(line, kind) => GenerateError::Parse {
+                path: path.into(),
+                line,
+                kind,
+            },
+            ParseError::Sink(never) => match never {},
+        })
+        .map(drop)
+    }
+
+    /// Warns about the malformed lines of the hash file at `path` skipped with --lenient.
+    fn report_skipped(&self, path: &Path, skipped: Skipped) {
+        if let Some((line, kind)) = skipped.first {
+            warn!(
+                "{}: skipped {} malformed lines, the first one at line {line}: {kind}",
+                path.display(),
+                skipped.lines
+            );
+            self.totals
+                .skipped
+                .fetch_add(skipped.lines as u64, Ordering::SeqCst);
+        }
+    }
+
+    /// Parses the hash file of `prefix` and serializes the passwords to keep into `serialized`.
+    fn serialize(
+        &self,
+        path: &Path,
+        prefix: &str,
+        content: &[u8],
+        passwords: &mut Vec<Password>,
+        serialized: &mut Vec<u8>,
+    ) -> Result<(), GenerateError> {
+        self.parse_passwords(path, prefix, content, passwords)?;
+        self.to_json(path, passwords, serialized)
+    }
+
+    /// Parses the hash file of `prefix` into the passwords to keep, in the order to write them.
+    fn parse_passwords(
+        &self,
+        path: &Path,
+        prefix: &str,
+        content: &[u8],
+        passwords: &mut Vec<Password>,
+    ) -> Result<(), GenerateError> {
+        StageTimes::time(&self.totals.times.parse, || {
+            let options = ParseOptions {
+                // the merged count may reach it even when those of the lines don't
+                min_count: if self.parse.merge_duplicates {
+                    0
+                } else {
+                    self.parse.min_count
+                },
+                ..self.parse
+            };
+            let skipped =
+                parse_hash_file(prefix, content, options, passwords).map_err(|(line, kind)| {
+                    GenerateError::Parse {
+                        path: path.into(),
+                        line,
+                        kind,
+                    }
+                })?;
+            self.report_skipped(path, skipped);
+
+            if self.parse.merge_duplicates {
+                let merged = merge_duplicates(passwords);
+                if merged > 0 {
+                    debug!("{}: merged {merged} duplicate hashes", path.display());
+                }
+                passwords.retain(|password| password.count >= self.parse.min_count);
+            } else if self.parse.strict {
+                // parsing only catches the ones next to each other, e.g. not in count order
+                if let Some(suffix) = find_duplicate(passwords) {
+                    return Err(GenerateError::Parse {
+                        path: path.into(),
+                        line: duplicate_line(content, suffix),
+                        kind: LineError::Duplicate(suffix.into()),
+                    });
+                }
+            }
+
+            if let Some(filter) = self.filter {
+                passwords.retain(|password| filter.keeps(&password.hash));
+            }
+
+            if let Some(top) = self.top {
+                keep_top(passwords, top);
+            }
+
+            // hex digits sort before letters in either case, so the order is kept
+            if self.case == Case::Lower {
+                for password in passwords.iter_mut() {
+                    password.hash.make_ascii_lowercase();
+                }
+            }
+
+            // a no-op for hash files already in that order
+            match self.sort_by {
+                SortBy::Hash => passwords.sort_by_key(|password| password.hash),
+                SortBy::Count => passwords.sort_by(by_count),
+            }
+            Ok::<_, GenerateError>(())
+        })?;
+
+        self.totals
+            .entries
+            .fetch_add(passwords.len() as u64, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn to_json(
+        &self,
+        path: &Path,
+        passwords: &[Password],
+        serialized: &mut Vec<u8>,
+    ) -> Result<(), GenerateError> {
+        StageTimes::time(&self.totals.times.serialize, || {
+            if !self.fast_json {
+                return to_json(path, &self.fields, passwords, serialized);
+            }
+            serialized.clear();
+            self.fields
+                .write_entries(passwords, serialized)
+                .map_err(|source| GenerateError::Serialize {
+                    path: path.into(),
+                    source,
+                })
+        })
+    }
+
+    /// Parses the whole hash file and writes its entries to stdout.
+    fn process_ndjson(
+        &self,
+        path: &Path,
+        prefix: &str,
+        content: &[u8],
+        ndjson: &Ndjson,
+        buffers: &mut Buffers,
+    ) -> Result<(u64, FormatBytes), GenerateError> {
+        let Buffers {
+            passwords,
+            serialized,
+            ..
+        } = buffers;
+
+        self.parse_passwords(path, prefix, content, passwords)?;
+        StageTimes::time(&self.totals.times.serialize, || {
+            ndjson.serialize(prefix, &self.fields, passwords, serialized)
+        })
+        .map_err(|source| GenerateError::Serialize {
+            path: path.into(),
+            source,
+        })?;
+
+        StageTimes::time(&self.totals.times.json, || ndjson.write(serialized)).map_err(
+            |source| GenerateError::Write {
+                path: ndjson::PATH.into(),
+                source,
+            },
+        )?;
+        let size = serialized.len() as u64;
+        self.totals.json.fetch_add(size, Ordering::SeqCst);
+        let bytes = FormatBytes {
+            json: size,
+            ..FormatBytes::default()
+        };
+        Ok((passwords.len() as u64, bytes))
+    }
+
+    /// Parses the whole hash file and serializes it once before writing every output. Returns
+    /// the amount of entries and the size of the outputs, not counting the tiers.
+    fn process_buffered(
+        &self,
+        path: &Path,
+        prefix: &str,
+        content: &[u8],
+        out: &Path,
+        buffers: &mut Buffers,
+    ) -> Result<(u64, FormatBytes), GenerateError> {
+        let Buffers {
+            passwords,
+            serialized,
+            gzip,
+            brotli,
+            dcb,
+            ..
+        } = buffers;
+
+        self.serialize(path, prefix, content, passwords, serialized)?;
+        let entries = passwords.len() as u64;
+        let bytes = self.write_outputs(out, serialized, gzip, brotli, dcb)?;
+
+        // tiers are ascending, so each of them is a subset of the one before
+        for &tier in &self.tiers {
+            passwords.retain(|password| password.count >= tier);
+            self.to_json(path, passwords, serialized)?;
+            let out = self.out_path(&tier_dir(self.dist, tier), prefix)?;
+            self.write_outputs(&out, serialized, gzip, brotli, dcb)?;
+        }
+
+        Ok((entries, bytes))
+    }
+
+    /// Writes every enabled output of a serialized prefix to `out`, returning their size.
+    fn write_outputs(
+        &self,
+        out: &Path,
+        serialized: &[u8],
+        gzip: &mut Vec<u8>,
+        brotli: &mut Vec<u8>,
+        dcb: &mut Vec<u8>,
+    ) -> Result<FormatBytes, GenerateError> {
+        if self.io_backend == IoBackend::Uring {
+            return self.write_batched(out, serialized, gzip, brotli, dcb);
+        }
+
+        let times = &self.totals.times;
+        let mut bytes = FormatBytes::default();
+        if self.json {
+            bytes.json = StageTimes::time(&times.json, || {
+                self.write(out.into(), &self.totals.json, serialized, |buf| {
+                    buf.write_all(serialized)
+                })
+            })?;
+        }
+
+        self.encode(out, serialized, gzip, brotli)?;
+        if self.gzip {
+            bytes.gzip = StageTimes::time(&times.gzip, || {
+                self.write_compressed(with_suffix(out, ".gz"), &self.totals.gzip, serialized, gzip)
+            })?;
+        }
+
+        if self.brotli {
+            bytes.brotli = StageTimes::time(&times.brotli, || {
+                self.write_compressed(
+                    with_suffix(out, ".br"),
+                    &self.totals.brotli,
+                    serialized,
+                    brotli,
+                )
+            })?;
+        }
+
+        if let Some(dictionary) = &self.dictionary {
+            bytes.dcb = StageTimes::time(&times.dcb, || {
+                let path = with_suffix(out, ".dcb");
+                dcb.clear();
+                if let Err(source) = dictionary.compress(dcb, serialized, &self.brotli_params) {
+                    return Err(GenerateError::Write { path, source });
+                }
+                self.write_compressed(path, &self.totals.dcb, serialized, dcb)
+            })?;
+        }
+
+        Ok(bytes)
+    }
+
+    /// Compresses `serialized` into the `gzip` and `brotli` buffers of the enabled formats. Both
+    /// encoders run at once, so a prefix takes as long as the slower of them instead of their sum.
+    fn encode(
+        &self,
+        out: &Path,
+        serialized: &[u8],
+        gzip: &mut Vec<u8>,
+        brotli: &mut Vec<u8>,
+    ) -> Result<(), GenerateError> {
+        let times = &self.totals.times;
+        let mut encode_gzip = || {
+            if !self.gzip {
+                return Ok(());
+            }
+            gzip.clear();
+            StageTimes::time(&times.gzip, || {
+                compress::gzip(
+                    gzip,
+                    serialized,
+                    self.gzip_level,
+                    self.gzip_engine,
+                    self.reproducible,
+                )
+            })
+            .map_err(|source| GenerateError::Write {
+                path: with_suffix(out, ".gz"),
+                source,
+            })
+        };
+        let mut encode_brotli = || {
+            if !self.brotli {
+                return Ok(());
+            }
+            brotli.clear();
+            StageTimes::time(&times.brotli, || {
+                compress::brotli(brotli, serialized, &self.brotli_params)
+            })
+            .map_err(|source| GenerateError::Write {
+                path: with_suffix(out, ".br"),
+                source,
+            })
+        };
+
+        // a worker waiting in join steals other prefixes, which would block on the memory its
+        // own reservation holds with --max-memory
+        if self.memory.is_some() {
+            return encode_gzip().and_then(|()| encode_brotli());
+        }
+        let (gzip, brotli) = rayon::join(encode_gzip, encode_brotli);
+        gzip.and(brotli)
+    }
+
+    /// Writes the `compressed` variant to `path`, unless it's no smaller than the JSON next to it
+    /// with --skip-larger-compressed. Returns the size written.
+    fn write_compressed(
+        &self,
+        path: PathBuf,
+        total: &AtomicU64,
+        serialized: &[u8],
+        compressed: &[u8],
+    ) -> Result<u64, GenerateError> {
+        if self.leaves_out(compressed, serialized) {
+            self.remove_stale(&path)?;
+            return Ok(0);
+        }
+
+        self.write(path, total, serialized, |out| out.write_all(compressed))
+    }
+
+    /// Whether the `compressed` variant is left out, as the JSON it was compressed from is
+    /// written as well and is no larger.
+    fn leaves_out(&self, compressed: &[u8], serialized: &[u8]) -> bool {
+        self.skip_larger_compressed && self.json && compressed.len() >= serialized.len()
+    }
+
+    /// Removes the variant at `path` left by an earlier run, which would be served in place of
+    /// the JSON otherwise.
+    fn remove_stale(&self, path: &Path) -> Result<(), GenerateError> {
+        let local = self.archive.is_none() && self.remote.is_none();
+        if !local || self.on_existing != OnExisting::Overwrite {
+            return Ok(());
+        }
+
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(source) => {
+                return Err(GenerateError::Write {
+                    path: path.into(),
+                    source,
+                })
+            }
+        }
+        if let Some(manifest) = &self.manifest {
+            manifest.remove(path);
+        }
+        Ok(())
+    }
+
+    /// Compresses `serialized` into the `gzip`, `brotli` and `dcb` buffers and writes every enabled
+    /// output of a prefix through io_uring in a single submission, returning their size.
+    fn write_batched(
+        &self,
+        out: &Path,
+        serialized: &[u8],
+        gzip: &mut Vec<u8>,
+        brotli: &mut Vec<u8>,
+        dcb: &mut Vec<u8>,
+    ) -> Result<FormatBytes, GenerateError> {
+        let write_err = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source: std::io::Error| GenerateError::Write { path, source }
+        };
+
+        let mut bytes = FormatBytes::default();
+        let mut outputs = Vec::with_capacity(3);
+        if self.json {
+            bytes.json = serialized.len() as u64;
+            outputs.push((out.to_path_buf(), &self.totals.json, serialized));
+        }
+
+        self.encode(out, serialized, gzip, brotli)?;
+        if self.gzip {
+            let path = with_suffix(out, ".gz");
+            if self.leaves_out(gzip, serialized) {
+                self.remove_stale(&path)?;
+            } else {
+                bytes.gzip = gzip.len() as u64;
+                outputs.push((path, &self.totals.gzip, gzip.as_slice()));
+            }
+        }
+
+        if self.brotli {
+            let path = with_suffix(out, ".br");
+            if self.leaves_out(brotli, serialized) {
+                self.remove_stale(&path)?;
+            } else {
+                bytes.brotli = brotli.len() as u64;
+                outputs.push((path, &self.totals.brotli, brotli.as_slice()));
+            }
+        }
+
+        if let Some(dictionary) = &self.dictionary {
+            let path = with_suffix(out, ".dcb");
+            dcb.clear();
+            dictionary
+                .compress(dcb, serialized, &self.brotli_params)
+                .map_err(write_err(&path))?;
+            if self.leaves_out(dcb, serialized) {
+                self.remove_stale(&path)?;
+            } else {
+                bytes.dcb = dcb.len() as u64;
+                outputs.push((path, &self.totals.dcb, dcb.as_slice()));
+            }
+        }
+
+        // written under temporary names and renamed into place, like an `AtomicFile`
+        let mut files = Vec::with_capacity(outputs.len());
+        for (path, ..) in &outputs {
+            let tmp = tmp_path(path, self.tmp_dir);
+            let file = File::create(&tmp).map_err(write_err(path))?;
+            files.push((file, TmpFile(Some(tmp))));
+        }
+
+        let writes: Vec<_> = files
+            .iter()
+            .zip(&outputs)
+            .map(|((file, _), (_, _, data))| (file, *data))
+            .collect();
+        uring::write_all(&writes).map_err(write_err(out))?;
+
+        if self.self_check {
+            for ((_, tmp), (path, ..)) in files.iter().zip(&outputs) {
+                let written = std::fs::read(tmp.path()).map_err(write_err(path))?;
+                if !self.decodes_to(path, &written, serialized) {
+                    return Err(GenerateError::SelfCheck(path.clone()));
+                }
+            }
+        }
+
+        for ((file, mut tmp), (path, total, data)) in files.into_iter().zip(&outputs) {
+            drop(file);
+            move_into_place(tmp.path(), path).map_err(write_err(path))?;
+            tmp.0 = None;
+            if let Some(manifest) = &self.manifest {
+                let entry = ManifestEntry {
+                    size: data.len() as u64,
+                    sha256: manifest::sha256(data),
+                };
+                manifest.insert(path, entry);
+            }
+            total.fetch_add(data.len() as u64, Ordering::SeqCst);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Serializes every line straight into all enabled outputs while parsing, without holding
+    /// the parsed or serialized hash file in memory. Returns the amount of entries written and
+    /// the size of the outputs.
+    fn process_streaming(
+        &self,
+        path: &Path,
+        prefix: &str,
+        content: &[u8],
+        out: &Path,
+    ) -> Result<(u64, FormatBytes), GenerateError> {
+        let hash = self.manifest.is_some();
+        let write_err = |source| GenerateError::Write {
+            path: out.into(),
+            source,
+        };
+        let create = |enabled: bool, path: PathBuf| {
+            enabled
+                .then(|| AtomicFile::create_in(path, self.tmp_dir, hash))
+                .transpose()
+                .map_err(write_err)
+        };
+
+        let mut json = create(self.json, out.into())?;
+        let mut gzip = create(self.gzip, with_suffix(out, ".gz"))?
+            .map(|file| compress::gzip_encoder(file, self.gzip_level));
+        let mut brotli = create(self.brotli, with_suffix(out, ".br"))?
+            .map(|file| compress::brotli_encoder(file, &self.brotli_params));
+
+        let mut sinks: Vec<&mut dyn Write> = Vec::with_capacity(3);
+        sinks.extend(json.as_mut().map(|w| w as &mut dyn Write));
+        sinks.extend(gzip.as_mut().map(|w| w as &mut dyn Write));
+        sinks.extend(brotli.as_mut().map(|w| w as &mut dyn Write));
+        let mut tee = Tee(sinks);
+
+        let mut entries = 0;
+        tee.write_all(b"[").map_err(write_err)?;
+        // duplicates are only caught next to each other without the whole prefix, so --strict
+        // needs the lines ordered by hash here
+        let options = ParseOptions {
+            hash_order: true,
+            ..self.parse
+        };
+        let skipped = parse_lines(content, options, |suffix, count| {
+            let mut password = Password {
+                hash: HashBuf::new(prefix, suffix),
+                count,
+            };
+            if self
+                .filter
+                .is_some_and(|filter| !filter.keeps(&password.hash))
+            {
+                return Ok(());
+            }
+
+            if entries > 0 {
+                tee.write_all(b",")?;
+            }
+            entries += 1;
+
+            if self.case == Case::Lower {
+                password.hash.make_ascii_lowercase();
+            }
+            serde_json::to_writer(&mut tee, &self.fields.entry(&password))
+                .map_err(std::io::Error::from)
+        })
+        .map_err(|err| match err {
+            ParseError::Line(line, kind) => GenerateError::Parse {
+                path: path.into(),
+                line,
+                kind,
+            },
+            ParseError::Sink(source) => write_err(source),
+        })?;
+        self.report_skipped(path, skipped);
+        tee.write_all(b"]").map_err(write_err)?;
+        drop(tee);
+
+        let manifest = self.manifest.as_ref();
+        let mut bytes = FormatBytes::default();
+        if let Some(file) = json {
+            bytes.json = file.commit(manifest).map_err(write_err)?;
+            self.totals.json.fetch_add(bytes.json, Ordering::SeqCst);
+        }
+        if let Some(enc) = gzip {
+            let size = enc.finish().and_then(|file| file.commit(manifest));
+            bytes.gzip = size.map_err(write_err)?;
+            self.totals.gzip.fetch_add(bytes.gzip, Ordering::SeqCst);
+        }
+        if let Some(enc) = brotli {
+            bytes.brotli = enc.into_inner().commit(manifest).map_err(write_err)?;
+            self.totals.brotli.fetch_add(bytes.brotli, Ordering::SeqCst);
+        }
+
+        Ok((entries, bytes))
+    }
+
+    /// Like [`Self::process`] but retries retryable errors up to `retries` times, backing off
+    /// a little longer after each attempt.
+    pub fn process_with_retries(
+        &self,
+        path: &Path,
+        buffers: &mut Buffers,
+        retries: u32,
+    ) -> Result<(), GenerateError> {
+        let mut attempt = 0;
+        loop {
+            match self.process(path, buffers) {
+                Err(err) if attempt < retries && err.is_retryable() => {
+                    attempt += 1;
+                    std::thread::sleep(Duration::from_millis(100 << attempt.min(6)));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Path of the .json output of `prefix`, compressed outputs get an extra suffix.
+    fn out_path(&self, dist: &Path, prefix: &str) -> Result<PathBuf, GenerateError> {
+        let out = json_path(dist, self.depth, self.template, prefix);
+        let local = self.archive.is_none() && self.remote.is_none();
+        if let (Some(parent), true) = (out.parent(), local) {
+            self.directories
+                .ensure(parent)
+                .map_err(|source| GenerateError::Write {
+                    path: parent.into(),
+                    source,
+                })?;
+        }
+
+        Ok(out)
+    }
+
+    /// The size of the output at `path` if it already exists and is kept as it is, in which case
+    /// it's counted as if it was written.
+    fn keep_existing(&self, path: &Path, total: &AtomicU64) -> Result<Option<u64>, GenerateError> {
+        if self.on_existing == OnExisting::Overwrite || !path.exists() {
+            return Ok(None);
+        }
+        if self.on_existing == OnExisting::Error {
+            return Err(GenerateError::Exists(path.into()));
+        }
+
+        let data = std::fs::read(path).map_err(|source| GenerateError::Read {
+            path: path.into(),
+            source,
+        })?;
+        let size = data.len() as u64;
+        if let Some(manifest) = &self.manifest {
+            let sha256 = manifest::sha256(&data);
+            manifest.insert(path, ManifestEntry { size, sha256 });
+        }
+        total.fetch_add(size, Ordering::SeqCst);
+
+        Ok(Some(size))
+    }
+
+    fn write(
+        &self,
+        path: PathBuf,
+        total: &AtomicU64,
+        serialized: &[u8],
+        write: impl FnOnce(&mut Output) -> std::io::Result<()>,
+    ) -> Result<u64, GenerateError> {
+        if let Some(size) = self.keep_existing(&path, total)? {
+            return Ok(size);
+        }
+
+        // relative to the tree the file is served from, which a tier-N tree is on its own
+        let mut relative = path.strip_prefix(self.dist).unwrap_or(&path);
+        if in_tier(self.dist, &path) {
+            let mut components = relative.components();
+            components.next();
+            relative = components.as_path();
+        }
+        let written = Output::create(
+            path.clone(),
+            relative.to_owned(),
+            self.archive,
+            self.remote,
+            self.encryption.as_ref(),
+            self.tmp_dir,
+            self.manifest.is_some(),
+        )
+        .and_then(|mut out| {
+            write(&mut out)?;
+            if self.self_check && !self.decodes_to(&path, &out.read_back()?, serialized) {
+                return Ok(None);
+            }
+            let size = out.commit(self.manifest.as_ref())?;
+            let written = self.manifest.as_ref().and_then(|m| m.get(&path));
+            if let (Some(previous), Some(entry)) = (self.previous, written) {
+                previous.link(&path, &entry)?;
+            }
+            Ok(Some(size))
+        });
+
+        match written {
+            Ok(Some(size)) => {
+                total.fetch_add(size, Ordering::SeqCst);
+                Ok(size)
+            }
+            Ok(None) => Err(GenerateError::SelfCheck(path)),
+            Err(source) => Err(GenerateError::Write { path, source }),
+        }
+    }
+
+    /// If the output `written` to `path` decodes back to exactly `serialized`, based on its
+    /// extension.
+    fn decodes_to(&self, path: &Path, written: &[u8], serialized: &[u8]) -> bool {
+        fn read_to_vec(mut reader: impl Read) -> std::io::Result<Vec<u8>> {
+            let mut decoded = Vec::new();
+            reader.read_to_end(&mut decoded)?;
+            Ok(decoded)
+        }
+
+        let decoded = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => read_to_vec(compress::gzip_decoder(written)),
+            Some("br") => read_to_vec(compress::brotli_decoder(written)),
+            Some("dcb") => match &self.dictionary {
+                Some(dictionary) => dictionary.decompress(written),
+                None => return false,
+            },
+            _ => return written == serialized,
+        };
+        decoded.is_ok_and(|decoded| decoded == serialized)
+    }
+}
+
+/// Path of the .json output of `prefix` within `dist`, rendered from `template` if there is one.
+pub fn json_path(
+    dist: &Path,
+    depth: usize,
+    template: Option<&PathTemplate>,
+    prefix: &str,
+) -> PathBuf {
+    match template {
+        Some(template) => dist.join(template.render(prefix)),
+        None => dist.join(format!("{}.json", prefix_path(prefix, depth))),
+    }
+}
+
+/// The path of the .json file of a prefix relative to the output directory, with `chars`
+/// standing in for its characters, e.g. the capture groups of a web server's rewrite rule.
+pub fn json_path_with(
+    depth: usize,
+    template: Option<&PathTemplate>,
+    chars: [&str; PREFIX_LEN],
+) -> String {
+    if let Some(template) = template {
+        return template.render_with(&chars.concat(), chars);
+    }
+
+    // like `prefix_path`, but with the characters standing in
+    let ends = directory_ends(depth);
+    let mut path = String::new();
+    for (i, c) in chars.into_iter().enumerate() {
+        if ends.contains(&i) {
+            path.push('/');
+        }
+        path.push_str(c);
+    }
+    path.push_str(".json");
+    path
+}
+
+/// Serializes `passwords` into `serialized` with the field names of `fields`.
+fn to_json(
+    path: &Path,
+    fields: &Fields,
+    passwords: &[Password],
+    serialized: &mut Vec<u8>,
+) -> Result<(), GenerateError> {
+    serialized.clear();
+    serde_json::to_writer(&mut *serialized, &fields.entries(passwords)).map_err(|source| {
+        GenerateError::Serialize {
+            path: path.into(),
+            source,
+        }
+    })
+}
+
+/// How the lines of a hash file are parsed.
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// Skip hashes with a count below this
+    pub min_count: u64,
+
+    /// Validate that every suffix is uppercase hex of the length of `hash_type` and unique, with
+    /// the lines ordered either by ascending hash or by descending count
+    pub strict: bool,
+
+    /// The hashes of the hash files, only checked with `strict`
+    pub hash_type: HashType,
+
+    /// Only accept lines ordered by hash with `strict`, as duplicates are then only caught next
+    /// to each other
+    pub hash_order: bool,
+
+    /// Skip blank and malformed lines instead of failing on them, reporting them in [`Skipped`]
+    pub lenient: bool,
+
+    /// Let suffixes appearing more than once through `strict`, as the caller merges them
+    pub merge_duplicates: bool,
+
+    /// What to do with counts that don't fit in 64 bits
+    pub count_overflow: CountOverflow,
+}
+
+/// The hashes of the hash files, which fixes the length of their suffixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashType {
+    /// SHA-1, like the default Pwned Passwords downloads
+    Sha1,
+    /// NTLM, like the Pwned Passwords downloads with `?mode=ntlm`
+    Ntlm,
+}
+
+impl HashType {
+    /// The name in `dataset.json` and the `mode` of the range API.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashType::Sha1 => "sha1",
+            HashType::Ntlm => "ntlm",
+        }
+    }
+
+    /// Length of the suffixes within a hash file, the first 5 characters are its file name.
+    pub fn suffix_len(self) -> usize {
+        match self {
+            HashType::Sha1 => 35,
+            HashType::Ntlm => 27,
+        }
+    }
+}
+
+/// What happens to a count that doesn't fit in 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CountOverflow {
+    /// Fail on the line like on any other invalid count
+    Error,
+    /// Clamp it to the largest 64-bit count
+    Saturate,
+}
+
+/// The malformed lines a lenient parse skipped.
+#[derive(Debug, Default)]
+pub struct Skipped {
+    pub lines: usize,
+    /// The 1-based line number of the first one and what is wrong with it
+    pub first: Option<(usize, LineError)>,
+}
+
+/// Length of a SHA-1 suffix within a hash file, the longest of the [`HashType`]s.
+const SUFFIX_LEN: usize = 35;
+
+/// Byte order mark some Windows tools start UTF-8 files with.
+pub const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Parses the `SUFFIX:COUNT` lines of the hash file of `prefix` into `passwords`, replacing the
+/// ones it contains. Errors contain the 1-based line number.
+pub fn parse_hash_file(
+    prefix: &str,
+    content: &[u8],
+    options: ParseOptions,
+    passwords: &mut Vec<Password>,
+) -> Result<Skipped, (usize, LineError)> {
+    passwords.clear();
+    let skipped = parse_lines(content, options, |suffix, count| {
+        passwords.push(Password {
+            hash: HashBuf::new(prefix, suffix),
+            count,
+        });
+        Ok::<_, Infallible>(())
+    })
+    .map_err(|err| match err {
+        ParseError::Line(line, kind) => (line, kind),
+        ParseError::Sink(never) => match never {},
+    })?;
+
+    Ok(skipped)
+}
+
+/// The 1-based line number of the second line with `suffix` in `content`, 0 if there's none.
+fn duplicate_line(content: &[u8], suffix: &str) -> usize {
+    lines(content)
+        .enumerate()
+        .filter(|(_, line)| {
+            line.trim_ascii_start()
+                .strip_prefix(suffix.as_bytes())
+                .is_some_and(|rest| rest.trim_ascii_start().starts_with(b":"))
+        })
+        .nth(1)
+        .map_or(0, |(i, _)| i + 1)
+}
+
+/// Failure of [`parse_lines`], either a malformed line or an error from its sink.
+#[derive(Debug)]
+pub enum ParseError<E> {
+    /// A 1-based line number and what is wrong with it
+    Line(usize, LineError),
+    Sink(E),
+}
+
+/// Parses the `SUFFIX:COUNT` lines of a hash file, passing the suffix and count of every line
+/// that isn't filtered out to `sink` as soon as it's parsed.
+pub fn parse_lines<'a, E>(
+    content: &'a [u8],
+    options: ParseOptions,
+    mut sink: impl FnMut(&'a str, u64) -> Result<(), E>,
+) -> Result<Skipped, ParseError<E>> {
+    // hash files are either ordered by hash or by descending count, whichever still holds
+    let mut previous = ("", u64::MAX);
+    let (mut by_hash, mut by_count) = (true, true);
+    let mut skipped = Skipped::default();
+
+    for (i, line) in lines(content).enumerate() {
+        let parsed = parse_line(line, options).and_then(|parsed| {
+            let Some((h, count)) = parsed else {
+                return Ok(None);
+            };
+            // hashes are held inline, no SHA-1 suffix is longer
+            if h.len() > SUFFIX_LEN {
+                return Err(LineError::SuffixTooLong(h.into()));
+            }
+            if options.strict {
+                validate_suffix(h, options.hash_type)?;
+                if h == previous.0 && !options.merge_duplicates {
+                    return Err(LineError::Duplicate(h.into()));
+                }
+                let ordered = (
+                    // equal ones only get here when they're merged
+                    by_hash && h >= previous.0,
+                    by_count && count <= previous.1 && !options.hash_order,
+                );
+                if ordered == (false, false) {
+                    return Err(LineError::OutOfOrder(h.into()));
+                }
+                (by_hash, by_count) = ordered;
+                previous = (h, count);
+            }
+            Ok(Some((h, count)))
+        });
+
+        let (h, count) = match parsed {
+            Ok(Some(line)) => line,
+            Ok(None) => continue,
+            Err(kind) if options.lenient => {
+                skipped.lines += 1;
+                skipped.first.get_or_insert((i + 1, kind));
+                continue;
+            }
+            Err(kind) => return Err(ParseError::Line(i + 1, kind)),
+        };
+
+        if count < options.min_count {
+            continue;
+        }
+
+        sink(h, count).map_err(ParseError::Sink)?;
+    }
+
+    Ok(skipped)
+}
+
+/// Splits a line into its suffix and count, or none if it's blank and `lenient` is set, which
+/// also ignores whitespace around them.
+fn parse_line(line: &[u8], options: ParseOptions) -> Result<Option<(&str, u64)>, LineError> {
+    let lenient = options.lenient;
+    let mut line = std::str::from_utf8(line).map_err(|_| LineError::Utf8)?;
+    if lenient {
+        line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+    }
+    // `:` is ascii, so both sides of it are still valid utf-8
+    let colon = memchr::memchr(b':', line.as_bytes()).ok_or(LineError::MissingSeparator)?;
+    let (h, c) = (&line[..colon], &line[colon + 1..]);
+    let (h, c) = if lenient {
+        (h.trim(), c.trim())
+    } else {
+        (h, c)
+    };
+    if let Some(count) = parse_digits(c.as_bytes()) {
+        return Ok(Some((h, count)));
+    }
+    // the rare counts with a sign, or too long or invalid ones
+    let count = match c.parse::<u64>() {
+        Ok(count) => count,
+        Err(err) if *err.kind() == IntErrorKind::PosOverflow => match options.count_overflow {
+            CountOverflow::Error => return Err(LineError::CountOverflow(c.into())),
+            CountOverflow::Saturate => u64::MAX,
+        },
+        Err(_) => return Err(LineError::InvalidCount(c.into())),
+    };
+    Ok(Some((h, count)))
+}
+
+/// Parses a count of 1 to 19 ascii digits, which always fits in 64 bits, without the checks
+/// of [`str::parse`]. Anything else is left to it.
+fn parse_digits(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 19 {
+        return None;
+    }
+    digits.iter().try_fold(0_u64, |count, &b| {
+        let digit = b.wrapping_sub(b'0');
+        (digit < 10).then(|| count * 10 + u64::from(digit))
+    })
+}
+
+/// Splits `content` into lines without allocating, like [`BufRead::lines`] this strips `\r\n`
+/// and doesn't yield an empty last line. A leading UTF-8 byte order mark is skipped.
+pub fn lines(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let content = content.strip_prefix(UTF8_BOM).unwrap_or(content);
+    let content = content.strip_suffix(b"\n").unwrap_or(content);
+    // memchr scans for the newlines many bytes at a time
+    let mut rest = (!content.is_empty()).then_some(content);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        let line = match memchr::memchr(b'\n', current) {
+            Some(end) => {
+                rest = Some(&current[end + 1..]);
+                &current[..end]
+            }
+            None => {
+                rest = None;
+                current
+            }
+        };
+        Some(line.strip_suffix(b"\r").unwrap_or(line))
+    })
+}
+
+/// Whether every byte is an uppercase hex character, looked up without branching.
+static UPPER_HEX: [bool; 256] = {
+    let mut table = [false; 256];
+    let mut b = 0;
+    while b < 256 {
+        table[b] = matches!(b as u8, b'0'..=b'9' | b'A'..=b'F');
+        b += 1;
+    }
+    table
+};
+
+/// Checks that `suffix` is uppercase hex of the length of the suffixes of `hash_type`.
+fn validate_suffix(suffix: &str, hash_type: HashType) -> Result<(), LineError> {
+    // folded rather than short-circuited, so the loop over the fixed length can be vectorized
+    let is_hex = suffix
+        .bytes()
+        .fold(true, |valid, b| valid & UPPER_HEX[usize::from(b)]);
+    if suffix.len() != hash_type.suffix_len() || !is_hex {
+        return Err(LineError::InvalidSuffix(
+            suffix.into(),
+            hash_type.suffix_len(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Unique part of the names of the temporary files in a --tmp-dir, shared by every output.
+static TMP_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Where the file at `path` is written before it's moved into place, next to it or within
+/// `tmp_dir` if there is one.
+pub fn tmp_path(path: &Path, tmp_dir: Option<&Path>) -> PathBuf {
+    match tmp_dir {
+        Some(tmp_dir) => {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let id = TMP_ID.fetch_add(1, Ordering::Relaxed);
+            tmp_dir.join(format!("{}-{id}-{name}.tmp", std::process::id()))
+        }
+        None => with_suffix(path, ".tmp"),
+    }
+}
+
+/// Renames `tmp` to `path`. If `tmp` is in a --tmp-dir on another filesystem, it's copied next to
+/// `path` first, so the file still appears atomically.
+pub fn move_into_place(tmp: &Path, path: &Path) -> std::io::Result<()> {
+    match std::fs::rename(tmp, path) {
+        Err(err) if crosses_devices(&err) => {}
+        result => return result,
+    }
+
+    let sibling = with_suffix(path, ".tmp");
+    std::fs::copy(tmp, &sibling)?;
+    std::fs::remove_file(tmp)?;
+    std::fs::rename(&sibling, path)
+}
+
+/// Whether a rename failed because it would move the file to another filesystem.
+#[cfg(unix)]
+fn crosses_devices(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(not(unix))]
+fn crosses_devices(err: &std::io::Error) -> bool {
+    // ERROR_NOT_SAME_DEVICE
+    cfg!(windows) && err.raw_os_error() == Some(17)
+}
+
+/// A file written under a temporary name and renamed into place by [`AtomicFile::commit`], so
+/// a crashed or killed run never leaves a truncated file behind.
+pub struct AtomicFile {
+    path: PathBuf,
+    writer: HashWriter<BufWriter<File>>,
+    /// Last, so the file is closed before it's removed
+    tmp: TmpFile,
+}
+
+/// Removes the temporary file of an [`AtomicFile`] that's dropped without being committed, e.g.
+/// after a failed write or self-check, so failed prefixes don't leave them behind in --tmp-dir.
+struct TmpFile(Option<PathBuf>);
+
+impl TmpFile {
+    fn path(&self) -> &Path {
+        self.0
+            .as_deref()
+            .expect("the temporary file was already moved into place")
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        if let Some(tmp) = self.0.take() {
+            let _ = std::fs::remove_file(tmp);
+        }
+    }
+}
+
+impl AtomicFile {
+    /// Creates the temporary file for `path`, hashing everything written to it if `hash` is set.
+    pub fn create(path: PathBuf, hash: bool) -> std::io::Result<Self> {
+        Self::create_in(path, None, hash)
+    }
+
+    /// Like [`AtomicFile::create`], but creates the temporary file in `tmp_dir` if there is one.
+    pub fn create_in(path: PathBuf, tmp_dir: Option<&Path>, hash: bool) -> std::io::Result<Self> {
+        let tmp = tmp_path(&path, tmp_dir);
+        let writer = HashWriter::new(BufWriter::new(File::create(&tmp)?), hash);
+        Ok(Self {
+            path,
+            writer,
+            tmp: TmpFile(Some(tmp)),
+        })
+    }
+
+    /// Renames the file into place, adding it to the `manifest` if there is one, and returns
+    /// its size.
+    pub fn commit(mut self, manifest: Option<&ManifestWriter>) -> std::io::Result<u64> {
+        let (buf, sha256) = self.writer.finish();
+        let file = buf
+            .into_inner()
+            .map_err(std::io::IntoInnerError::into_error)?;
+        let size = file.metadata()?.len();
+        drop(file);
+        let tmp = self.tmp.0.take().unwrap();
+        if let Err(err) = move_into_place(&tmp, &self.path) {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(err);
+        }
+
+        if let (Some(manifest), Some(sha256)) = (manifest, sha256) {
+            manifest.insert(&self.path, ManifestEntry { size, sha256 });
+        }
+
+        Ok(size)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A generated file, written into the output directory, appended to the archive, or uploaded.
+pub enum Output<'a> {
+    File(AtomicFile),
+    Archived {
+        archive: &'a Archive,
+        path: PathBuf,
+        data: Vec<u8>,
+    },
+    Uploaded {
+        remote: &'a Remote,
+        path: PathBuf,
+        data: Vec<u8>,
+    },
+    /// Buffers the plaintext, encrypted into the output it wraps when committed
+    Encrypted {
+        output: Box<Output<'a>>,
+        encryption: &'a Encryption,
+        /// Path within the output directory, authenticated along with the plaintext
+        relative: PathBuf,
+        plain: Vec<u8>,
+    },
+}
+
+impl<'a> Output<'a> {
+    /// Starts the file at `path`, within the `archive` or on the `remote` if there is one, hashing
+    /// everything written to it if `hash` is set and encrypting it if there's an `encryption`,
+    /// bound to its `relative` path. Files are written in `tmp_dir` before they're moved into
+    /// place if there is one.
+    pub fn create(
+        path: PathBuf,
+        relative: PathBuf,
+        archive: Option<&'a Archive>,
+        remote: Option<&'a Remote>,
+        encryption: Option<&'a Encryption>,
+        tmp_dir: Option<&Path>,
+        hash: bool,
+    ) -> std::io::Result<Self> {
+        let output = match (archive, remote) {
+            (Some(archive), _) => Self::Archived {
+                archive,
+                path,
+                data: Vec::new(),
+            },
+            (None, Some(remote)) => Self::Uploaded {
+                remote,
+                path,
+                data: Vec::new(),
+            },
+            (None, None) => AtomicFile::create_in(path, tmp_dir, hash).map(Self::File)?,
+        };
+
+        Ok(match encryption {
+            Some(encryption) => Self::Encrypted {
+                output: Box::new(output),
+                encryption,
+                relative,
+                plain: Vec::new(),
+            },
+            None => output,
+        })
+    }
+
+    /// Everything written so far, read back from the temporary file unless it's buffered. The
+    /// plaintext of encrypted files.
+    pub fn read_back(&mut self) -> std::io::Result<Cow<'_, [u8]>> {
+        match self {
+            Self::File(file) => {
+                file.flush()?;
+                std::fs::read(file.tmp.path()).map(Cow::Owned)
+            }
+            Self::Archived { data, .. }
+            | Self::Uploaded { data, .. }
+            | Self::Encrypted { plain: data, .. } => Ok(Cow::Borrowed(data)),
+        }
+    }
+
+    /// Moves the file into place, adding it to the `manifest` if there is one, and returns its
+    /// size.
+    pub fn commit(self, manifest: Option<&ManifestWriter>) -> std::io::Result<u64> {
+        match self {
+            Self::File(file) => file.commit(manifest),
+            Self::Archived {
+                archive,
+                path,
+                data,
+            } => {
+                archive.append(&path, &data)?;
+                Ok(buffered_entry(manifest, &path, &data))
+            }
+            Self::Uploaded { remote, path, data } => {
+                let size = buffered_entry(manifest, &path, &data);
+                remote.put(&path, data)?;
+                Ok(size)
+            }
+            Self::Encrypted {
+                mut output,
+                encryption,
+                relative,
+                plain,
+            } => {
+                encryption.encrypt(&mut *output, &plain, &relative)?;
+                output.commit(manifest)
+            }
+        }
+    }
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::File(file) => file.write(buf),
+            Self::Archived { data, .. }
+            | Self::Uploaded { data, .. }
+            | Self::Encrypted { plain: data, .. } => data.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::File(file) => file.flush(),
+            Self::Archived { .. } | Self::Uploaded { .. } | Self::Encrypted { .. } => Ok(()),
+        }
+    }
+}
+
+/// Adds a file that was buffered in memory to the `manifest` if there is one, and returns its size.
+fn buffered_entry(manifest: Option<&ManifestWriter>, path: &Path, data: &[u8]) -> u64 {
+    let size = data.len() as u64;
+    if let Some(manifest) = manifest {
+        let sha256 = manifest::sha256(data);
+        manifest.insert(path, ManifestEntry { size, sha256 });
+    }
+    size
+}
+
+/// Writes `path` as an [`AtomicFile`] and returns its size.
+///
+/// The written file is added to the `manifest` if there is one.
+pub fn write_atomic(
+    path: &Path,
+    manifest: Option<&ManifestWriter>,
+    write: impl FnOnce(&mut AtomicFile) -> std::io::Result<()>,
+) -> std::io::Result<u64> {
+    let mut file = AtomicFile::create(path.into(), manifest.is_some())?;
+    write(&mut file)?;
+    file.commit(manifest)
+}
+
+/// Counts the bytes written to it and discards them.
+struct Counter(u64);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes everything to all of its writers.
+struct Tee<'a>(Vec<&'a mut dyn Write>);
+
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for writer in &mut self.0 {
+            writer.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.iter_mut().try_for_each(|writer| writer.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(hash_type: HashType) -> ParseOptions {
+        ParseOptions {
+            min_count: 0,
+            strict: true,
+            hash_type,
+            hash_order: false,
+            lenient: false,
+            merge_duplicates: false,
+            count_overflow: CountOverflow::Error,
+        }
+    }
+
+    fn parse(
+        content: &[u8],
+        options: ParseOptions,
+    ) -> Result<Vec<(&str, u64)>, (usize, LineError)> {
+        let mut parsed = Vec::new();
+        parse_lines(content, options, |suffix, count| {
+            parsed.push((suffix, count));
+            Ok::<_, Infallible>(())
+        })
+        .map_err(|err| match err {
+            ParseError::Line(line, kind) => (line, kind),
+            ParseError::Sink(never) => match never {},
+        })?;
+        Ok(parsed)
+    }
+
+    #[test]
+    fn suffix_length_of_hash_type() {
+        let ntlm = b"0000000000000000000000000AA:3\r\n0000000000000000000000000BB:2\r\n";
+        let parsed = parse(ntlm, options(HashType::Ntlm)).unwrap();
+        assert_eq!(
+            parsed,
+            [
+                ("0000000000000000000000000AA", 3),
+                ("0000000000000000000000000BB", 2)
+            ]
+        );
+        assert!(matches!(
+            parse(ntlm, options(HashType::Sha1)),
+            Err((1, LineError::InvalidSuffix(_, 35)))
+        ));
+    }
+
+    #[test]
+    fn hash_order_rejects_count_order() {
+        let by_count =
+            b"00000000000000000000000000000000BBB:5\n00000000000000000000000000000000AAA:2\n";
+        assert_eq!(parse(by_count, options(HashType::Sha1)).unwrap().len(), 2);
+
+        let options = ParseOptions {
+            hash_order: true,
+            ..options(HashType::Sha1)
+        };
+        assert!(matches!(
+            parse(by_count, options),
+            Err((2, LineError::OutOfOrder(_)))
+        ));
+    }
+
+    const A: &str = "0018A45C4D1DEF81644B54AB7F969B88D65";
+    const B: &str = "00D4F6E8FA6EECAD2A3AA415EEC418D38EC";
+
+    #[test]
+    fn strict_checks() {
+        let strict = options(HashType::Sha1);
+        let by_hash = format!("{A}:2\n{B}:3\n");
+        assert_eq!(parse(by_hash.as_bytes(), strict).unwrap(), [(A, 2), (B, 3)]);
+        let by_count = format!("{B}:3\n{A}:2\n");
+        assert_eq!(
+            parse(by_count.as_bytes(), strict).unwrap(),
+            [(B, 3), (A, 2)]
+        );
+
+        let unordered = format!("{B}:2\n{A}:3\n");
+        assert!(matches!(
+            parse(unordered.as_bytes(), strict),
+            Err((2, LineError::OutOfOrder(_)))
+        ));
+        let duplicate = format!("{A}:2\n{A}:2\n");
+        assert!(matches!(
+            parse(duplicate.as_bytes(), strict),
+            Err((2, LineError::Duplicate(_)))
+        ));
+        let lowercase = A.to_ascii_lowercase() + ":2\n";
+        assert!(matches!(
+            parse(lowercase.as_bytes(), strict),
+            Err((1, LineError::InvalidSuffix(_, 35)))
+        ));
+        assert!(matches!(
+            parse(format!("{A}\n").as_bytes(), strict),
+            Err((1, LineError::MissingSeparator))
+        ));
+
+        let loose = ParseOptions {
+            strict: false,
+            ..strict
+        };
+        assert_eq!(parse(unordered.as_bytes(), loose).unwrap().len(), 2);
+        assert_eq!(parse(lowercase.as_bytes(), loose).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn lenient_skips_malformed_lines() {
+        let options = ParseOptions {
+            lenient: true,
+            ..options(HashType::Sha1)
+        };
+        let content = format!("\n {A} : 2 \n\n{A}:many\n{B}:3\n");
+        let mut parsed = Vec::new();
+        let skipped = parse_lines(content.as_bytes(), options, |suffix, count| {
+            parsed.push((suffix, count));
+            Ok::<_, Infallible>(())
+        })
+        .unwrap();
+
+        assert_eq!(parsed, [(A, 2), (B, 3)]);
+        assert_eq!(skipped.lines, 1);
+        assert!(matches!(
+            skipped.first,
+            Some((4, LineError::InvalidCount(count))) if count == "many"
+        ));
+    }
+
+    #[test]
+    fn merge_duplicates_lets_them_through() {
+        let options = ParseOptions {
+            merge_duplicates: true,
+            ..options(HashType::Sha1)
+        };
+        let content = format!("{A}:2\n{A}:3\n{B}:1\n");
+        assert_eq!(
+            parse(content.as_bytes(), options).unwrap(),
+            [(A, 2), (A, 3), (B, 1)]
+        );
+        let decreasing = format!("{A}:3\n{A}:2\n{B}:5\n");
+        assert_eq!(parse(decreasing.as_bytes(), options).unwrap().len(), 3);
+
+        let mut passwords: Vec<_> = parse(content.as_bytes(), options)
+            .unwrap()
+            .into_iter()
+            .map(|(suffix, count)| Password {
+                hash: HashBuf::new("00000", suffix),
+                count,
+            })
+            .collect();
+        assert_eq!(merge_duplicates(&mut passwords), 1);
+        assert_eq!(passwords.len(), 2);
+        assert_eq!(passwords[0].count, 5);
+    }
+
+    #[test]
+    fn count_overflow() {
+        let content = format!("{A}:18446744073709551616\n");
+        assert!(matches!(
+            parse(content.as_bytes(), options(HashType::Sha1)),
+            Err((1, LineError::CountOverflow(_)))
+        ));
+
+        let saturate = ParseOptions {
+            count_overflow: CountOverflow::Saturate,
+            ..options(HashType::Sha1)
+        };
+        assert_eq!(
+            parse(content.as_bytes(), saturate).unwrap(),
+            [(A, u64::MAX)]
+        );
+    }
+}
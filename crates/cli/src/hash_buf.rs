@@ -0,0 +1,160 @@
+//! Hashes stored inline in every [`Password`](crate::Password) rather than on the heap, as a run
+//! parses about a thousand of them for each of the million prefixes.
+
+use std::{cmp::Ordering, fmt, ops::Deref};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Longest hash held, a hex SHA-1.
+pub const CAPACITY: usize = 40;
+
+/// A hex hash of up to [`CAPACITY`] characters.
+#[derive(Clone, Copy)]
+pub struct HashBuf {
+    len: u8,
+    bytes: [u8; CAPACITY],
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("hash `{0}` is longer than {CAPACITY} characters")]
+pub struct TooLong(String);
+
+impl HashBuf {
+    /// The hash `prefix` followed by `suffix`, whose length parsing limits so that both fit.
+    ///
+    /// # Panics
+    ///
+    /// If they're longer than [`CAPACITY`] together.
+    pub fn new(prefix: &str, suffix: &str) -> Self {
+        let len = prefix.len() + suffix.len();
+        let mut bytes = [0; CAPACITY];
+        bytes[..prefix.len()].copy_from_slice(prefix.as_bytes());
+        bytes[prefix.len()..len].copy_from_slice(suffix.as_bytes());
+        Self {
+            len: len as u8,
+            bytes,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: only ever built from whole strs, and changing the ascii case keeps it utf-8
+        unsafe { std::str::from_utf8_unchecked(self.as_bytes()) }
+    }
+
+    pub fn make_ascii_lowercase(&mut self) {
+        self.bytes[..usize::from(self.len)].make_ascii_lowercase();
+    }
+
+    pub fn make_ascii_uppercase(&mut self) {
+        self.bytes[..usize::from(self.len)].make_ascii_uppercase();
+    }
+}
+
+impl TryFrom<&str> for HashBuf {
+    type Error = TooLong;
+
+    fn try_from(hash: &str) -> Result<Self, TooLong> {
+        if hash.len() > CAPACITY {
+            return Err(TooLong(hash.into()));
+        }
+        Ok(Self::new(hash, ""))
+    }
+}
+
+impl Deref for HashBuf {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for HashBuf {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for HashBuf {}
+
+impl PartialOrd for HashBuf {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HashBuf {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_bytes().cmp(other.as_bytes())
+    }
+}
+
+impl fmt::Display for HashBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for HashBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for HashBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for HashBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = HashBuf;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a hash of at most {CAPACITY} characters")
+            }
+
+            fn visit_str<E: de::Error>(self, hash: &str) -> Result<HashBuf, E> {
+                HashBuf::try_from(hash).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA1: &str = "ABCDE0018A45C4D1DEF81644B54AB7F969B88D65";
+
+    #[test]
+    fn holds_the_hash_inline() {
+        let mut hash = HashBuf::new(&SHA1[..5], &SHA1[5..]);
+        assert_eq!(hash.as_str(), SHA1);
+        assert_eq!(serde_json::to_string(&hash).unwrap(), format!("\"{SHA1}\""));
+        hash.make_ascii_lowercase();
+        assert_eq!(&*hash, SHA1.to_ascii_lowercase());
+
+        let ntlm = HashBuf::new("ABCDE", "0000000000000000000000000AA");
+        assert_eq!(ntlm.len(), 32);
+        assert!(ntlm < HashBuf::try_from(SHA1).unwrap());
+    }
+
+    #[test]
+    fn deserializes_up_to_capacity() {
+        let hash: HashBuf = serde_json::from_str(&format!("\"{SHA1}\"")).unwrap();
+        assert_eq!(hash, HashBuf::try_from(SHA1).unwrap());
+        assert!(serde_json::from_str::<HashBuf>(&format!("\"{SHA1}0\"")).is_err());
+        assert!(HashBuf::try_from(&*format!("{SHA1}0")).is_err());
+    }
+}
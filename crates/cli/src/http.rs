@@ -0,0 +1,74 @@
+//! Requests to the Pwned Passwords range API. Builds for wasi, which has no sockets, or without
+//! the `server-client` feature fail every request instead.
+
+pub use imp::Agent;
+
+/// What a request got back, the body is empty for a 304.
+pub struct Response {
+    pub status: u16,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub body: Vec<u8>,
+}
+
+#[cfg(all(feature = "server-client", not(target_os = "wasi")))]
+mod imp {
+    use std::io::Read;
+
+    use anyhow::Result;
+
+    use super::Response;
+
+    /// Keeps connections open across requests.
+    pub struct Agent(ureq::Agent);
+
+    impl Default for Agent {
+        fn default() -> Self {
+            Self(ureq::agent())
+        }
+    }
+
+    impl Agent {
+        /// GETs `url` with the extra `headers`.
+        pub fn get(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response> {
+            let mut request = self.0.get(url);
+            for (name, value) in headers {
+                request = request.set(name, value);
+            }
+
+            let response = request.call()?;
+            let mut body = Vec::new();
+            let (status, etag, last_modified) = (
+                response.status(),
+                response.header("ETag").map(String::from),
+                response.header("Last-Modified").map(String::from),
+            );
+            response.into_reader().read_to_end(&mut body)?;
+
+            Ok(Response {
+                status,
+                etag,
+                last_modified,
+                body,
+            })
+        }
+    }
+}
+
+#[cfg(not(all(feature = "server-client", not(target_os = "wasi"))))]
+mod imp {
+    use anyhow::Result;
+
+    use super::Response;
+
+    #[derive(Default)]
+    pub struct Agent;
+
+    impl Agent {
+        pub fn get(&self, url: &str, _headers: &[(&str, &str)]) -> Result<Response> {
+            anyhow::bail!(
+                "unable to request {url}, built for wasi or without the server-client feature"
+            )
+        }
+    }
+}
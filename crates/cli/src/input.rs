@@ -0,0 +1,140 @@
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{BufReader, Read},
+    ops::Deref,
+    path::Path,
+};
+
+use clap::ValueEnum;
+use memmap2::Mmap;
+use serde::Deserialize;
+
+use crate::{compress, generate::write_atomic, uring, with_suffix};
+
+/// How hash files are read and outputs are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IoBackend {
+    /// Plain blocking reads and writes
+    Std,
+    /// Batched through io_uring, only available on Linux builds with the io-uring feature
+    Uring,
+}
+
+/// What happens to a hash file once all of its outputs are written, with --remove-source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoveSource {
+    /// Delete it
+    Delete,
+    /// Replace it with a zstd compressed copy that can still be generated from, e.g. ABCDE.txt.zst
+    Zstd,
+}
+
+/// zstd level hash files are recompressed at, compressing them to about a third as fast as
+/// they're generated.
+const SOURCE_ZSTD_LEVEL: i32 = 9;
+
+impl RemoveSource {
+    /// Removes the hash file at `path` with the `content` it was read as. Hash files that are
+    /// already compressed are kept as they are by [`Self::Zstd`].
+    pub fn remove(self, path: &Path, content: &[u8]) -> std::io::Result<()> {
+        if self == Self::Zstd {
+            if Compression::of(path).is_some() {
+                return Ok(());
+            }
+            write_atomic(&with_suffix(path, ".zst"), None, |file| {
+                compress::zstd(file, content, SOURCE_ZSTD_LEVEL)
+            })?;
+        }
+        std::fs::remove_file(path)
+    }
+}
+
+/// How a hash file is compressed, detected by its extension, e.g. `ABCDE.txt.gz`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Brotli,
+}
+
+impl Compression {
+    pub fn of(path: &Path) -> Option<Self> {
+        match path.extension().and_then(OsStr::to_str)? {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            "br" => Some(Self::Brotli),
+            _ => None,
+        }
+    }
+
+    /// Decompresses all of `file` into `buf`.
+    fn decompress(self, file: File, buf: &mut Vec<u8>) -> std::io::Result<()> {
+        let file = BufReader::new(file);
+        buf.clear();
+        match self {
+            Self::Gzip => compress::gzip_decoder(file).read_to_end(buf)?,
+            Self::Zstd => zstd::Decoder::with_buffer(file)?.read_to_end(buf)?,
+            Self::Brotli => compress::brotli_decoder(file).read_to_end(buf)?,
+        };
+        Ok(())
+    }
+}
+
+/// The content of a hash file, either read into a reused buffer or memory-mapped.
+pub enum Content<'a> {
+    Buffered(&'a [u8]),
+    Mapped(Mmap),
+}
+
+impl Deref for Content<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Buffered(content) => content,
+            Self::Mapped(content) => content,
+        }
+    }
+}
+
+/// Reads the hash file at `path` into `buf` through `backend`, or memory-maps it if `mmap` is
+/// set. Compressed hash files are always decompressed into `buf`.
+pub fn read<'a>(
+    path: &Path,
+    mmap: bool,
+    backend: IoBackend,
+    buf: &'a mut Vec<u8>,
+) -> std::io::Result<Content<'a>> {
+    let mut file = File::open(path)?;
+    if let Some(compression) = Compression::of(path) {
+        compression.decompress(file, buf)?;
+        return Ok(Content::Buffered(buf));
+    }
+
+    if backend == IoBackend::Uring {
+        uring::read_to_end(&file, buf)?;
+        return Ok(Content::Buffered(buf));
+    }
+
+    // mapping an empty file fails on some platforms
+    if !mmap || file.metadata()?.len() == 0 {
+        buf.clear();
+        file.read_to_end(buf)?;
+        return Ok(Content::Buffered(buf));
+    }
+
+    // SAFETY: hash files are not expected to be modified while generating, a concurrent
+    // truncation would at worst produce garbage that fails to parse or a SIGBUS
+    let map = unsafe { Mmap::map(&file)? };
+    Ok(Content::Mapped(map))
+}
+
+/// Reads all of the hash file at `path`, decompressing it if needed.
+pub fn read_all(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    read(path, false, IoBackend::Std, &mut buf)?;
+    Ok(buf)
+}
@@ -1,254 +1,1843 @@
 use std::{
+    collections::HashSet,
     ffi::OsStr,
-    fs::File,
-    io::{stdout, BufRead, BufWriter, Write},
+    io::{stderr, stdout, BufReader, IsTerminal},
     path::{Path, PathBuf},
-    sync::atomic::{AtomicU64, Ordering},
-    time::Instant,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use clap::{ArgAction, Parser};
-use console::style;
-use flate2::Compression;
-use indicatif::{ParallelProgressIterator, ProgressStyle};
-use mimalloc::MiMalloc;
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
-use serde::Serialize;
+use anyhow::{Context, Result};
+use clap::{
+    builder::TypedValueParser, ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand,
+    ValueEnum,
+};
+use globset::{Glob, GlobMatcher};
+use hibp_json_util::{
+    parse_prefix, PathTemplate, PrefixRange, Shard, CURRENT_LINK, PREFIX_COUNT, PREFIX_LEN,
+};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn, Level};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use walkdir::WalkDir;
 
+pub use crate::exit::exit_code;
+
+use crate::{
+    archive::{Archive, ArchiveFormat},
+    checkpoint::Checkpoint,
+    compress::GzipEngine,
+    config::Config,
+    dataset::{Dataset, FieldNames},
+    dictionary::Dictionary,
+    dump::Dump,
+    encrypt::{EncryptSpec, Encryption},
+    error::GenerateError,
+    exit::{Exit, ResultExt},
+    fields::Fields,
+    filter::HashFilter,
+    generate::{Buffers, CountOverflow, Directories, Generator, HashType, ParseOptions, Totals},
+    hash_buf::HashBuf,
+    input::{IoBackend, RemoveSource},
+    manifest::{Manifest, ManifestWriter},
+    memory::Memory,
+    ndjson::{Ndjson, StdoutFormat},
+    previous::Previous,
+    progress::{Event, ProgressMode},
+    rank::Ranks,
+    remote::{Remote, RemoteOptions},
+    report::Report,
+    state::{SourceState, Stamp},
+    summary::Summary,
+};
+
+mod archive;
+mod bench;
+mod checkpoint;
+mod clean;
+mod compress;
+mod config;
+mod convert;
+mod cpus;
+mod dataset;
+mod dictionary;
+mod diff;
+mod dump;
+mod encrypt;
+mod error;
+mod exit;
+mod export;
+mod fields;
+mod filter;
+mod gen_config;
+mod generate;
+mod hash_buf;
+mod http;
+mod input;
+mod manifest;
+mod memory;
+mod merge;
+mod ndjson;
+mod nice;
+mod pipeline;
+mod previous;
+mod progress;
+mod rank;
+mod recompress;
+mod remote;
+mod report;
+mod sign;
+mod space;
+mod split;
+mod state;
+mod static_site;
+mod stats;
+mod stdin;
+mod summary;
+mod synth;
+mod top;
+mod update;
+mod uring;
+mod verify;
+mod versioned;
+mod watch;
+
+#[cfg(all(feature = "mimalloc", not(target_os = "wasi")))]
 #[global_allocator]
-static GLOBAL: MiMalloc = MiMalloc;
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 /// Generate JSON formatted files for HIBP password hash files
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[allow(clippy::struct_excessive_bools)]
 struct Args {
-    /// Path to existing hashes
-    #[arg(long, default_value = "hashes")]
+    /// Generates the files when omitted
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to a config file, defaults to hibp-json.toml if it exists
+    #[arg(long, env = "HIBP_JSON_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
+    /// Path to existing hashes, or - to read `PREFIX:SUFFIX:COUNT` or `HASH:COUNT` lines ordered
+    /// by hash from stdin
+    #[arg(long, env = "HIBP_JSON_HASHES", default_value = "hashes")]
     hashes: PathBuf,
 
-    /// Path to output to
-    #[arg(short, long, default_value = "dist")]
+    /// Path to a single file of every hash ordered by hash to read instead of --hashes, e.g.
+    /// pwnedpasswords.txt
+    #[arg(long, env = "HIBP_JSON_HASHES_FILE", conflicts_with_all = ["incremental", "train_brotli_dictionary", "mmap", "io_backend"])]
+    hashes_file: Option<PathBuf>,
+
+    /// Path to output to, an object storage URL like s3://bucket/prefix, gs://bucket/prefix, or
+    /// az://container/prefix to upload the generated files to, or - to write the entries to
+    /// stdout as newline-delimited JSON
+    #[arg(short, long, env = "HIBP_JSON_OUT", default_value = "dist")]
     out: PathBuf,
 
-    /// If the input should be strictly checked
+    /// Generate into a directory with this name within --out, e.g. dist/v8, and point the
+    /// dist/current link at it once the run succeeded
+    #[arg(long, conflicts_with_all = ["archive", "dry_run"])]
+    version_label: Option<String>,
+
+    /// How the entries are written to stdout with --out -
+    #[arg(long, value_enum, default_value_t = StdoutFormat::Entries)]
+    stdout_format: StdoutFormat,
+
+    /// Write the temporary files of the outputs to this directory instead of next to them, e.g. a
+    /// tmpfs, and copy them into place if it's on another filesystem
+    #[arg(long)]
+    tmp_dir: Option<PathBuf>,
+
+    /// If the input should be strictly checked, both the amount of files and every line
     #[arg(long, default_value_t = true, action = ArgAction::Set)]
     strict: bool,
 
+    /// Sum the counts of suffixes appearing more than once in a hash file, e.g. of hand-merged
+    /// corpora, instead of failing on them with --strict
+    #[arg(long, conflicts_with = "stream")]
+    merge_duplicates: bool,
+
+    /// What to do with counts that don't fit in 64 bits
+    #[arg(long, value_enum, default_value_t = CountOverflow::Error)]
+    count_overflow: CountOverflow,
+
+    /// The hashes of the hash files, e.g. ntlm for the Pwned Passwords NTLM downloads
+    #[arg(long, value_enum, default_value_t = HashType::Sha1)]
+    hash_type: HashType,
+
+    /// Extensions of the hash files to pick up besides bare 5 character hex names, each also
+    /// compressed with .gz, .zst, or .br
+    #[arg(long, value_delimiter = ',', default_value = "txt")]
+    input_extensions: Vec<String>,
+
+    /// Only pick up the hash files matching this glob relative to --hashes, e.g. 'a/**/0*.txt',
+    /// the hash files are found in every subdirectory
+    #[arg(long)]
+    glob: Option<Glob>,
+
+    /// Skip blank and malformed lines of the hash files instead of failing on them, reporting
+    /// them at the end
+    #[arg(long)]
+    lenient: bool,
+
     /// If .gz files should be generated
-    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    #[arg(long, env = "HIBP_JSON_GZIP", default_value_t = compress::GZIP, action = ArgAction::Set)]
     gzip: bool,
 
     /// If .br files should be generated
-    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    #[arg(long, env = "HIBP_JSON_BROTLI", default_value_t = compress::BROTLI, action = ArgAction::Set)]
     brotli: bool,
 
     /// If .json files should be generated
-    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    #[arg(long, env = "HIBP_JSON_JSON", default_value_t = cfg!(feature = "json"), action = ArgAction::Set)]
     json: bool,
+
+    /// Gzip compression level, from 0 to 9
+    #[arg(long, env = "HIBP_JSON_GZIP_LEVEL", default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+    gzip_level: u32,
+
+    /// Gzip encoder, zopfli is a few percent smaller but orders of magnitude slower
+    #[arg(long, value_enum, default_value_t = GzipEngine::Flate2, conflicts_with = "stream")]
+    gzip_engine: GzipEngine,
+
+    /// Brotli compression quality, from 0 to 11
+    #[arg(long, env = "HIBP_JSON_BROTLI_QUALITY", default_value_t = 11, value_parser = clap::value_parser!(u32).range(0..=11))]
+    brotli_quality: u32,
+
+    /// Also write .dcb files, brotli compressed with this shared dictionary
+    #[arg(long, conflicts_with = "stream")]
+    brotli_dictionary: Option<PathBuf>,
+
+    /// Train a shared brotli dictionary from the hash files, write it to this path and exit
+    #[arg(long, conflicts_with = "brotli_dictionary")]
+    train_brotli_dictionary: Option<PathBuf>,
+
+    /// How many hash files to train the brotli dictionary on
+    #[arg(long, default_value_t = 1000, requires = "train_brotli_dictionary")]
+    dictionary_samples: usize,
+
+    /// Only include hashes seen at least this many times
+    #[arg(long, default_value_t = 0)]
+    min_count: u64,
+
+    /// Add the global rank of every entry's count and where it falls among all hashes in percent,
+    /// computed in a first pass over every hash file
+    #[arg(long, conflicts_with_all = ["incremental", "watch"])]
+    with_rank: bool,
+
+    /// Leave out the hashes listed in this file, full SHA-1s one per line, e.g. known test hashes
+    #[arg(long)]
+    exclude_hashes: Option<PathBuf>,
+
+    /// Only include the hashes listed in this file, full SHA-1s one per line, e.g. a
+    /// customer-provided scope
+    #[arg(long)]
+    only_hashes: Option<PathBuf>,
+
+    /// Only include the N highest count hashes of each prefix
+    #[arg(long)]
+    top: Option<usize>,
+
+    /// Also write a tree of just the hashes seen at least this many times to tier-N for every
+    /// threshold, e.g. 100,1000,100000
+    #[arg(long, value_delimiter = ',', conflicts_with = "stream")]
+    tiers: Vec<u64>,
+
+    /// The order of the entries in the generated files, --stream keeps the order of the hash files
+    #[arg(long, value_enum, default_value_t = SortBy::Hash, conflicts_with = "stream")]
+    sort_by: SortBy,
+
+    /// The case of the hex hashes in the generated files, hash files may be named in either
+    #[arg(long, value_enum, default_value_t = Case::Upper)]
+    case: Case,
+
+    /// The name of the hash field of every entry in the generated files
+    #[arg(long, default_value = "hash")]
+    hash_field: String,
+
+    /// The name of the count field of every entry in the generated files
+    #[arg(long, default_value = "count")]
+    count_field: String,
+
+    /// What to do with output files that already exist
+    #[arg(long, value_enum, default_value_t = OnExisting::Overwrite)]
+    on_existing: OnExisting,
+
+    /// How many characters of the prefix name the directories of the output files, from 0 to 4.
+    /// 4 nests a directory per character, A/B/C/D/E.json, less groups them into a single one,
+    /// e.g. 2 creates AB/CDE.json. See --path-template for others
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(0..=4))]
+    depth: u8,
+
+    /// How the output files are laid out, flat ignores --depth
+    #[arg(long, value_enum, default_value_t = Layout::Nested)]
+    layout: Layout,
+
+    /// Template for the output path of each .json file, e.g. "{p0}{p1}/{prefix}.json"
+    ///
+    /// `{prefix}` is the whole prefix while `{p0}` through `{p4}` are its individual
+    /// characters. Compressed files are written next to it with an extra `.gz` or `.br`.
+    #[arg(long, conflicts_with_all = ["depth", "layout"])]
+    path_template: Option<PathTemplate>,
+
+    /// Only process prefixes within a range, e.g. 00000..=0FFFF
+    #[arg(long)]
+    range: Option<PrefixRange>,
+
+    /// Only process the prefixes of shard i out of n, e.g. 1/4
+    #[arg(long)]
+    shard: Option<Shard>,
+
+    /// Only process the prefixes listed in this file, one per line, e.g. from `diff --list`
+    #[arg(long)]
+    prefix_list: Option<PathBuf>,
+
+    /// The prefixes read from --prefix-list
+    #[arg(skip)]
+    listed: Option<HashSet<u32>>,
+
+    /// Skip prefixes already completed by a previous interrupted run
+    #[arg(long)]
+    resume: bool,
+
+    /// Only regenerate prefixes whose source file changed since the last run
+    #[arg(long)]
+    incremental: bool,
+
+    /// Guarantee byte-identical outputs across runs and platforms
+    #[arg(long)]
+    reproducible: bool,
+
+    /// Read every output back after writing it and check it decodes to the serialized JSON, to
+    /// catch silent encoder or disk corruption
+    #[arg(long, conflicts_with = "stream")]
+    self_check: bool,
+
+    /// Leave out the compressed variants of a prefix that are no smaller than its .json, which
+    /// the server falls back to
+    #[arg(long, conflicts_with = "stream")]
+    skip_larger_compressed: bool,
+
+    /// Write the entries with a hand-rolled serializer instead of serde_json, about twice as
+    /// fast when the JSON isn't compressed, with byte-identical output
+    #[arg(long, conflicts_with = "stream")]
+    fast_json: bool,
+
+    /// Remove every hash file once all of its outputs are written and read back, for when the
+    /// disk can't hold both datasets. zstd keeps a compressed copy to generate from instead
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "delete",
+        conflicts_with_all = ["stream", "archive", "incremental", "watch", "dry_run"]
+    )]
+    remove_source: Option<RemoveSource>,
+
+    /// Encrypt every generated file at rest, with age:<recipient> or aes-gcm:<keyfile> holding 32
+    /// random bytes
+    #[arg(long, conflicts_with_all = ["stream", "io_backend", "reproducible", "static_site"])]
+    encrypt: Option<EncryptSpec>,
+
+    /// If a manifest.json with the size and SHA-256 of every file should be generated
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    manifest: bool,
+
+    /// Write a summary.csv with the entries, input and output sizes, and time of every prefix
+    #[arg(long)]
+    summary: bool,
+
+    /// Write the totals, durations, and failures of the run to this file as JSON
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Path to an ed25519 secret key to sign the manifest with, written to manifest.json.sig
+    #[arg(long)]
+    sign_key: Option<PathBuf>,
+
+    /// Keep going when a prefix fails, reporting every failed prefix at the end
+    #[arg(long)]
+    keep_going: bool,
+
+    /// How many times to retry a prefix that failed on I/O with --keep-going
+    #[arg(long, default_value_t = 2, requires = "keep_going")]
+    retries: u32,
+
+    /// How to report progress while generating
+    #[arg(long, value_enum, default_value_t = ProgressMode::Bar)]
+    progress: ProgressMode,
+
+    /// Memory-map the hash files instead of reading them, faster when they're page-cached
+    #[arg(long)]
+    mmap: bool,
+
+    /// Serialize straight into the outputs while parsing, halving peak memory per worker
+    #[arg(long, conflicts_with_all = ["top", "reproducible"])]
+    stream: bool,
+
+    /// How to read hash files and write outputs, uring needs a Linux build with the io-uring
+    /// feature
+    #[arg(long, value_enum, default_value_t = IoBackend::Std, conflicts_with_all = ["mmap", "stream"])]
+    io_backend: IoBackend,
+
+    /// Read hash files on a separate pool of this many I/O threads ahead of the workers
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..).map(usize::from))]
+    io_threads: Option<usize>,
+
+    /// How many hash files the I/O threads may read ahead of the workers
+    #[arg(long, default_value_t = 256, requires = "io_threads")]
+    read_ahead: usize,
+
+    /// Limit the memory of the prefixes generated at once to about this size, e.g. 1500M or 2G,
+    /// holding back hash files until there's room for them
+    #[arg(long, value_parser = memory::parse_size)]
+    max_memory: Option<u64>,
+
+    /// Amount of worker threads, defaults to one per cpu core available to the process, within
+    /// its cgroup cpu quota
+    #[arg(short = 'j', long, env = "RAYON_NUM_THREADS", global = true, value_parser = clap::value_parser!(u16).range(1..).map(usize::from))]
+    threads: Option<usize>,
+
+    /// Run at the lowest cpu and I/O priority, so a regeneration doesn't starve the server on
+    /// the same box
+    #[arg(long, global = true)]
+    nice: bool,
+
+    /// Write every generated file into a single archive next to the output directory instead,
+    /// e.g. dist.tar.gz
+    #[arg(long, value_enum, conflicts_with_all = ["stream", "io_backend", "resume", "incremental"])]
+    archive: Option<ArchiveFormat>,
+
+    /// The output directory of the previous dataset version, outputs identical to its files are
+    /// hardlinked to them instead of taking up the disk space again
+    #[arg(long, conflicts_with_all = ["stream", "io_backend", "archive"])]
+    previous: Option<PathBuf>,
+
+    /// Uploads in flight at once when --out is an object storage URL
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u16).range(1..).map(usize::from))]
+    upload_concurrency: usize,
+
+    /// How many times to retry a failed upload when --out is an object storage URL
+    #[arg(long, default_value_t = 10)]
+    upload_retries: usize,
+
+    /// How many seconds to keep retrying a failed upload for at most
+    #[arg(long, default_value_t = 180)]
+    upload_retry_timeout: u64,
+
+    /// Also write a page checking passwords in the browser and an index of every directory, to
+    /// serve the output directory from static hosting like GitHub Pages
+    #[arg(long)]
+    static_site: bool,
+
+    /// Keep running after generating, regenerating the outputs of hash files as they change
+    #[arg(long, conflicts_with_all = ["dry_run", "archive", "train_brotli_dictionary"])]
+    watch: bool,
+
+    /// Estimate the size of every enabled format from a sample of prefixes without writing
+    /// anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Check there's enough free disk space for the outputs before generating them
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    space_check: bool,
+
+    /// How many prefixes to sample with --dry-run
+    #[arg(long, default_value_t = 100, requires = "dry_run")]
+    dry_run_samples: usize,
+
+    /// Only log warnings and errors, and hide the progress bar
+    #[arg(short, long, conflicts_with = "verbose", global = true)]
+    quiet: bool,
+
+    /// Log more details, pass twice for even more
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
 }
 
-#[derive(Serialize)]
-pub struct Password {
-    hash: String,
-    count: usize,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report statistics about the hash files, or the files of a generated output directory
+    Stats(stats::StatsArgs),
+
+    /// Report the hashes with the highest counts across all prefixes
+    Top(top::TopArgs),
+
+    /// Report the hashes added, removed, and changed between two releases of the hash files
+    Diff(diff::DiffArgs),
+
+    /// Merge several directories of hash files into one, summing the counts of shared hashes
+    Merge(merge::MergeArgs),
+
+    /// Check the generated files round-trip to the lines of the hash files they came from
+    Verify(verify::VerifyArgs),
+
+    /// Write compressed variants of the .json files of a generated output directory
+    Compress(recompress::CompressArgs),
+
+    /// Convert the files of a generated output directory to another format
+    Convert(convert::ConvertArgs),
+
+    /// Remove temporary files, disabled formats, orphaned prefixes, and empty directories from a
+    /// generated output directory
+    Clean(clean::CleanArgs),
+
+    /// Download the ranges that changed since the last update into the hashes directory, then
+    /// regenerate the outputs of just those prefixes
+    Update(update::UpdateArgs),
+
+    /// Compress a sample of prefixes with a range of gzip levels, brotli qualities, and zstd
+    /// levels, reporting the size and time of every setting
+    Bench(bench::BenchArgs),
+
+    /// Export every hash to a single file for bulk-loading into another system, like Redis
+    Export(export::ExportArgs),
+
+    /// Print a nginx, Caddy, or Apache configuration serving the generated files with their
+    /// precompressed variants
+    GenConfig(gen_config::GenConfigArgs),
+
+    /// Write a small tree of fake but valid hash files to --hashes, for tests and demos
+    Synth(synth::SynthArgs),
+
+    /// Split a single file of every hash ordered by hash into the hash file of every prefix
+    Split(split::SplitArgs),
 }
 
-pub fn walk1(path: impl AsRef<Path>) -> walkdir::IntoIter {
-    WalkDir::new(path.as_ref())
-        .max_depth(1)
-        .min_depth(1)
-        .into_iter()
+impl Command {
+    fn run(&self) -> Result<()> {
+        match self {
+            Self::Stats(args) => stats::run(args),
+            Self::Top(args) => top::run(args),
+            Self::Diff(args) => diff::run(args),
+            Self::Merge(args) => merge::run(args),
+            Self::Verify(args) => verify::run(args),
+            Self::Compress(args) => recompress::run(args),
+            Self::Convert(args) => convert::run(args),
+            Self::Clean(args) => clean::run(args),
+            Self::Bench(args) => bench::run(args),
+            Self::Export(args) => export::run(args),
+            Self::GenConfig(args) => gen_config::run(args),
+            Self::Split(args) => split::run(args),
+            // need the generation arguments, see `run`
+            Self::Update(_) => unreachable!("update is run before generating"),
+            Self::Synth(_) => unreachable!("synth is run before generating"),
+        }
+    }
 }
 
-pub fn generate_out_structure(out: &Path) -> Result<()> {
-    #[rustfmt::skip]
-    let hex = [
-        "0", "1", "2", "3", "4", "5", "6", "7",
-        "8", "9", "A", "B", "C", "D", "E", "F",
-    ];
+impl Args {
+    /// Every setting that affects the content or location of the generated files.
+    fn output_settings(&self) -> String {
+        format!(
+            "json={} gzip={} brotli={} gzip_level={} gzip_engine={:?} brotli_quality={} \
+             brotli_dictionary={:?} min_count={} exclude_hashes={:?} \
+             only_hashes={:?} top={:?} tiers={:?} sort_by={:?} case={:?} \
+             hash_field={:?} count_field={:?} with_rank={} depth={} layout={:?} template={:?} reproducible={} \
+             encrypt={:?} skip_larger_compressed={} merge_duplicates={}",
+            self.json,
+            self.gzip,
+            self.brotli,
+            self.gzip_level,
+            self.gzip_engine,
+            self.brotli_quality,
+            self.brotli_dictionary,
+            self.min_count,
+            self.exclude_hashes,
+            self.only_hashes,
+            self.top,
+            self.tiers,
+            self.sort_by,
+            self.case,
+            self.hash_field,
+            self.count_field,
+            self.with_rank,
+            self.depth,
+            self.layout,
+            self.path_template,
+            self.reproducible,
+            self.encrypt,
+            self.skip_larger_compressed,
+            self.merge_duplicates
+        )
+    }
 
-    for c1 in hex {
-        let p1 = out.join(c1);
-        for c2 in hex {
-            let p2 = p1.join(c2);
-            for c3 in hex {
-                let p3 = p2.join(c3);
-                for c4 in hex {
-                    std::fs::create_dir_all(p3.join(c4))?;
-                }
-            }
-        }
+    /// Whether `prefix` is within the --range, --shard, and --prefix-list to generate.
+    fn selected(&self, prefix: u32) -> bool {
+        self.range.as_ref().is_none_or(|r| r.contains(prefix))
+            && self.shard.is_none_or(|s| s.contains(prefix))
+            && self.listed.as_ref().is_none_or(|l| l.contains(&prefix))
     }
 
-    Ok(())
+    /// Whether only some of the prefixes are generated.
+    fn is_subset(&self) -> bool {
+        self.range.is_some() || self.shard.is_some() || self.listed.is_some()
+    }
 }
 
-pub fn flush() -> Result<()> {
-    Ok(stdout().lock().flush()?)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Layout {
+    /// Nest output files in directories, see --depth
+    Nested,
+
+    /// Write every output file directly into the output directory
+    Flat,
 }
 
-pub fn ensure_output_directories(dist: &Path) -> Result<()> {
-    print!(
-        "{} Ensuring 65,536 output directories",
-        style("[1/3]").bold().dim()
-    );
-    flush()?;
+/// The order of the entries within a generated file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortBy {
+    /// Ascending hash, for clients that binary search
+    Hash,
 
-    let start = Instant::now();
-    generate_out_structure(dist)?;
+    /// Descending count, most common first
+    Count,
+}
 
-    println!(
-        "\r{} Ensured 65,536 output directories in {}ms",
-        style("[1/3]").bold().dim(),
-        style(start.elapsed().as_millis()).bold()
-    );
+/// What to do with an output file that already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OnExisting {
+    /// Replace it
+    Overwrite,
 
-    Ok(())
+    /// Keep it as it is, to fill in the missing files of an output directory
+    Skip,
+
+    /// Stop, e.g. when --out points at the wrong directory
+    Error,
 }
 
+/// The case of the hex hashes within a generated file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Case {
+    /// Like the hash files, e.g. 0018A45C4D1DEF81644B54AB7F969B88D65
+    Upper,
+
+    /// For systems that store SHA-1s lowercase, e.g. 0018a45c4d1def81644b54ab7f969b88d65
+    Lower,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Password {
+    hash: HashBuf,
+    count: u64,
+}
+
+/// Extensions of the hash files picked up by default, besides bare 5 character hex names.
+pub const HASH_FILE_EXTENSIONS: &[&str] = &["txt"];
+
+/// Entries of the hash directory logged as examples of what was ignored.
+const IGNORED_EXAMPLES: usize = 5;
+
 pub fn find_all_hash_files(hashes: &Path) -> Result<Vec<PathBuf>> {
-    print!(
-        "{} Finding all hash files in {}",
-        style("[2/3]").bold().dim(),
-        style(hashes.display()).bold()
-    );
-    flush()?;
+    find_hash_files(hashes, HASH_FILE_EXTENSIONS, None)
+}
+
+/// Every file within `hashes` and its subdirectories named like a hash file with one of
+/// `extensions`, and matching `glob` relative to `hashes` if there is one, sorted by prefix.
+///
+/// Warns about the entries it ignored, like `.DS_Store`, editor swap files, or partial
+/// downloads, and fails if a prefix was found more than once.
+pub fn find_hash_files(
+    hashes: &Path,
+    extensions: &[impl AsRef<str>],
+    glob: Option<&GlobMatcher>,
+) -> Result<Vec<PathBuf>> {
+    debug!("[2/3] Finding all hash files in {}", hashes.display());
 
     let start = Instant::now();
-    let paths =
-        walk1(hashes).try_fold(Vec::with_capacity(1_048_576), |mut acc: Vec<_>, item| {
-            acc.push(item?.into_path());
-            Ok::<_, walkdir::Error>(acc)
-        })?;
+    let mut paths = Vec::with_capacity(1_048_576);
+    let (mut ignored, mut unmatched) = (Vec::new(), 0);
+    // skips hidden directories like .git
+    let walk = WalkDir::new(hashes)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            !(entry.file_type().is_dir() && entry.file_name().to_string_lossy().starts_with('.'))
+        });
+    for entry in walk {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        // only symlinks need another stat to tell if they point to a file
+        let is_file =
+            entry.file_type().is_file() || (entry.path_is_symlink() && entry.path().is_file());
+        if !is_file || !is_hash_file_name(entry.path(), extensions) {
+            ignored.push(entry.into_path());
+        } else if glob.is_some_and(|glob| {
+            !glob.is_match(entry.path().strip_prefix(hashes).unwrap_or(entry.path()))
+        }) {
+            unmatched += 1;
+        } else {
+            paths.push(entry.into_path());
+        }
+    }
+    if unmatched > 0 {
+        debug!("Skipped {unmatched} hash files not matching --glob");
+    }
 
-    println!(
-        "\r{} Found {} hash files in {} in {}ms",
-        style("[2/3]").bold().dim(),
-        style(paths.len()).bold(),
-        style(hashes.display()).bold(),
-        style(start.elapsed().as_millis()).bold(),
+    if !ignored.is_empty() {
+        let examples: Vec<_> = ignored
+            .iter()
+            .take(IGNORED_EXAMPLES)
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+            .collect();
+        warn!(
+            "Ignored {} entries of {} that aren't hash files, like {}",
+            ignored.len(),
+            hashes.display(),
+            examples.join(", ")
+        );
+    }
+
+    // nested layouts could hold a prefix twice, which would be generated twice
+    paths.sort_by_cached_key(|path| prefix_of(path));
+    if let Some(pair) = paths
+        .windows(2)
+        .find(|pair| prefix_of(&pair[0]) == prefix_of(&pair[1]))
+    {
+        anyhow::bail!(
+            "{} and {} hold the same prefix",
+            pair[0].display(),
+            pair[1].display()
+        );
+    }
+
+    info!(
+        "[2/3] Found {} hash files in {} in {}ms",
+        paths.len(),
+        hashes.display(),
+        start.elapsed().as_millis(),
     );
 
     Ok(paths)
 }
 
+/// Every generated file with `extension` in `dist`, skipping the metadata files next to them.
+pub fn find_generated_files(dist: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    let metadata: Vec<_> = metadata_files(dist).collect();
+    let mut paths = Vec::new();
+    for entry in WalkDir::new(dist).min_depth(1) {
+        let entry = entry?;
+        if entry.file_type().is_file()
+            && entry.path().extension() == Some(OsStr::new(extension))
+            && !metadata.iter().any(|m| m == entry.path())
+        {
+            paths.push(entry.into_path());
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Logs to stdout, or to stderr when stdout carries the output, with colors only when it's a
+/// terminal.
+pub fn init_logging(quiet: bool, verbose: u8, to_stderr: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+
+    let (writer, ansi) = if to_stderr {
+        (BoxMakeWriter::new(stderr), stderr().is_terminal())
+    } else {
+        (BoxMakeWriter::new(stdout), stdout().is_terminal())
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(writer)
+        .with_ansi(ansi)
+        .with_target(false)
+        .without_time()
+        .init();
+}
+
 pub fn progress_style() -> ProgressStyle {
-    ProgressStyle::with_template("{elapsed} {bar} {percent}% eta {eta} {per_sec} ")
+    ProgressStyle::with_template("{elapsed} {bar} {percent}% eta {eta} {binary_bytes_per_sec} ")
         .unwrap()
         .progress_chars("█▉▊▋▌▍▎▏  ")
 }
 
-pub fn format_prefix_to_dirs(prefix: &str) -> String {
-    prefix
-        .char_indices()
-        .fold(String::with_capacity(9), |mut acc, (i, c)| {
-            if i > 0 {
-                acc.push('/');
-            }
-            acc.push(c);
-            acc
-        })
+/// Directory depth of the output files, none with a `template` as it creates its own
+/// directories as the files get written.
+fn output_depth(layout: Layout, depth: u8, template: bool) -> usize {
+    match layout {
+        _ if template => 0,
+        Layout::Nested => usize::from(depth),
+        Layout::Flat => 0,
+    }
+}
+
+/// Keeps only the `n` highest count passwords, sorted by hash.
+pub fn keep_top(passwords: &mut Vec<Password>, n: usize) {
+    if passwords.len() <= n {
+        return;
+    }
+
+    // ties are broken by hash, so the cutoff doesn't depend on the order of the hash file
+    passwords.sort_unstable_by(by_count);
+    passwords.truncate(n);
+    passwords.sort_unstable_by_key(|password| password.hash);
+}
+
+/// Sums the counts of the hashes appearing more than once into a single password, ordering
+/// `passwords` by hash. Returns the amount of passwords merged away.
+pub fn merge_duplicates(passwords: &mut Vec<Password>) -> usize {
+    let len = passwords.len();
+    // a no-op for hash files already in that order
+    passwords.sort_unstable_by_key(|password| password.hash);
+    passwords.dedup_by(|next, kept| {
+        let duplicate = next.hash == kept.hash;
+        if duplicate {
+            kept.count = kept.count.saturating_add(next.count);
+        }
+        duplicate
+    });
+    len - passwords.len()
+}
+
+/// The suffix of the first hash appearing more than once in `passwords`, ordering them by hash.
+pub fn find_duplicate(passwords: &mut [Password]) -> Option<&str> {
+    if !passwords.windows(2).all(|w| w[0].hash < w[1].hash) {
+        passwords.sort_unstable_by_key(|password| password.hash);
+    }
+    passwords
+        .windows(2)
+        .find(|w| w[0].hash == w[1].hash)
+        .map(|w| &w[0].hash[PREFIX_LEN..])
+}
+
+/// Orders passwords by descending count, then by hash.
+pub fn by_count(a: &Password, b: &Password) -> std::cmp::Ordering {
+    b.count.cmp(&a.count).then_with(|| a.hash.cmp(&b.hash))
+}
+
+/// Parses the prefix a hash file is named after.
+pub fn prefix_of(path: &Path) -> Option<u32> {
+    file_prefix(path).and_then(parse_prefix)
+}
+
+/// Reads a list of prefixes, one per line. Anything after the prefix is ignored, and empty
+/// lines and `#` comments are skipped.
+fn read_prefix_list(path: &Path) -> Result<HashSet<u32>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read prefix list {}", path.display()))?;
+
+    let mut prefixes = HashSet::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.split_whitespace().next().unwrap_or_default();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let prefix = parse_prefix(line).with_context(|| {
+            format!(
+                "{}:{}: `{line}` is not a 5 character hex prefix",
+                path.display(),
+                i + 1
+            )
+        })?;
+        prefixes.insert(prefix);
+    }
+
+    Ok(prefixes)
+}
+
+/// Whether `path` is named after a 5 character hex prefix, optionally followed by one of
+/// `extensions` and a compression extension, e.g. `ABCDE`, `ABCDE.txt`, or `ABCDE.txt.gz`.
+fn is_hash_file_name(path: &Path, extensions: &[impl AsRef<str>]) -> bool {
+    let Some(name) = path.file_name().and_then(OsStr::to_str) else {
+        return false;
+    };
+    let (prefix, rest) = name.split_at(name.find('.').unwrap_or(name.len()));
+    if parse_prefix(prefix).is_none() {
+        return false;
+    }
+
+    let rest = [".gz", ".zst", ".br"]
+        .into_iter()
+        .find_map(|suffix| rest.strip_suffix(suffix))
+        .unwrap_or(rest);
+    rest.is_empty()
+        || rest
+            .strip_prefix('.')
+            .is_some_and(|ext| extensions.iter().any(|e| e.as_ref() == ext))
+}
+
+/// The file name of `path` up to its first dot, e.g. `ABCDE` for `hashes/ABCDE.txt.gz`.
+pub fn file_prefix(path: &Path) -> Option<&str> {
+    path.file_name()?.to_str()?.split('.').next()
+}
+
+/// The single file of every hash to read instead of a directory of hash files, if any.
+///
+/// Besides `--hashes-file`, this detects both modes of the official haveibeenpwned-downloader:
+/// `--hashes` being a file, or missing while the downloader's single file mode wrote to it with
+/// `.txt` appended, e.g. `hashes.txt`.
+fn single_hash_file(hashes: &Path, hashes_file: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = hashes_file {
+        return Some(path.into());
+    }
+    if hashes.is_file() {
+        return Some(hashes.into());
+    }
+
+    let txt = with_suffix(hashes, ".txt");
+    (!hashes.exists() && txt.is_file()).then_some(txt)
+}
+
+/// The metadata files of the last run in `dist` that describe the generated files.
+fn metadata_files(dist: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    [
+        Dataset::FILE_NAME,
+        Manifest::FILE_NAME,
+        "manifest.json.sig",
+        Dictionary::FILE_NAME,
+        Summary::FILE_NAME,
+    ]
+    .into_iter()
+    .map(|name| dist.join(name))
+    .filter(|path| path.exists())
+}
+
+/// The directory of the tree filtered to hashes seen at least `tier` times, e.g. `dist/tier-100`.
+pub fn tier_dir(dist: &Path, tier: u64) -> PathBuf {
+    dist.join(format!("tier-{tier}"))
+}
+
+/// Whether `path` is within one of the tier-N trees of `dist`.
+pub fn in_tier(dist: &Path, path: &Path) -> bool {
+    path.strip_prefix(dist)
+        .ok()
+        .and_then(|relative| relative.components().next())
+        .and_then(|dir| dir.as_os_str().to_str())
+        .and_then(|dir| dir.strip_prefix("tier-"))
+        .is_some_and(|tier| tier.parse::<usize>().is_ok())
+}
+
+/// Appends `suffix` to the file name of `path`, e.g. `.gz` to `A/B/C/D/E.json`.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Sizes the outputs of `samples` hash files spread evenly over `paths` without writing
+/// anything, adding them to the totals, and returns how to extrapolate those to all of `paths`.
+fn sample(generator: &Generator, paths: &[PathBuf], samples: usize) -> Result<impl Fn(u64) -> u64> {
+    let step = (paths.len() / samples.max(1)).max(1);
+    let sample: Vec<_> = paths.iter().step_by(step).take(samples).collect();
+    debug!("Sampling {} of {} hash files", sample.len(), paths.len());
+
+    let (sampled, total) = (sample.len() as u128, paths.len() as u128);
+    sample
+        .into_par_iter()
+        .try_for_each_init(Buffers::default, |buffers, path| {
+            generator.estimate(path, buffers)
+        })?;
+
+    Ok(move |n: u64| {
+        let n = u128::from(n) * total / sampled.max(1);
+        u64::try_from(n).unwrap_or(u64::MAX)
+    })
+}
+
+/// Generates `samples` hash files spread evenly over `paths` without writing anything, and
+/// prints the size of every enabled format extrapolated to all of `paths`.
+fn dry_run(generator: &Generator, paths: &[PathBuf], samples: usize) -> Result<()> {
+    info!("Dry run, sampling {} hash files", samples.min(paths.len()));
+    let extrapolate = sample(generator, paths, samples)?;
+
+    let totals = &generator.totals;
+    let bytes = totals.bytes();
+    println!("Estimated output of {} hash files:", paths.len());
+    println!(
+        "  entries {}",
+        extrapolate(totals.entries.load(Ordering::SeqCst))
+    );
+    let formats = [
+        (generator.json, "json", bytes.json),
+        (generator.gzip, "gzip", bytes.gzip),
+        (generator.brotli, "brotli", bytes.brotli),
+        (generator.dictionary.is_some(), "dcb", bytes.dcb),
+    ];
+    for (_, format, bytes) in formats.into_iter().filter(|(enabled, ..)| *enabled) {
+        println!("  {format:<7} {}", HumanBytes(extrapolate(bytes)));
+    }
+
+    Ok(())
 }
 
 pub fn run() -> Result<()> {
     let very_start = Instant::now();
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    init_logging(args.quiet, args.verbose, ndjson::is_stdout(&args.out));
+
+    let config = Config::load(args.config.as_deref()).exit(Exit::InvalidArguments)?;
+    config
+        .generate
+        .apply(&mut args, &matches)
+        .exit(Exit::InvalidArguments)?;
+    // the formats left out of the build are off by default, but can still be asked for
+    for (missing, feature) in [
+        (args.json && !cfg!(feature = "json"), "json"),
+        (args.gzip && !compress::GZIP, "gzip"),
+        (
+            (args.brotli || args.brotli_dictionary.is_some()) && !compress::BROTLI,
+            "brotli",
+        ),
+    ] {
+        exit::ensure!(
+            Exit::InvalidArguments,
+            !missing,
+            "--{feature} needs a build with the {feature} feature"
+        );
+    }
+    // wasi has no threads to read or report on the side, nor memory maps
+    exit::ensure!(
+        Exit::InvalidArguments,
+        !cfg!(target_os = "wasi")
+            || (args.io_threads.is_none()
+                && args.progress != ProgressMode::Json
+                && !args.mmap
+                && !stdin::is_stdin(&args.hashes)),
+        "--io-threads, --progress json, --mmap, and --hashes - aren't supported on wasi"
+    );
+
+    // before any thread is started, which inherit it
+    if args.nice {
+        nice::lower_priority()
+            .context("unable to lower the priority")
+            .exit(Exit::Setup)?;
+        debug!("Lowered the cpu and I/O priority");
+    }
 
-    ensure_output_directories(&args.out)?;
-    let paths = find_all_hash_files(&args.hashes)?;
+    // rayon would default to every core of the host, even in a container limited to a few
+    let threads = args.threads.unwrap_or_else(cpus::available);
+    // without threads on wasi, rayon falls back to running everything on the main thread
+    if !cfg!(target_os = "wasi") {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .exit(Exit::Setup)?;
+        debug!("Using {threads} worker threads");
+    }
+
+    let remote = Remote::parse(
+        &args.out,
+        RemoteOptions {
+            concurrency: args.upload_concurrency,
+            retries: args.upload_retries,
+            retry_timeout: Duration::from_secs(args.upload_retry_timeout),
+        },
+    )
+    .exit(Exit::Setup)?;
+    if let Some(remote) = &remote {
+        exit::ensure!(
+            Exit::InvalidArguments,
+            args.archive.is_none()
+                && args.previous.is_none()
+                && args.on_existing == OnExisting::Overwrite
+                && !args.stream
+                && args.io_backend != IoBackend::Uring
+                && args.remove_source.is_none(),
+            "--out {} can't be combined with --archive, --previous, --on-existing, --stream, \
+             --io-backend uring, or --remove-source",
+            remote.url()
+        );
+        // the generated files are uploaded, only the metadata and progress are kept locally
+        args.out = remote.staging().into();
+        info!(
+            "Uploading to {}, staging metadata in {}",
+            remote.url(),
+            args.out.display()
+        );
+    }
+
+    if let Some(label) = &args.version_label {
+        exit::ensure!(
+            Exit::InvalidArguments,
+            remote.is_none() && !ndjson::is_stdout(&args.out),
+            "--version-label needs a local --out to link the versions in"
+        );
+        versioned::check_label(label).exit(Exit::InvalidArguments)?;
+        args.out = args.out.join(label);
+    }
+
+    if let Some(list) = &args.prefix_list {
+        let listed = read_prefix_list(list).exit(Exit::InvalidArguments)?;
+        info!("Only processing the {} listed prefixes", listed.len());
+        args.listed = Some(listed);
+    }
+
+    match &args.command {
+        Some(Command::Update(update)) => {
+            update::run(update, &args.hashes, &args.out, args.hash_type, |p| {
+                args.selected(p)
+            })?;
+            // the ranges that weren't downloaded keep their stamps, so they're skipped
+            args.incremental = true;
+        }
+        Some(Command::Synth(synth)) => {
+            synth::run(synth, &args.hashes)?;
+            if !synth.generate {
+                return Ok(());
+            }
+            // only some of the prefixes have a hash file
+            args.strict = false;
+        }
+        Some(command) => return command.run(),
+        None => {}
+    }
+
+    if !args.watch {
+        return generate(&args, remote.as_ref(), very_start);
+    }
+    // every run only regenerates the prefixes whose hash files changed since the last one
+    args.incremental = true;
+    watch::run(&args.hashes, || {
+        generate(&args, remote.as_ref(), Instant::now())
+    })
+}
+
+/// Generates the outputs of every selected hash file, `very_start` being when the program
+/// started.
+fn generate(args: &Args, remote: Option<&Remote>, very_start: Instant) -> Result<()> {
+    exit::ensure!(
+        Exit::InvalidArguments,
+        args.manifest || args.sign_key.is_none(),
+        "--sign-key requires the manifest to be generated"
+    );
+    let sign_key = args
+        .sign_key
+        .as_deref()
+        .map(sign::read_signing_key)
+        .transpose()
+        .exit(Exit::Setup)?;
+    let dictionary = args
+        .brotli_dictionary
+        .as_deref()
+        .map(Dictionary::read)
+        .transpose()
+        .exit(Exit::Setup)?;
+
+    let depth = output_depth(args.layout, args.depth, args.path_template.is_some());
+    let stage = |stage| {
+        if args.progress == ProgressMode::Json {
+            Event::Stage { stage }.emit();
+        }
+    };
+
+    // fails before anything is generated when --out is the output directory of another run
+    exit::ensure!(
+        Exit::InvalidArguments,
+        args.on_existing != OnExisting::Error
+            || args.resume
+            || !args.out.join(Dataset::FILE_NAME).exists(),
+        "{} already holds a generated dataset, pass --on-existing overwrite or skip to allow this",
+        args.out.display()
+    );
+
+    let to_stdout = ndjson::is_stdout(&args.out);
+    let mut tiers = args.tiers.clone();
+    tiers.sort_unstable();
+    tiers.dedup();
+    if to_stdout {
+        // nothing but the entries is written
+    } else if args.archive.is_some() || remote.is_some() {
+        // only holds the metadata written next to the archive or uploaded
+        std::fs::create_dir_all(&args.out).exit(Exit::Setup)?;
+    } else if !args.dry_run {
+        stage("directories");
+        // the directories of the prefixes are created as they're written into
+        std::fs::create_dir_all(&args.out).exit(Exit::Setup)?;
+        if let Some(tmp_dir) = &args.tmp_dir {
+            std::fs::create_dir_all(tmp_dir).exit(Exit::Setup)?;
+        }
+    }
+    stage("discover");
+    let from_stdin = stdin::is_stdin(&args.hashes) && args.hashes_file.is_none();
+    let dump = (!from_stdin)
+        .then(|| single_hash_file(&args.hashes, args.hashes_file.as_deref()))
+        .flatten()
+        .map(|path| {
+            exit::ensure!(
+                Exit::InvalidArguments,
+                !args.incremental
+                    && args.train_brotli_dictionary.is_none()
+                    && args.remove_source.is_none(),
+                "{} is a single file of every hash, which --incremental, \
+                 --train-brotli-dictionary, and --remove-source don't support",
+                path.display()
+            );
+            Dump::open(&path).exit(Exit::Setup)
+        })
+        .transpose()?;
+    let mut paths = match &dump {
+        Some(dump) => {
+            let paths = dump.paths();
+            info!(
+                "[2/3] Found {} prefixes in {}",
+                paths.len(),
+                dump.path().display()
+            );
+            paths
+        }
+        None if from_stdin => {
+            info!("[2/3] Reading the hashes from stdin");
+            Vec::new()
+        }
+        None => {
+            let glob = args.glob.as_ref().map(Glob::compile_matcher);
+            find_hash_files(&args.hashes, &args.input_extensions, glob.as_ref())
+                .exit(Exit::Setup)?
+        }
+    };
+    // ranked against every hash file, also the ones left out of this run
+    let all = args.with_rank.then(|| paths.clone());
+    if args.is_subset() {
+        paths.retain(|path| prefix_of(path).is_some_and(|p| args.selected(p)));
+    }
     let count = paths.len() as u64;
 
-    if args.strict {
-        // HIBP has every single 5 character prefix of sha1
-        assert!(count == 16_u64.pow(5));
+    if let Some(out) = &args.train_brotli_dictionary {
+        let options = ParseOptions {
+            min_count: args.min_count,
+            strict: args.strict,
+            hash_type: args.hash_type,
+            hash_order: false,
+            lenient: args.lenient,
+            merge_duplicates: args.merge_duplicates,
+            count_overflow: args.count_overflow,
+        };
+        let trained = dictionary::train(&paths, args.dictionary_samples, options)?;
+        std::fs::write(out, &trained)?;
+        info!(
+            "Trained a {} byte brotli dictionary, written to {}",
+            trained.len(),
+            out.display()
+        );
+        return Ok(());
+    }
+
+    // HIBP has every single 5 character prefix of sha1
+    let expected = (0..PREFIX_COUNT).filter(|p| args.selected(*p)).count() as u64;
+    if args.strict && !from_stdin {
+        exit::ensure!(
+            Exit::Strict,
+            count == expected,
+            "expected {expected} hash files but found {count}, pass --strict false to allow this"
+        );
+    }
+
+    let checkpoint = (!args.dry_run && args.archive.is_none() && !to_stdout)
+        .then(|| Checkpoint::open(&args.out, args.resume))
+        .transpose()
+        .exit(Exit::Setup)?;
+    if let Some(checkpoint) = checkpoint.as_ref().filter(|c| !c.is_empty()) {
+        paths.retain(|path| prefix_of(path).is_none_or(|prefix| !checkpoint.is_done(prefix)));
+        info!(
+            "Resuming, skipping {} already completed prefixes",
+            checkpoint.len()
+        );
     }
 
+    let state = if args.incremental {
+        let state = SourceState::load(&args.out, args.output_settings()).exit(Exit::Setup)?;
+        let before = paths.len();
+        paths.retain(|path| match (prefix_of(path), Stamp::of(path)) {
+            (Some(prefix), Ok(stamp)) => !state.is_unchanged(prefix, stamp),
+            _ => true,
+        });
+        info!(
+            "Skipping {} prefixes with unchanged sources",
+            before - paths.len()
+        );
+        Some(state)
+    } else {
+        None
+    };
+
+    let partial = args.resume || args.incremental || args.is_subset();
+    let manifest = if args.manifest && !to_stdout {
+        Some(ManifestWriter::new(&args.out, partial).exit(Exit::Setup)?)
+    } else {
+        None
+    };
+
     let (json, brotli, gzip) = (args.json, args.brotli, args.gzip);
+    let dcb = dictionary.is_some();
 
-    println!(
-        "{} Generating{}{}{} files ",
-        style("[3/3]").bold().dim(),
-        if json { " .json" } else { "" },
-        if brotli { " .br" } else { "" },
-        if gzip { " .gz" } else { "" }
-    );
+    let archive = args
+        .archive
+        .filter(|_| !args.dry_run)
+        .map(|format| Archive::create(&args.out, format, args.reproducible))
+        .transpose()
+        .exit(Exit::Setup)?;
 
-    let dist = args.out.as_path();
+    let previous = args
+        .previous
+        .as_deref()
+        .filter(|_| !args.dry_run)
+        .map(|previous| Previous::open(&args.out, previous))
+        .transpose()
+        .exit(Exit::Setup)?;
+
+    let ndjson = to_stdout.then(|| Ndjson::new(args.stdout_format));
+    let summary = args.summary.then(Summary::default);
+    let memory = args.max_memory.map(Memory::new);
+    let filter = HashFilter::read(args.only_hashes.as_deref(), args.exclude_hashes.as_deref())
+        .exit(Exit::Setup)?;
+    let encryption = args
+        .encrypt
+        .as_ref()
+        .map(Encryption::new)
+        .transpose()
+        .exit(Exit::Setup)?;
+    let mut generator = Generator {
+        dist: &args.out,
+        depth,
+        template: args.path_template.as_ref(),
+        json,
+        gzip,
+        brotli,
+        parse: ParseOptions {
+            min_count: args.min_count,
+            strict: args.strict,
+            hash_type: args.hash_type,
+            hash_order: false,
+            lenient: args.lenient,
+            merge_duplicates: args.merge_duplicates,
+            count_overflow: args.count_overflow,
+        },
+        filter: filter.as_ref(),
+        top: args.top,
+        sort_by: args.sort_by,
+        case: args.case,
+        on_existing: args.on_existing,
+        fields: Fields {
+            hash: args.hash_field.clone(),
+            count: args.count_field.clone(),
+            ranks: None,
+        },
+        tiers: tiers.clone(),
+        gzip_level: args.gzip_level,
+        gzip_engine: args.gzip_engine,
+        reproducible: args.reproducible,
+        brotli_params: compress::brotli_params(args.brotli_quality, args.reproducible),
+        dictionary,
+        encryption,
+        // a hash file is only removed once its outputs are known to decode
+        self_check: args.self_check || args.remove_source.is_some(),
+        skip_larger_compressed: args.skip_larger_compressed,
+        fast_json: args.fast_json,
+        remove_source: args.remove_source,
+        mmap: args.mmap,
+        stream: args.stream,
+        io_backend: args.io_backend,
+        dump: dump.as_ref(),
+        archive: archive.as_ref(),
+        tmp_dir: args.tmp_dir.as_deref(),
+        remote: remote.filter(|_| !args.dry_run),
+        ndjson: ndjson.as_ref(),
+        summary: summary.as_ref(),
+        previous: previous.as_ref(),
+        memory: memory.as_ref(),
+        checkpoint,
+        state,
+        manifest,
+        directories: Directories::default(),
+        totals: Totals::default(),
+    };
+
+    if let Some(all) = &all {
+        stage("rank");
+        info!("Ranking the counts of {} hash files", all.len());
+        let ranks = Ranks::compute(&generator, all)?;
+        generator.fields.ranks = Some(Arc::new(ranks));
+    }
+
+    if args.dry_run {
+        return dry_run(&generator, &paths, args.dry_run_samples);
+    }
+
+    // the uploaded outputs are only staged one at a time
+    if args.space_check && remote.is_none() && !to_stdout && !paths.is_empty() {
+        stage("space");
+        let extrapolate = sample(&generator, &paths, space::SAMPLES)?;
+        let bytes = generator.totals.bytes();
+        let estimate = extrapolate(bytes.json + bytes.gzip + bytes.brotli + bytes.dcb);
+        space::check(&args.out, estimate, partial)?;
+        generator.totals = Totals::default();
+    }
+
+    if let Some(dictionary) = generator.dictionary.as_ref().filter(|_| !to_stdout) {
+        // served to dictionary-aware clients along with the .dcb files
+        std::fs::write(args.out.join(Dictionary::FILE_NAME), dictionary.content())?;
+    }
+
+    if to_stdout {
+        info!("[3/3] Writing the entries to stdout");
+    } else {
+        info!(
+            "[3/3] Generating{}{}{}{} files",
+            if json { " .json" } else { "" },
+            if brotli { " .br" } else { "" },
+            if gzip { " .gz" } else { "" },
+            if dcb { " .dcb" } else { "" }
+        );
+    }
 
     let start = Instant::now();
 
-    let total_json = AtomicU64::new(0);
-    let total_gz = AtomicU64::new(0);
-    let total_br = AtomicU64::new(0);
+    let total = paths.len() as u64;
+    // prefixes vary a lot in size, their bytes give a steadier eta than their amount
+    let total_bytes: u64 = paths.par_iter().map(|p| generator.input_size(p)).sum();
+    let bar = match args.progress {
+        // the size of stdin isn't known ahead of time
+        ProgressMode::Bar if !args.quiet && stderr().is_terminal() && from_stdin => {
+            ProgressBar::no_length().with_style(
+                ProgressStyle::with_template(
+                    "{elapsed} {spinner} {binary_bytes} {binary_bytes_per_sec} ",
+                )
+                .unwrap(),
+            )
+        }
+        ProgressMode::Bar if !args.quiet && stderr().is_terminal() => {
+            ProgressBar::new(total_bytes).with_style(progress_style())
+        }
+        _ => ProgressBar::hidden(),
+    };
 
-    paths
-        .into_par_iter()
-        .progress_with_style(progress_style())
-        .for_each(|path| {
-            let prefix = path.file_stem().and_then(OsStr::to_str).unwrap();
-            let mut passwords = Vec::with_capacity(2048);
-
-            let content = std::fs::read(&path).unwrap();
-            for line in content.lines().map(Result::unwrap) {
-                let mut hash = String::with_capacity(40);
-                let (h, c) = line.split_once(':').unwrap();
-                let count = c.parse().unwrap();
-                hash.push_str(prefix);
-                hash.push_str(h);
-                passwords.push(Password { hash, count });
-            }
+    let failures = Mutex::new(Vec::new());
+    let mut read = 0;
+    let process = || {
+        if from_stdin {
+            let reader = BufReader::with_capacity(1 << 20, std::io::stdin());
+            read = stdin::run(
+                &generator,
+                reader,
+                args.read_ahead,
+                |p| args.selected(p),
+                &bar,
+            )?;
+            return Ok(());
+        }
 
-            let prefix = format_prefix_to_dirs(prefix);
+        if let Some(io_threads) = args.io_threads {
+            let retries = args.keep_going.then_some(args.retries);
+            return pipeline::run(
+                &generator,
+                paths,
+                io_threads,
+                args.read_ahead,
+                bar,
+                retries,
+                &failures,
+            );
+        }
 
-            let serialized = serde_json::to_vec(&passwords).unwrap();
+        let finish = |path: &Path| {
+            let size = generator.input_size(path);
+            generator.totals.finish(size);
+            bar.inc(size);
+        };
+        let paths = paths.into_par_iter();
+        let result = if args.keep_going {
+            paths.for_each_init(Buffers::default, |buffers, path| {
+                let result = generator.process_with_retries(&path, buffers, args.retries);
+                finish(&path);
+                if let Err(err) = result {
+                    failures.lock().unwrap().push((path, err));
+                }
+            });
+            Ok(())
+        } else {
+            paths.try_for_each_init(Buffers::default, |buffers, path| {
+                generator.process(&path, buffers)?;
+                finish(&path);
+                Ok::<_, GenerateError>(())
+            })
+        };
+        bar.finish();
+        result
+    };
 
-            if json {
-                let content = serialized.as_slice();
-                std::fs::write(dist.join(format!("{prefix}.json")), content).unwrap();
-                total_json.fetch_add(content.len() as u64, Ordering::SeqCst);
-            }
+    stage("generate");
+    let generated = match args.progress {
+        ProgressMode::Bar => process(),
+        ProgressMode::Json => progress::report_json(
+            &generator.totals,
+            total,
+            total_bytes,
+            Duration::from_secs(1),
+            process,
+        ),
+    };
+    // prefixes are only known as they're read from stdin
+    let total = if from_stdin { read } else { total };
+    if let (Err(err), Some(path)) = (&generated, &args.report) {
+        let failures = failures.lock().unwrap();
+        Report::new(&generator.totals, total, &failures, very_start, start)
+            .failed(err)
+            .write(path)?;
+    }
+    generated?;
+    if args.strict && from_stdin {
+        exit::ensure!(
+            Exit::Strict,
+            read == expected,
+            "expected {expected} prefixes but read {read} from stdin, pass --strict false to allow \
+             this"
+        );
+    }
 
-            if gzip {
-                let file = File::create(dist.join(format!("{prefix}.json.gz"))).unwrap();
-                let mut buf: BufWriter<File> = BufWriter::new(file);
-                let mut enc = flate2::write::GzEncoder::new(&mut buf, Compression::best());
-                enc.write_all(&serialized).unwrap();
-                enc.finish().unwrap();
+    let Generator {
+        state,
+        manifest,
+        totals,
+        encryption,
+        ..
+    } = generator;
 
-                let f = buf.into_inner().unwrap();
-                let size = f.metadata().unwrap().len();
-                total_gz.fetch_add(size, Ordering::SeqCst);
-            }
+    stage("finish");
+    if let Some(state) = state {
+        state.save()?;
+    }
 
-            if brotli {
-                let mut serialized = std::io::Cursor::new(serialized);
-                let file = File::create(dist.join(format!("{prefix}.json.br"))).unwrap();
-                let mut buf: BufWriter<File> = BufWriter::new(file);
-                let size = brotli::BrotliCompress(
-                    &mut serialized,
-                    &mut buf,
-                    &brotli::enc::BrotliEncoderInitParams(),
-                )
-                .unwrap();
-                total_br.fetch_add(size as u64, Ordering::SeqCst);
-            }
-        });
+    let bytes = totals.bytes();
+    let formats = [
+        (json, "json"),
+        (gzip, "gzip"),
+        (brotli, "brotli"),
+        (dcb, "dcb"),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, format)| enabled.then_some(format))
+    .collect();
+    let mut dataset = Dataset::new(formats, tiers, totals.entries.load(Ordering::SeqCst), bytes);
+    dataset.hash_type = args.hash_type.name();
+    dataset.fields = FieldNames {
+        hash: args.hash_field.clone(),
+        count: args.count_field.clone(),
+    };
+    dataset.encryption = encryption.as_ref().map(Encryption::name);
+    if let Some(ndjson) = ndjson {
+        ndjson.flush().context("unable to write to stdout")?;
+    } else {
+        dataset.write(&args.out)?;
+    }
+    if let Some(summary) = summary {
+        let rows = summary.write(&args.out)?;
+        debug!("Wrote the summary of {rows} prefixes");
+    }
 
-    println!(
+    let site = if args.static_site {
+        static_site::write(
+            &args.out,
+            depth,
+            args.path_template.as_ref(),
+            &args.hash_field,
+            &args.count_field,
+            &dataset,
+        )?
+    } else {
+        Vec::new()
+    };
+
+    if let Some(manifest) = manifest {
+        manifest.save()?;
+    }
+
+    if let Some(key) = sign_key {
+        let public = sign::sign_file(&key, &args.out.join(Manifest::FILE_NAME))?;
+        info!("Signed manifest, public key: {public}");
+    }
+
+    if let Some(previous) = &previous {
+        info!(
+            "Linked {} outputs identical to {}, saving {}",
+            previous.linked.load(Ordering::SeqCst),
+            previous.path().display(),
+            HumanBytes(previous.saved.load(Ordering::SeqCst))
+        );
+    }
+
+    if let Some(archive) = archive {
+        // the metadata describes the archived files, so it goes along with them
+        for path in site.iter().cloned().chain(metadata_files(&args.out)) {
+            archive.append_file(&path)?;
+        }
+
+        let path = archive.finish()?;
+        info!("Wrote the generated files to {}", path.display());
+    }
+
+    if let Some(remote) = remote {
+        // uploaded last, so it never describes files that aren't there yet
+        remote.flush()?;
+        for path in site.iter().cloned().chain(metadata_files(&args.out)) {
+            remote
+                .put_file(&path)
+                .with_context(|| format!("unable to upload {}", path.display()))?;
+        }
+        remote.flush()?;
+        info!("Uploaded the generated files to {}", remote.url());
+    }
+
+    info!(
         "Finished generating files in {}ms ({}ms total)",
-        style(start.elapsed().as_millis()).bold(),
+        start.elapsed().as_millis(),
         very_start.elapsed().as_millis()
     );
 
-    println!(
-        "Bytes: json {} | br {} | gz {}",
-        total_json.into_inner(),
-        total_br.into_inner(),
-        total_gz.into_inner()
+    info!(
+        "Bytes: json {} | br {} | gz {} | dcb {}",
+        dataset.bytes.json, dataset.bytes.brotli, dataset.bytes.gzip, dataset.bytes.dcb
     );
+    let skipped = totals.skipped.load(Ordering::SeqCst);
+    if skipped > 0 {
+        info!("Skipped {skipped} malformed lines with --lenient");
+    }
+    totals.times.report();
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by(|a, b| a.0.cmp(&b.0));
+    if let Some(path) = &args.report {
+        Report::new(&totals, total, &failures, very_start, start).write(path)?;
+    }
+    if !failures.is_empty() {
+        let count = failures.len();
+        error!("{count} prefixes failed, re-run with --resume to retry only them:");
+        for (_, err) in failures {
+            error!("  {:#}", anyhow::Error::from(err));
+        }
+
+        return Err(exit::classify(
+            Exit::Partial,
+            anyhow::anyhow!("{count} prefixes failed"),
+        ));
+    }
+
+    // last, so the link only ever points at a complete dataset
+    if let Some(label) = &args.version_label {
+        let versions = args.out.parent().unwrap_or(Path::new(""));
+        let link = versions.join(CURRENT_LINK);
+        versioned::switch_current(versions, label)
+            .with_context(|| format!("unable to point {} at {label}", link.display()))?;
+        info!("Pointed {} at {label}", link.display());
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::{aead::Aead, aead::Payload, Aes256Gcm, Key, KeyInit, Nonce};
+
+    use super::*;
+
+    const A: &str = "0018A45C4D1DEF81644B54AB7F969B88D65";
+    const B: &str = "00D4F6E8FA6EECAD2A3AA415EEC418D38EC";
+
+    /// An empty directory unique to the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hibp-json-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Parses the `flags` like `run` does, for the hash files and outputs in `dir`.
+    fn parse(dir: &Path, flags: &[&str]) -> Result<Args> {
+        let (hashes, out) = (dir.join("hashes"), dir.join("dist"));
+        let mut argv = vec![
+            "hibp-json",
+            "--hashes",
+            path_str(&hashes),
+            "--out",
+            path_str(&out),
+        ];
+        argv.extend(["--strict", "false", "--quiet"]);
+        argv.extend(flags);
+        let matches = Args::command().try_get_matches_from(argv)?;
+        let mut args = Args::from_arg_matches(&matches)?;
+        Config::default().generate.apply(&mut args, &matches)?;
+        Ok(args)
+    }
+
+    fn path_str(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    /// Generates the hash files `hashes` of `dir` with the `flags`, into `dir/dist`.
+    fn generate_in(dir: &Path, hashes: &[(&str, &str)], flags: &[&str]) -> Result<PathBuf> {
+        let hashes_dir = dir.join("hashes");
+        std::fs::create_dir_all(&hashes_dir)?;
+        for (prefix, content) in hashes {
+            std::fs::write(hashes_dir.join(format!("{prefix}.txt")), content)?;
+        }
+
+        let args = parse(dir, flags)?;
+        generate(&args, None, Instant::now())?;
+        Ok(args.out)
+    }
+
+    #[test]
+    fn resume_skips_done_prefixes() {
+        let dir = temp_dir("resume");
+        let (a, b) = (format!("{A}:2\r\n"), format!("{B}:3\r\n"));
+        let dist = generate_in(&dir, &[("00000", &a), ("00001", &b)], &[]).unwrap();
+        let done = std::fs::read(dist.join("0/0/0/0/0.json")).unwrap();
+
+        // as if it was interrupted after the first prefix, which changed since
+        std::fs::write(dist.join(Checkpoint::FILE_NAME), "00000\n").unwrap();
+        std::fs::remove_file(dist.join("0/0/0/0/1.json")).unwrap();
+        let changed = format!("{A}:9\r\n");
+        generate_in(&dir, &[("00000", &changed)], &["--resume"]).unwrap();
+
+        assert_eq!(std::fs::read(dist.join("0/0/0/0/0.json")).unwrap(), done);
+        assert!(dist.join("0/0/0/0/1.json").exists());
+        let checkpoint = std::fs::read_to_string(dist.join(Checkpoint::FILE_NAME)).unwrap();
+        assert_eq!(checkpoint, "00000\n00001\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_of_every_file() {
+        let dir = temp_dir("manifest");
+        let content = format!("{A}:2\r\n{B}:3\r\n");
+        let dist = generate_in(&dir, &[("00000", &content)], &["--gzip", "true"]).unwrap();
+
+        let manifest = Manifest::read(&dist).unwrap();
+        assert!(manifest.files.contains_key("0/0/0/0/0.json"));
+        assert!(manifest.files.contains_key("0/0/0/0/0.json.gz"));
+        for (name, entry) in &manifest.files {
+            let data = std::fs::read(dist.join(name)).unwrap();
+            assert_eq!(entry.size, data.len() as u64, "{name}");
+            assert_eq!(entry.sha256, manifest::sha256(&data), "{name}");
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fast_json_like_serde_json() {
+        let content = format!("{A}:2\r\n{B}:3\r\n");
+        let hashes = [
+            ("00000", content.as_str()),
+            ("00001", "0000000000000000000000000000000000A:2\r\n"),
+        ];
+        for flags in [
+            &[][..],
+            &["--with-rank"],
+            &["--hash-field", "h\"", "--count-field", "c"],
+        ] {
+            let (serde, fast) = (temp_dir("serde-json"), temp_dir("fast-json"));
+            let serde_dist = generate_in(&serde, &hashes, flags).unwrap();
+            let fast_dist =
+                generate_in(&fast, &hashes, &[flags, &["--fast-json"]].concat()).unwrap();
+            for prefix in ["0/0/0/0/0.json", "0/0/0/0/1.json"] {
+                let json = std::fs::read_to_string(serde_dist.join(prefix)).unwrap();
+                assert_eq!(
+                    std::fs::read_to_string(fast_dist.join(prefix)).unwrap(),
+                    json,
+                    "{flags:?}"
+                );
+                assert_eq!(json.contains("\"percentile\":"), flags == ["--with-rank"]);
+            }
+
+            std::fs::remove_dir_all(serde).unwrap();
+            std::fs::remove_dir_all(fast).unwrap();
+        }
+    }
+
+    #[test]
+    fn on_existing_needs_the_outputs_checked() {
+        let dir = temp_dir("on-existing");
+        for flag in ["--stream", "--archive=tar", "--incremental"] {
+            for on_existing in ["skip", "error"] {
+                let flags = ["--on-existing", on_existing, flag];
+                assert!(parse(&dir, &flags).is_err(), "{on_existing} {flag}");
+            }
+            assert!(parse(&dir, &["--on-existing", "overwrite", flag]).is_ok());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn self_check_reads_back_the_files() {
+        let dir = temp_dir("self-check");
+        let content = format!("{A}:2\r\n{B}:3\r\n");
+        let dist = generate_in(
+            &dir,
+            &[("00000", &content)],
+            &["--self-check", "--gzip", "true"],
+        )
+        .unwrap();
+
+        let json = std::fs::read(dist.join("0/0/0/0/0.json")).unwrap();
+        assert!(json.starts_with(format!("[{{\"hash\":\"00000{A}\"").as_bytes()));
+        assert!(dist.join("0/0/0/0/0.json.gz").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn encrypt_conflicts_with_uring() {
+        let dir = temp_dir("encrypt-uring");
+        let err = parse(&dir, &["--encrypt", "aes-gcm:key", "--io-backend", "uring"]).unwrap_err();
+        let err = err.downcast_ref::<clap::Error>().unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn encrypted_outputs() {
+        let dir = temp_dir("encrypted");
+        let keyfile = dir.join("key");
+        std::fs::write(&keyfile, [7; 32]).unwrap();
+        let encrypt = format!("aes-gcm:{}", keyfile.display());
+        let dist = generate_in(
+            &dir,
+            &[("00000", &format!("{A}:2\r\n{B}:3\r\n"))],
+            &[
+                "--encrypt",
+                &encrypt,
+                "--gzip",
+                "false",
+                "--brotli",
+                "false",
+            ],
+        )
+        .unwrap();
+
+        // no plaintext is left behind, only the encrypted file under the same name
+        let encrypted = std::fs::read(dist.join("0/0/0/0/0.json")).unwrap();
+        assert!(!encrypted.windows(A.len()).any(|w| w == A.as_bytes()));
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&[7; 32]));
+        let (nonce, ciphertext) = encrypted.split_at(12);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: b"0/0/0/0/0.json",
+        };
+        let json = cipher.decrypt(Nonce::from_slice(nonce), payload).unwrap();
+        let entries: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(entries[0]["hash"], format!("00000{A}"));
+        assert_eq!(entries[1]["count"], 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,3 +1,11 @@
-fn main() -> anyhow::Result<()> {
-    hibp_json::run()
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    match hibp_json::run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(hibp_json::exit_code(&err))
+        }
+    }
 }
@@ -0,0 +1,144 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Size and SHA-256 of every generated file, keyed by its path relative to the output directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Manifest {
+    pub const FILE_NAME: &'static str = "manifest.json";
+
+    pub fn read(dist: &Path) -> Result<Self> {
+        let file = File::open(dist.join(Self::FILE_NAME))?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+}
+
+/// Collects manifest entries from the parallel workers.
+pub struct ManifestWriter {
+    dist: PathBuf,
+    manifest: Mutex<Manifest>,
+}
+
+impl ManifestWriter {
+    /// Starts a manifest for `dist`, building on top of the existing one if `extend` is set.
+    pub fn new(dist: &Path, extend: bool) -> Result<Self> {
+        let manifest = if extend && dist.join(Manifest::FILE_NAME).exists() {
+            Manifest::read(dist)?
+        } else {
+            Manifest::default()
+        };
+
+        Ok(Self {
+            dist: dist.into(),
+            manifest: Mutex::new(manifest),
+        })
+    }
+
+    /// Builds on top of the manifest of `dist` if there is one, to keep it in step with the files
+    /// next to it when they're changed.
+    pub fn open_existing(dist: &Path) -> Result<Option<Self>> {
+        dist.join(Manifest::FILE_NAME)
+            .exists()
+            .then(|| Self::new(dist, true))
+            .transpose()
+    }
+
+    fn key(&self, path: &Path) -> String {
+        key(&self.dist, path)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<ManifestEntry> {
+        let key = self.key(path);
+        self.manifest.lock().unwrap().files.get(&key).cloned()
+    }
+
+    pub fn insert(&self, path: &Path, entry: ManifestEntry) {
+        let key = self.key(path);
+        self.manifest.lock().unwrap().files.insert(key, entry);
+    }
+
+    pub fn remove(&self, path: &Path) {
+        let key = self.key(path);
+        self.manifest.lock().unwrap().files.remove(&key);
+    }
+
+    pub fn save(self) -> Result<()> {
+        let manifest = self.manifest.into_inner().unwrap();
+        let path = self.dist.join(Manifest::FILE_NAME);
+        let tmp = path.with_extension("json.tmp");
+
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut out, &manifest)?;
+        out.flush()?;
+        drop(out);
+
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// The manifest key of `path`, relative to the output directory `dist` with forward slashes.
+pub fn key(dist: &Path, path: &Path) -> String {
+    path.strip_prefix(dist)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Lowercase hex SHA-256 of `data`.
+pub fn sha256(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Passes writes through to `inner` while optionally hashing them.
+pub struct HashWriter<W> {
+    inner: W,
+    hasher: Option<Sha256>,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W, hash: bool) -> Self {
+        Self {
+            inner,
+            hasher: hash.then(Sha256::new),
+        }
+    }
+
+    /// Returns the inner writer and the lowercase hex SHA-256 of everything written, if hashing.
+    pub fn finish(self) -> (W, Option<String>) {
+        let digest = self.hasher.map(|h| format!("{:x}", h.finalize()));
+        (self.inner, digest)
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,92 @@
+//! Bounds the memory of a run, so generation fits in small containers instead of being killed
+//! when brotli falls behind and prefixes pile up.
+
+use std::sync::{Condvar, Mutex};
+
+/// Peak memory of generating a prefix relative to the size of its hash file: the content, the
+/// parsed passwords, the serialized JSON, and a compressed copy per format.
+const FACTOR: u64 = 8;
+
+/// Parses a size like `2G`, `512M`, `64K`, or plain bytes, in powers of 1024.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (digits, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => size.split_at(i),
+        None => (size, ""),
+    };
+    let shift = match unit
+        .trim()
+        .to_ascii_uppercase()
+        .trim_end_matches(['B', 'I'])
+    {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("unknown unit in {size:?}, expected K, M, G, or T")),
+    };
+    let number: u64 = digits
+        .parse()
+        .map_err(|_| format!("{size:?} isn't a size like 2G or 512M"))?;
+
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("{size:?} is too large"))
+}
+
+/// The memory the prefixes being generated may take up together.
+pub struct Memory {
+    limit: u64,
+    used: Mutex<u64>,
+    freed: Condvar,
+}
+
+impl Memory {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Waits until the memory to generate a hash file of `size` bytes is available, reserving it
+    /// until the returned [`Reservation`] is dropped.
+    ///
+    /// A hash file too large for the limit by itself waits until nothing else is generated.
+    pub fn reserve(&self, size: u64) -> Reservation<'_> {
+        let amount = size.saturating_mul(FACTOR).min(self.limit);
+        let mut used = self
+            .freed
+            .wait_while(self.used.lock().unwrap(), |used| {
+                *used + amount > self.limit
+            })
+            .unwrap();
+        *used += amount;
+
+        Reservation {
+            memory: self,
+            amount,
+        }
+    }
+
+    /// The most every buffer of a worker keeps allocated between prefixes, out of `workers`.
+    pub fn buffer_cap(&self, workers: usize) -> usize {
+        let cap = self.limit / FACTOR / workers.max(1) as u64;
+        usize::try_from(cap).unwrap_or(usize::MAX)
+    }
+}
+
+/// Memory reserved by [`Memory::reserve`], released when dropped.
+pub struct Reservation<'a> {
+    memory: &'a Memory,
+    amount: u64,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.memory.used.lock().unwrap() -= self.amount;
+        self.memory.freed.notify_all();
+    }
+}
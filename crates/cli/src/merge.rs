@@ -0,0 +1,160 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    convert::Infallible,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use tracing::info;
+
+use crate::{
+    error::GenerateError,
+    find_all_hash_files,
+    generate::{parse_lines, write_atomic, CountOverflow, HashType, ParseError, ParseOptions},
+    input::{self, Compression},
+    prefix_of,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct MergeArgs {
+    /// Directories of hash files to merge
+    #[arg(required = true, num_args = 2..)]
+    sources: Vec<PathBuf>,
+
+    /// Directory to write the merged hash files to
+    #[arg(short, long)]
+    out: PathBuf,
+}
+
+/// Merges the hash files of a single prefix into `out`, summing the counts of hashes found in
+/// more than one of them. Returns the amount of hashes written.
+fn merge_prefix(paths: &[PathBuf], out: &Path) -> Result<u64> {
+    let contents = paths
+        .iter()
+        .map(|path| {
+            input::read_all(path).map_err(|source| GenerateError::Read {
+                path: path.clone(),
+                source,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let options = ParseOptions {
+        min_count: 0,
+        strict: false,
+        hash_type: HashType::Sha1,
+        hash_order: false,
+        lenient: false,
+        merge_duplicates: false,
+        count_overflow: CountOverflow::Error,
+    };
+    let mut lines = Vec::new();
+    for (path, content) in paths.iter().zip(&contents) {
+        parse_lines(content, options, |suffix, count| {
+            // other corpora aren't necessarily uppercase like HIBP
+            let suffix = if suffix.bytes().any(|b| b.is_ascii_lowercase()) {
+                Cow::Owned(suffix.to_ascii_uppercase())
+            } else {
+                Cow::Borrowed(suffix)
+            };
+            lines.push((suffix, count));
+            Ok::<_, Infallible>(())
+        })
+        .map_err(|err| match err {
+            ParseError::Line(line, kind) => GenerateError::Parse {
+                path: path.clone(),
+                line,
+                kind,
+            },
+            ParseError::Sink(never) => match never {},
+        })?;
+    }
+
+    lines.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    lines.dedup_by(|next, kept| {
+        let duplicate = next.0 == kept.0;
+        if duplicate {
+            kept.1 = kept.1.saturating_add(next.1);
+        }
+        duplicate
+    });
+
+    write_atomic(out, None, |file| {
+        for (suffix, count) in &lines {
+            write!(file, "{suffix}:{count}\r\n")?;
+        }
+        Ok(())
+    })
+    .with_context(|| format!("unable to write {}", out.display()))?;
+
+    Ok(lines.len() as u64)
+}
+
+pub fn run(args: &MergeArgs) -> Result<()> {
+    let mut prefixes: BTreeMap<u32, Vec<PathBuf>> = BTreeMap::new();
+    for source in &args.sources {
+        for path in find_all_hash_files(source)? {
+            if let Some(prefix) = prefix_of(&path) {
+                prefixes.entry(prefix).or_default().push(path);
+            }
+        }
+    }
+
+    std::fs::create_dir_all(&args.out)?;
+    let total = prefixes.len() as u64;
+    let entries = prefixes
+        .into_par_iter()
+        .progress_count(total)
+        .map(|(_, paths)| {
+            // named like the first source that has the prefix, e.g. ABCDE.txt, but uncompressed
+            let first = &paths[0];
+            let name = match Compression::of(first) {
+                Some(_) => first.file_stem(),
+                None => first.file_name(),
+            }
+            .unwrap_or_default();
+            merge_prefix(&paths, &args.out.join(name))
+        })
+        .try_reduce(|| 0, |a, b| Ok(a + b))?;
+
+    info!(
+        "Merged {total} prefixes with {entries} hashes into {}",
+        args.out.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &str = "0018A45C4D1DEF81644B54AB7F969B88D65";
+    const B: &str = "00D4F6E8FA6EECAD2A3AA415EEC418D38EC";
+
+    #[test]
+    fn sums_duplicates() {
+        let dir = std::env::temp_dir().join(format!("hibp-json-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (hibp, other, out) = (
+            dir.join("hibp.txt"),
+            dir.join("other.txt"),
+            dir.join("out.txt"),
+        );
+        std::fs::write(&hibp, format!("{A}:2\r\n{B}:3\r\n")).unwrap();
+        let lowercase = A.to_ascii_lowercase();
+        std::fs::write(&other, format!("{lowercase}:5\n{B}:{}\n", u64::MAX)).unwrap();
+
+        assert_eq!(merge_prefix(&[hibp, other], &out).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            format!("{A}:7\r\n{B}:{}\r\n", u64::MAX)
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
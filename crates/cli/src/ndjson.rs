@@ -0,0 +1,93 @@
+//! Writes the entries to stdout as newline-delimited JSON with `--out -`, to feed a database
+//! loader or another pipeline stage directly instead of writing any files.
+
+use std::{
+    io::{self, BufWriter, Stdout, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fields::{Entries, Fields},
+    Password,
+};
+
+/// The `--out` that writes to stdout.
+pub const PATH: &str = "-";
+
+/// Whether `out` is [`PATH`].
+pub fn is_stdout(out: &Path) -> bool {
+    out.as_os_str() == PATH
+}
+
+/// How the entries are framed on stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StdoutFormat {
+    /// A line per entry, e.g. {"hash":"...","count":3}
+    Entries,
+    /// A line per prefix with all of its entries, e.g. {"prefix":"ABCDE","entries":[...]}
+    Prefixes,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    prefix: &'a str,
+    entries: Entries<'a>,
+}
+
+/// Stdout shared by the workers, which write all lines of a prefix at once so that they never
+/// interleave. Prefixes are written in the order they finish, not in the order of their hashes.
+pub struct Ndjson {
+    format: StdoutFormat,
+    out: Mutex<BufWriter<Stdout>>,
+}
+
+impl Ndjson {
+    pub fn new(format: StdoutFormat) -> Self {
+        Self {
+            format,
+            out: Mutex::new(BufWriter::with_capacity(1 << 20, io::stdout())),
+        }
+    }
+
+    /// Serializes the entries of `prefix` into `serialized` as its lines.
+    pub fn serialize(
+        &self,
+        prefix: &str,
+        fields: &Fields,
+        passwords: &[Password],
+        serialized: &mut Vec<u8>,
+    ) -> serde_json::Result<()> {
+        serialized.clear();
+        match self.format {
+            StdoutFormat::Entries => {
+                for password in passwords {
+                    serde_json::to_writer(&mut *serialized, &fields.entry(password))?;
+                    serialized.push(b'\n');
+                }
+            }
+            StdoutFormat::Prefixes => {
+                let record = Record {
+                    prefix,
+                    entries: fields.entries(passwords),
+                };
+                serde_json::to_writer(&mut *serialized, &record)?;
+                serialized.push(b'\n');
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the lines serialized by [`Self::serialize`].
+    pub fn write(&self, serialized: &[u8]) -> io::Result<()> {
+        self.out.lock().unwrap().write_all(serialized)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.out.lock().unwrap().flush()
+    }
+}
@@ -0,0 +1,73 @@
+//! Lowers the cpu and I/O priority of a run, so a regeneration on a production box doesn't
+//! starve the server next to it.
+
+use std::io;
+
+/// Lowers the priority of the calling thread, which every thread it spawns afterwards inherits,
+/// so this runs before any worker or I/O thread is started.
+pub fn lower_priority() -> io::Result<()> {
+    imp::lower_priority()
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::io;
+
+    /// The lowest cpu priority.
+    const NICENESS: libc::c_int = 19;
+    /// `IOPRIO_PRIO_VALUE(IOPRIO_CLASS_IDLE, 0)`, only served when the disk is otherwise idle.
+    const IOPRIO_IDLE: libc::c_long = 3 << 13;
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+
+    pub fn lower_priority() -> io::Result<()> {
+        // on Linux both only apply to the calling thread, and are inherited by new threads
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, NICENESS) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_IDLE) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::io;
+
+    /// `PRIO_DARWIN_PROCESS` and `PRIO_DARWIN_BG` from `sys/resource.h`, the background QoS
+    /// which throttles both cpu and I/O.
+    const PRIO_DARWIN_PROCESS: libc::c_int = 4;
+    const PRIO_DARWIN_BG: libc::c_int = 0x1000;
+
+    pub fn lower_priority() -> io::Result<()> {
+        if unsafe { libc::setpriority(PRIO_DARWIN_PROCESS, 0, PRIO_DARWIN_BG) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+mod imp {
+    use std::io;
+
+    pub fn lower_priority() -> io::Result<()> {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::io;
+
+    pub fn lower_priority() -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--nice is only supported on unix",
+        ))
+    }
+}
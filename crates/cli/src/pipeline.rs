@@ -0,0 +1,83 @@
+use std::{
+    path::PathBuf,
+    sync::{mpsc, Mutex},
+};
+
+use indicatif::ProgressBar;
+use rayon::iter::{ParallelBridge, ParallelIterator};
+
+use crate::{
+    error::GenerateError,
+    generate::{Buffers, Generator},
+};
+
+/// Hash files that failed in `--keep-going` mode.
+pub type Failures = Mutex<Vec<(PathBuf, GenerateError)>>;
+
+/// Generates `paths` with a dedicated pool of `io_threads` reading up to `read_ahead` hash files
+/// ahead of the rayon workers, so that neither the disk nor the cpu sits idle waiting on the
+/// other.
+///
+/// With `retries` set, failed hash files are retried and collected into `failures` instead of
+/// stopping at the first error.
+pub fn run(
+    generator: &Generator,
+    paths: Vec<PathBuf>,
+    io_threads: usize,
+    read_ahead: usize,
+    bar: ProgressBar,
+    retries: Option<u32>,
+    failures: &Failures,
+) -> Result<(), GenerateError> {
+    let queue = Mutex::new(paths.into_iter());
+    let (tx, rx) = mpsc::sync_channel(read_ahead);
+
+    std::thread::scope(|scope| {
+        for _ in 0..io_threads {
+            let tx = tx.clone();
+            let queue = &queue;
+            scope.spawn(move || loop {
+                let Some(path) = queue.lock().unwrap().next() else {
+                    break;
+                };
+
+                // the receiver is gone once the cpu stage stopped on an error
+                if tx.send(generator.load(path)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        let loaded = rx.into_iter().par_bridge();
+        let result = loaded.try_for_each_init(Buffers::default, |buffers, loaded| {
+            let size = match &loaded {
+                Ok(loaded) => generator.input_size(&loaded.path),
+                Err((path, _)) => generator.input_size(path),
+            };
+            let result = match loaded {
+                Ok(loaded) => generator
+                    .process_loaded(&loaded, buffers)
+                    .map_err(|err| (loaded.path, err)),
+                Err(failed) => Err(failed),
+            };
+            generator.totals.finish(size);
+            bar.inc(size);
+
+            match (result, retries) {
+                (Ok(()), _) => Ok(()),
+                (Err((path, _)), Some(retries)) => {
+                    // a fresh attempt reads the hash file again
+                    if let Err(err) = generator.process_with_retries(&path, buffers, retries) {
+                        failures.lock().unwrap().push((path, err));
+                    }
+                    Ok(())
+                }
+                (Err((_, err)), None) => Err(err),
+            }
+        });
+
+        bar.finish();
+        result
+    })
+}
@@ -0,0 +1,82 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use tracing::debug;
+
+use crate::{
+    manifest::{self, Manifest, ManifestEntry},
+    with_suffix,
+};
+
+/// The output directory of a previous dataset version, whose files unchanged outputs are
+/// hardlinked to instead of taking up the disk space again.
+///
+/// Files are matched by the size and SHA-256 in its manifest, so they're never read.
+pub struct Previous {
+    dist: PathBuf,
+    previous: PathBuf,
+    manifest: Manifest,
+    /// Amount and size of the outputs that were linked
+    pub linked: AtomicU64,
+    pub saved: AtomicU64,
+}
+
+impl Previous {
+    /// Opens the `previous` version of the output directory `dist`.
+    pub fn open(dist: &Path, previous: &Path) -> Result<Self> {
+        let manifest = Manifest::read(previous).with_context(|| {
+            format!(
+                "unable to read the manifest of the previous version {}",
+                previous.display()
+            )
+        })?;
+
+        Ok(Self {
+            dist: dist.into(),
+            previous: previous.into(),
+            manifest,
+            linked: AtomicU64::default(),
+            saved: AtomicU64::default(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.previous
+    }
+
+    /// Replaces the output just written to `path` with a hardlink to the previous version of it,
+    /// if that's identical to `entry`.
+    pub fn link(&self, path: &Path, entry: &ManifestEntry) -> io::Result<()> {
+        let key = manifest::key(&self.dist, path);
+        if self.manifest.files.get(&key) != Some(entry) {
+            return Ok(());
+        }
+
+        let source = self.previous.join(&key);
+        // the previous version may have been touched since its manifest was written
+        if !std::fs::metadata(&source).is_ok_and(|m| m.len() == entry.size) {
+            return Ok(());
+        }
+
+        // linked under a temporary name first, so the output is never missing
+        let tmp = with_suffix(path, ".tmp");
+        if let Err(err) = std::fs::hard_link(&source, &tmp) {
+            // e.g. the versions are on different filesystems, the copy is kept
+            debug!(
+                "Unable to link {} to {}: {err}",
+                path.display(),
+                source.display()
+            );
+            return Ok(());
+        }
+        std::fs::rename(&tmp, path)?;
+
+        self.linked.fetch_add(1, Ordering::Relaxed);
+        self.saved.fetch_add(entry.size, Ordering::Relaxed);
+        Ok(())
+    }
+}
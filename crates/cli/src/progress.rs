@@ -0,0 +1,165 @@
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{dataset::FormatBytes, generate::Totals};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressMode {
+    /// Interactive progress bar
+    Bar,
+
+    /// JSON lines on stderr, for CI systems and wrappers
+    Json,
+}
+
+/// A machine-readable progress event, written as a single JSON line.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Stage {
+        stage: &'a str,
+    },
+    Progress {
+        done: u64,
+        total: u64,
+        /// Bytes of the hash files that were processed, which the eta is based on
+        read_bytes: u64,
+        total_bytes: u64,
+        bytes: FormatBytes,
+        elapsed_secs: f64,
+        eta_secs: Option<f64>,
+    },
+}
+
+impl Event<'_> {
+    /// Writes the event to stderr, ignoring failures as progress is best effort.
+    pub fn emit(&self) {
+        if let Ok(mut line) = serde_json::to_vec(self) {
+            line.push(b'\n');
+            let _ = std::io::stderr().lock().write_all(&line);
+        }
+    }
+}
+
+/// Runs `work` while emitting a progress event every `interval`, and once more when it's done.
+///
+/// `total` is the amount of hash files and `total_bytes` their size.
+pub fn report_json<R>(
+    totals: &Totals,
+    total: u64,
+    total_bytes: u64,
+    interval: Duration,
+    work: impl FnOnce() -> R,
+) -> R {
+    let start = Instant::now();
+    let (stop, stopped) = mpsc::channel::<()>();
+
+    std::thread::scope(|scope| {
+        scope.spawn(move || loop {
+            let finished = !matches!(
+                stopped.recv_timeout(interval),
+                Err(mpsc::RecvTimeoutError::Timeout)
+            );
+            progress_event(totals, total, total_bytes, start).emit();
+            if finished {
+                break;
+            }
+        });
+
+        let result = work();
+        drop(stop);
+        result
+    })
+}
+
+fn progress_event(totals: &Totals, total: u64, total_bytes: u64, start: Instant) -> Event<'static> {
+    let done = totals.done.load(Ordering::SeqCst);
+    let read_bytes = totals.read.load(Ordering::SeqCst);
+    let elapsed = start.elapsed().as_secs_f64();
+    // prefixes vary a lot in size, their bytes give a steadier estimate than their amount
+    // unknown when reading stdin
+    let eta_secs = (read_bytes > 0 && total_bytes > 0)
+        .then(|| elapsed / read_bytes as f64 * total_bytes.saturating_sub(read_bytes) as f64);
+
+    Event::Progress {
+        done,
+        total,
+        read_bytes,
+        total_bytes,
+        bytes: totals.bytes(),
+        elapsed_secs: elapsed,
+        eta_secs,
+    }
+}
+
+/// Time spent in every stage of generating, in nanoseconds summed over the workers.
+#[derive(Debug, Default)]
+pub struct StageTimes {
+    pub read: AtomicU64,
+    pub parse: AtomicU64,
+    pub serialize: AtomicU64,
+    /// Writing the .json files
+    pub json: AtomicU64,
+    /// Compressing and writing the compressed files
+    pub gzip: AtomicU64,
+    pub brotli: AtomicU64,
+    pub dcb: AtomicU64,
+}
+
+impl StageTimes {
+    /// Runs `f`, adding the time it took to `stage`.
+    pub fn time<T>(stage: &AtomicU64, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let nanos = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        stage.fetch_add(nanos, Ordering::Relaxed);
+        result
+    }
+
+    /// The nanoseconds of every stage by its name.
+    pub fn stages(&self) -> [(&'static str, u64); 7] {
+        [
+            ("read", &self.read),
+            ("parse", &self.parse),
+            ("serialize", &self.serialize),
+            ("json", &self.json),
+            ("gzip", &self.gzip),
+            ("brotli", &self.brotli),
+            ("dcb", &self.dcb),
+        ]
+        .map(|(stage, nanos)| (stage, nanos.load(Ordering::SeqCst)))
+    }
+
+    /// Logs the time of every stage that took any, and its share of the total.
+    pub fn report(&self) {
+        let stages = self.stages();
+        let total: u64 = stages.iter().map(|(_, nanos)| nanos).sum();
+
+        let breakdown: Vec<_> = stages
+            .iter()
+            .filter(|(_, nanos)| *nanos > 0)
+            .map(|(stage, nanos)| {
+                format!(
+                    "{stage} {:.1?} ({:.0}%)",
+                    Duration::from_nanos(*nanos),
+                    *nanos as f64 * 100.0 / total.max(1) as f64
+                )
+            })
+            .collect();
+        info!(
+            "Time per stage, summed over the workers: {}",
+            breakdown.join(" | ")
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Global prevalence ranks of the counts, so clients can tell how common a password is among
+//! every breached one without their own analytics.
+
+use std::{cmp::Reverse, collections::HashMap, path::PathBuf};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    error::GenerateError,
+    generate::{Buffers, Generator},
+};
+
+#[derive(Debug)]
+pub struct Ranks {
+    /// Every distinct count, descending, with the rank of the hashes seen that many times
+    ranks: Vec<(u64, u64)>,
+    /// Hashes across every hash file
+    total: u64,
+}
+
+impl Ranks {
+    /// Counts the hashes of every hash file in `paths` by how many times they were seen.
+    ///
+    /// This ignores --min-count, --top, and the hash lists, so an entry has the same rank in
+    /// every dataset generated from the same hash files.
+    pub fn compute(generator: &Generator, paths: &[PathBuf]) -> Result<Self, GenerateError> {
+        let counts = paths
+            .par_iter()
+            .map_init(Buffers::default, |buffers, path| {
+                let mut counts = HashMap::new();
+                generator.count_histogram(path, buffers, &mut counts)?;
+                Ok(counts)
+            })
+            .try_reduce(HashMap::new, |mut a, b| {
+                for (count, hashes) in b {
+                    *a.entry(count).or_default() += hashes;
+                }
+                Ok(a)
+            })?;
+
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_unstable_by_key(|&(count, _)| Reverse(count));
+
+        // hashes seen equally often share the rank
+        let mut ranks = Vec::with_capacity(counts.len());
+        let mut above = 0;
+        for (count, hashes) in counts {
+            ranks.push((count, above + 1));
+            above += hashes;
+        }
+
+        Ok(Self {
+            ranks,
+            total: above,
+        })
+    }
+
+    /// 1-based rank of the hashes seen `count` times, one more than the hashes seen more often.
+    pub fn rank(&self, count: u64) -> u64 {
+        match self.ranks.binary_search_by(|&(c, _)| count.cmp(&c)) {
+            Ok(i) => self.ranks[i].1,
+            Err(i) => self.ranks.get(i).map_or(self.total + 1, |&(_, rank)| rank),
+        }
+    }
+
+    /// Where `rank` falls among every hash in percent, e.g. 0.01 for the top 0.01%, rounded to 6
+    /// decimals.
+    pub fn percentile(&self, rank: u64) -> f64 {
+        let percent = rank as f64 / self.total.max(1) as f64 * 100.0;
+        (percent * 1e6).round() / 1e6
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use clap::ArgAction;
+use indicatif::{HumanBytes, ParallelProgressIterator};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use tracing::info;
+
+use crate::{
+    compress::{self, GzipEngine},
+    find_generated_files,
+    generate::{write_atomic, AtomicFile},
+    manifest::ManifestWriter,
+    with_suffix,
+};
+
+#[derive(clap::Args, Debug)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CompressArgs {
+    /// Path to the generated files
+    #[arg(long, env = "HIBP_JSON_OUT", default_value = "dist")]
+    dist: PathBuf,
+
+    /// If .gz files should be generated
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    gzip: bool,
+
+    /// If .br files should be generated
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    brotli: bool,
+
+    /// If .zst files should be generated
+    #[arg(long, default_value_t = false, action = ArgAction::Set)]
+    zstd: bool,
+
+    /// Gzip compression level, from 0 to 9
+    #[arg(long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+    gzip_level: u32,
+
+    /// Gzip encoder, zopfli is a few percent smaller but orders of magnitude slower
+    #[arg(long, value_enum, default_value_t = GzipEngine::Flate2)]
+    gzip_engine: GzipEngine,
+
+    /// Brotli compression quality, from 0 to 11
+    #[arg(long, default_value_t = 11, value_parser = clap::value_parser!(u32).range(0..=11))]
+    brotli_quality: u32,
+
+    /// Zstandard compression level, from 1 to 22
+    #[arg(long, default_value_t = 19, value_parser = clap::value_parser!(i32).range(1..=22))]
+    zstd_level: i32,
+
+    /// Guarantee byte-identical outputs across runs and platforms
+    #[arg(long)]
+    reproducible: bool,
+
+    /// Keep compressed files that already exist instead of replacing them
+    #[arg(long)]
+    skip_existing: bool,
+}
+
+/// Bytes written per format.
+#[derive(Default)]
+struct Written {
+    gzip: AtomicU64,
+    brotli: AtomicU64,
+    zstd: AtomicU64,
+}
+
+/// Writes `path` with `suffix` added through `compress`, unless it exists and should be kept.
+fn write_variant(
+    args: &CompressArgs,
+    path: &Path,
+    suffix: &str,
+    manifest: Option<&ManifestWriter>,
+    total: &AtomicU64,
+    compress: impl FnOnce(&mut AtomicFile) -> std::io::Result<()>,
+) -> Result<()> {
+    let out = with_suffix(path, suffix);
+    if args.skip_existing && out.exists() {
+        return Ok(());
+    }
+
+    let bytes = write_atomic(&out, manifest, compress)
+        .with_context(|| format!("unable to write {}", out.display()))?;
+    total.fetch_add(bytes, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Writes the enabled compressed variants of the .json file at `path`.
+fn compress_file(
+    args: &CompressArgs,
+    path: &Path,
+    manifest: Option<&ManifestWriter>,
+    written: &Written,
+) -> Result<()> {
+    let data = std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+
+    if args.gzip {
+        write_variant(args, path, ".gz", manifest, &written.gzip, |out| {
+            compress::gzip(
+                out,
+                &data,
+                args.gzip_level,
+                args.gzip_engine,
+                args.reproducible,
+            )
+        })?;
+    }
+    if args.brotli {
+        let params = compress::brotli_params(args.brotli_quality, args.reproducible);
+        write_variant(args, path, ".br", manifest, &written.brotli, |out| {
+            compress::brotli(out, &data, &params)
+        })?;
+    }
+    if args.zstd {
+        write_variant(args, path, ".zst", manifest, &written.zstd, |out| {
+            compress::zstd(out, &data, args.zstd_level)
+        })?;
+    }
+
+    Ok(())
+}
+
+pub fn run(args: &CompressArgs) -> Result<()> {
+    anyhow::ensure!(
+        args.gzip || args.brotli || args.zstd,
+        "at least one of --gzip, --brotli, or --zstd must be enabled"
+    );
+
+    let paths = find_generated_files(&args.dist, "json")?;
+    info!(
+        "Compressing {} .json files in {}",
+        paths.len(),
+        args.dist.display()
+    );
+
+    let manifest = ManifestWriter::open_existing(&args.dist)?;
+
+    let written = Written::default();
+    paths
+        .par_iter()
+        .progress_count(paths.len() as u64)
+        .try_for_each(|path| compress_file(args, path, manifest.as_ref(), &written))?;
+
+    if let Some(manifest) = manifest {
+        manifest.save()?;
+    }
+
+    info!(
+        "Wrote {} of .gz, {} of .br, and {} of .zst",
+        HumanBytes(written.gzip.into_inner()),
+        HumanBytes(written.brotli.into_inner()),
+        HumanBytes(written.zstd.into_inner()),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,276 @@
+//! Uploads the generated files straight to object storage, instead of writing them into the
+//! output directory to be synced afterwards.
+
+use std::time::Duration;
+
+#[cfg(all(feature = "server-client", not(target_os = "wasi")))]
+pub use store::Remote;
+#[cfg(not(all(feature = "server-client", not(target_os = "wasi"))))]
+pub use unsupported::Remote;
+
+/// How uploads to object storage are spread out and retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RemoteOptions {
+    /// Uploads in flight at once, the workers wait for one to finish beyond it
+    pub concurrency: usize,
+    /// How many times a failed upload is retried
+    pub retries: usize,
+    /// How long an upload is retried for at most
+    pub retry_timeout: Duration,
+}
+
+#[cfg(all(feature = "server-client", not(target_os = "wasi")))]
+mod store {
+    use std::{
+        ffi::OsStr,
+        io,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::{Context, Result};
+    use object_store::{
+        aws::AmazonS3Builder, azure::MicrosoftAzureBuilder, gcp::GoogleCloudStorageBuilder,
+        path::Path as ObjectPath, Attribute, Attributes, BackoffConfig, ObjectStore, PutOptions,
+        RetryConfig,
+    };
+    use tokio::{runtime::Runtime, sync::Semaphore};
+    use tracing::error;
+    use url::Url;
+
+    use super::RemoteOptions;
+
+    /// An `--out` URL like `s3://bucket/prefix` the generated files are uploaded to.
+    pub struct Remote {
+        url: String,
+        store: Arc<dyn ObjectStore>,
+        /// Key prefix of every uploaded file, without slashes around it
+        prefix: String,
+        staging: PathBuf,
+        concurrency: usize,
+        /// One permit for every upload in flight
+        permits: Arc<Semaphore>,
+        /// Every upload that failed since the last flush
+        failed: Arc<Mutex<Vec<String>>>,
+        runtime: Runtime,
+    }
+
+    impl Remote {
+        /// Connects to the object storage `out` points to, or none if it's a local directory.
+        ///
+        /// The scheme picks the backend:
+        /// - `s3://bucket/prefix` and `s3a://`, also for S3-compatible endpoints set with
+        ///   `AWS_ENDPOINT`
+        /// - `gs://bucket/prefix`
+        /// - `az://container/prefix`, `azure://`, `abfs://`, and `abfss://`
+        ///
+        /// Credentials come from the backend's usual environment variables, like `AWS_*`,
+        /// `GOOGLE_*`, and `AZURE_*`.
+        pub fn parse(out: &Path, options: RemoteOptions) -> Result<Option<Self>> {
+            let Some(url) = out.to_str().filter(|out| out.contains("://")) else {
+                return Ok(None);
+            };
+            let parsed = Url::parse(url).with_context(|| format!("invalid --out URL {url}"))?;
+            let host = parsed.host_str().unwrap_or_default();
+            anyhow::ensure!(!host.is_empty(), "{url} is missing a bucket");
+
+            let retry = RetryConfig {
+                max_retries: options.retries,
+                retry_timeout: options.retry_timeout,
+                backoff: BackoffConfig::default(),
+            };
+            let store: Arc<dyn ObjectStore> = match parsed.scheme() {
+                "s3" | "s3a" => Arc::new(
+                    AmazonS3Builder::from_env()
+                        .with_url(url)
+                        .with_retry(retry)
+                        .build()?,
+                ),
+                "gs" => Arc::new(
+                    GoogleCloudStorageBuilder::from_env()
+                        .with_url(url)
+                        .with_retry(retry)
+                        .build()?,
+                ),
+                "az" | "azure" | "abfs" | "abfss" => Arc::new(
+                    MicrosoftAzureBuilder::from_env()
+                        .with_url(url)
+                        .with_retry(retry)
+                        .build()?,
+                ),
+                scheme => {
+                    anyhow::bail!(
+                        "unsupported --out scheme {scheme}://, expected s3, gs, az, or abfss"
+                    )
+                }
+            };
+
+            let prefix = parsed.path().trim_matches('/').to_owned();
+            let staging = std::env::temp_dir()
+                .join("hibp-json")
+                .join(parsed.scheme())
+                .join(host)
+                .join(&prefix);
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()?;
+
+            Ok(Some(Self {
+                url: url.into(),
+                store,
+                prefix,
+                staging,
+                concurrency: options.concurrency,
+                permits: Arc::new(Semaphore::new(options.concurrency)),
+                failed: Arc::default(),
+                runtime,
+            }))
+        }
+
+        pub fn url(&self) -> &str {
+            &self.url
+        }
+
+        /// The local directory the generated paths are relative to, which only holds the metadata and
+        /// progress of a run until it's uploaded.
+        pub fn staging(&self) -> &Path {
+            &self.staging
+        }
+
+        /// Starts uploading the file that would have been written to `path` within the staging
+        /// directory, once less than the configured amount of uploads are in flight.
+        ///
+        /// Failures are reported by [`Remote::flush`].
+        pub fn put(&self, path: &Path, data: Vec<u8>) -> io::Result<()> {
+            let name = path.strip_prefix(&self.staging).unwrap_or(path);
+            let mut key = self.prefix.clone();
+            for component in name.components() {
+                if !key.is_empty() {
+                    key.push('/');
+                }
+                key.push_str(&component.as_os_str().to_string_lossy());
+            }
+
+            let options = PutOptions {
+                attributes: attributes(path),
+                ..Default::default()
+            };
+            let permit = self
+                .runtime
+                .block_on(Arc::clone(&self.permits).acquire_owned())
+                .map_err(io::Error::other)?;
+
+            let store = Arc::clone(&self.store);
+            let failed = Arc::clone(&self.failed);
+            self.runtime.spawn(async move {
+                let location = ObjectPath::from(key);
+                if let Err(err) = store.put_opts(&location, data.into(), options).await {
+                    failed.lock().unwrap().push(format!("{location}: {err}"));
+                }
+                drop(permit);
+            });
+
+            Ok(())
+        }
+
+        /// Starts uploading the file at `path` within the staging directory as it is on disk.
+        pub fn put_file(&self, path: &Path) -> io::Result<()> {
+            self.put(path, std::fs::read(path)?)
+        }
+
+        /// Waits for every upload in flight, and fails if any of them failed.
+        pub fn flush(&self) -> Result<()> {
+            let all = u32::try_from(self.concurrency)?;
+            drop(self.runtime.block_on(self.permits.acquire_many(all))?);
+
+            let failed = std::mem::take(&mut *self.failed.lock().unwrap());
+            if failed.is_empty() {
+                return Ok(());
+            }
+            for failure in &failed {
+                error!("  {failure}");
+            }
+            anyhow::bail!(
+                "{} uploads to {} failed, re-run without --resume to upload them again",
+                failed.len(),
+                self.url
+            )
+        }
+    }
+
+    /// The `Content-Type` and `Content-Encoding` the generated file at `path` is served with.
+    fn attributes(path: &Path) -> Attributes {
+        let mut attributes = Attributes::new();
+        let mut name = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+
+        let encodings = [
+            (".gz", "gzip"),
+            (".br", "br"),
+            (".dcb", "dcb"),
+            (".zst", "zstd"),
+        ];
+        for (suffix, encoding) in encodings {
+            if let Some(base) = name.strip_suffix(suffix) {
+                name = base;
+                attributes.insert(Attribute::ContentEncoding, encoding.into());
+                break;
+            }
+        }
+
+        let content_type = match name.rsplit_once('.').map(|(_, extension)| extension) {
+            Some("json") => "application/json",
+            Some("ndjson") => "application/x-ndjson",
+            Some("csv") => "text/csv",
+            Some("sig") => "text/plain",
+            Some("html") => "text/html; charset=utf-8",
+            Some("js") => "text/javascript",
+            _ => "application/octet-stream",
+        };
+        attributes.insert(Attribute::ContentType, content_type.into());
+
+        attributes
+    }
+}
+
+#[cfg(not(all(feature = "server-client", not(target_os = "wasi"))))]
+mod unsupported {
+    use std::{io, path::Path};
+
+    use anyhow::Result;
+
+    use super::RemoteOptions;
+
+    /// Never created, as builds for wasi or without the `server-client` feature can't upload.
+    pub enum Remote {}
+
+    impl Remote {
+        pub fn parse(out: &Path, _options: RemoteOptions) -> Result<Option<Self>> {
+            anyhow::ensure!(
+                !out.to_str().is_some_and(|out| out.contains("://")),
+                "--out {} needs a build with the server-client feature and not for wasi",
+                out.display()
+            );
+            Ok(None)
+        }
+
+        pub fn url(&self) -> &str {
+            match *self {}
+        }
+
+        pub fn staging(&self) -> &Path {
+            match *self {}
+        }
+
+        pub fn put(&self, _path: &Path, _data: Vec<u8>) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub fn put_file(&self, _path: &Path) -> io::Result<()> {
+            match *self {}
+        }
+
+        pub fn flush(&self) -> Result<()> {
+            match *self {}
+        }
+    }
+}
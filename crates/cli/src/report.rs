@@ -0,0 +1,150 @@
+//! The end-of-run report written with `--report`, in a stable schema so that release pipelines
+//! can assert on it instead of parsing the log.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{
+    dataset::FormatBytes,
+    error::GenerateError,
+    generate::{write_atomic, Totals},
+};
+
+/// Bumped whenever a field is removed or changes meaning, adding fields doesn't bump it.
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub status: Status,
+    pub files: Files,
+    pub entries: u64,
+    /// Malformed lines skipped with --lenient
+    pub skipped_lines: u64,
+    /// Bytes of the hash files that were processed, as they're stored
+    pub read_bytes: u64,
+    pub bytes: FormatBytes,
+    pub durations: Durations,
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Success,
+    /// Some prefixes failed with --keep-going, the others were generated
+    Partial,
+    /// Generating stopped at the first failure
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Files {
+    /// Hash files this run set out to generate
+    pub total: u64,
+    /// Hash files that were processed, successfully or not
+    pub processed: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Durations {
+    /// Since the program started
+    pub total_secs: f64,
+    pub generate_secs: f64,
+    /// Time of every stage, summed over the workers
+    pub stage_secs: BTreeMap<&'static str, f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    /// The hash file that failed, none if the run stopped on an error of its own
+    pub path: Option<PathBuf>,
+    pub error: String,
+}
+
+impl Failure {
+    pub fn new(path: Option<PathBuf>, err: &GenerateError) -> Self {
+        // every cause, like anyhow's alternate format
+        let mut error = err.to_string();
+        let mut source = err.source();
+        while let Some(cause) = source {
+            error.push_str(": ");
+            error.push_str(&cause.to_string());
+            source = cause.source();
+        }
+        Self { path, error }
+    }
+}
+
+impl Report {
+    /// The report of a run that set out to generate `total` hash files, with the `failures` of
+    /// --keep-going. `very_start` is when the program started and `start` when generating did.
+    pub fn new(
+        totals: &Totals,
+        total: u64,
+        failures: &[(PathBuf, GenerateError)],
+        very_start: Instant,
+        start: Instant,
+    ) -> Self {
+        let stage_secs = totals
+            .times
+            .stages()
+            .into_iter()
+            .map(|(stage, nanos)| (stage, Duration::from_nanos(nanos).as_secs_f64()))
+            .collect();
+
+        Self {
+            schema_version: SCHEMA_VERSION,
+            status: if failures.is_empty() {
+                Status::Success
+            } else {
+                Status::Partial
+            },
+            files: Files {
+                total,
+                processed: totals.done.load(Ordering::SeqCst),
+                failed: failures.len() as u64,
+            },
+            entries: totals.entries.load(Ordering::SeqCst),
+            skipped_lines: totals.skipped.load(Ordering::SeqCst),
+            read_bytes: totals.read.load(Ordering::SeqCst),
+            bytes: totals.bytes(),
+            durations: Durations {
+                total_secs: very_start.elapsed().as_secs_f64(),
+                generate_secs: start.elapsed().as_secs_f64(),
+                stage_secs,
+            },
+            failures: failures
+                .iter()
+                .map(|(path, err)| Failure::new(Some(path.clone()), err))
+                .collect(),
+        }
+    }
+
+    /// Marks the run as stopped by `err`.
+    pub fn failed(mut self, err: &GenerateError) -> Self {
+        self.status = Status::Failed;
+        self.files.failed += 1;
+        self.failures.push(Failure::new(None, err));
+        self
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        write_atomic(path, None, |file| {
+            serde_json::to_writer_pretty(&mut *file, self)?;
+            file.write_all(b"\n")
+        })
+        .with_context(|| format!("unable to write the report to {}", path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Signer, SigningKey};
+
+/// Reads an ed25519 secret key from `path`, stored either as 32 raw bytes or 64 hex characters.
+pub fn read_signing_key(path: &Path) -> Result<SigningKey> {
+    let raw = std::fs::read(path)
+        .with_context(|| format!("unable to read signing key {}", path.display()))?;
+
+    let seed: [u8; 32] = match raw.len() {
+        32 => raw.as_slice().try_into()?,
+        _ => decode_hex(std::str::from_utf8(&raw)?.trim())?,
+    };
+
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// Signs the file at `path`, writing the hex encoded signature next to it with a `.sig` suffix.
+///
+/// Returns the hex encoded public key the signature can be verified with.
+pub fn sign_file(key: &SigningKey, path: &Path) -> Result<String> {
+    let content = std::fs::read(path)?;
+    let signature = key.sign(&content);
+
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(".sig");
+    std::fs::write(sig, encode_hex(&signature.to_bytes()) + "\n")?;
+
+    Ok(encode_hex(&key.verifying_key().to_bytes()))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Result<[u8; 32]> {
+    if s.len() != 64 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("signing key must be 32 raw bytes or 64 hex characters");
+    }
+
+    let mut out = [0; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16)?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+    use super::*;
+
+    #[test]
+    fn signature_verifies() {
+        let dir = std::env::temp_dir().join(format!("hibp-json-sign-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (key_path, manifest) = (dir.join("key"), dir.join("manifest.json"));
+        std::fs::write(&key_path, format!("{}\n", "ab".repeat(32))).unwrap();
+        std::fs::write(&manifest, r#"{"files":{}}"#).unwrap();
+
+        let key = read_signing_key(&key_path).unwrap();
+        assert_eq!(key.to_bytes(), [0xab; 32]);
+        let public = sign_file(&key, &manifest).unwrap();
+
+        let public = VerifyingKey::from_bytes(&decode_hex(&public).unwrap()).unwrap();
+        let sig = std::fs::read_to_string(dir.join("manifest.json.sig")).unwrap();
+        let mut bytes = [0; 64];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&sig[i * 2..i * 2 + 2], 16).unwrap();
+        }
+        let signature = Signature::from_bytes(&bytes);
+        assert!(public.verify(br#"{"files":{}}"#, &signature).is_ok());
+        assert!(public.verify(br#"{"files":[]}"#, &signature).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+//! Checks there's enough free disk space for the outputs before generating them, rather than
+//! failing on a full disk hours in.
+
+use std::{io, path::Path};
+
+use anyhow::{Context, Result};
+use indicatif::HumanBytes;
+use tracing::info;
+
+use crate::{
+    exit::{self, Exit},
+    manifest::Manifest,
+};
+
+/// How many hash files are sampled to estimate the size of the outputs.
+pub const SAMPLES: usize = 100;
+
+/// Bytes of the outputs of a previous run in `dist`, as recorded by its manifest. They're
+/// replaced by the new outputs, so their space doesn't have to be free.
+fn existing(dist: &Path) -> u64 {
+    Manifest::read(dist)
+        .map(|manifest| manifest.files.values().map(|entry| entry.size).sum())
+        .unwrap_or(0)
+}
+
+/// Free bytes of the filesystem of `dist` for this user.
+#[cfg(not(target_os = "wasi"))]
+fn available_space(dist: &Path) -> io::Result<u64> {
+    fs2::available_space(dist)
+}
+
+#[cfg(target_os = "wasi")]
+fn available_space(_dist: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "wasi has no way to tell, pass --space-check false",
+    ))
+}
+
+/// Fails if the filesystem of `dist` doesn't have the `estimate` bytes the outputs take up
+/// available, with some headroom as the estimate is extrapolated from a sample.
+///
+/// The outputs of a previous run are only counted as replaced when every prefix is generated
+/// again, i.e. not with `partial` runs.
+pub fn check(dist: &Path, estimate: u64, partial: bool) -> Result<()> {
+    let available = available_space(dist)
+        .with_context(|| format!("unable to get the free space of {}", dist.display()))?;
+    let replaced = if partial { 0 } else { existing(dist) };
+    let required = (estimate + estimate / 20).saturating_sub(replaced);
+    info!(
+        "Estimated {} of outputs, {} available in {}",
+        HumanBytes(estimate),
+        HumanBytes(available),
+        dist.display()
+    );
+
+    exit::ensure!(
+        Exit::Setup,
+        required <= available,
+        "{} needs about {} free for the outputs but only has {}, free up space or pass \
+         --space-check false to skip this check",
+        dist.display(),
+        HumanBytes(required),
+        HumanBytes(available)
+    );
+    Ok(())
+}
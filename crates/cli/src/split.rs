@@ -0,0 +1,86 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use hibp_json_util::{format_prefix, PREFIX_COUNT};
+use indicatif::{HumanBytes, ProgressBar};
+use tracing::{info, warn};
+
+use crate::{generate::write_atomic, progress_style, stdin};
+
+#[derive(clap::Args, Debug)]
+pub struct SplitArgs {
+    /// Single file of every hash ordered by hash to split, e.g. pwnedpasswords.txt, or - to read
+    /// it from stdin
+    dump: PathBuf,
+
+    /// Directory to write the hash files of every prefix to, e.g. hashes/ABCDE.txt
+    #[arg(short, long, default_value = "hashes")]
+    out: PathBuf,
+}
+
+/// Writes the hash file of every prefix of a dump of `HASH:COUNT` or `PREFIX:SUFFIX:COUNT` lines,
+/// holding just the lines of the prefix being split in memory.
+pub fn run(args: &SplitArgs) -> Result<()> {
+    let (reader, bar): (Box<dyn BufRead>, _) = if stdin::is_stdin(&args.dump) {
+        (
+            Box::new(BufReader::with_capacity(1 << 20, std::io::stdin())),
+            ProgressBar::hidden(),
+        )
+    } else {
+        let file = File::open(&args.dump)
+            .with_context(|| format!("unable to open {}", args.dump.display()))?;
+        let bar = ProgressBar::new(file.metadata()?.len()).with_style(progress_style());
+        (
+            Box::new(BufReader::with_capacity(1 << 20, bar.wrap_read(file))),
+            bar,
+        )
+    };
+
+    std::fs::create_dir_all(&args.out)?;
+    let mut written = 0;
+    let mut bytes = 0;
+    let mut failed = None;
+    stdin::split(&args.dump, reader, |prefix, lines| {
+        let path = prefix_file(&args.out, prefix);
+        match write_atomic(&path, None, |file| file.write_all(&lines)) {
+            Ok(size) => {
+                written += 1;
+                bytes += size;
+                true
+            }
+            Err(err) => {
+                let context = format!("unable to write {}", path.display());
+                failed = Some(anyhow::Error::new(err).context(context));
+                false
+            }
+        }
+    })?;
+    bar.finish_and_clear();
+    if let Some(err) = failed {
+        return Err(err);
+    }
+
+    info!(
+        "Split {} into {written} hash files ({}) in {}",
+        args.dump.display(),
+        HumanBytes(bytes),
+        args.out.display()
+    );
+    if written < PREFIX_COUNT {
+        warn!(
+            "{} prefixes have no hashes and got no hash file, generate them with --strict false",
+            PREFIX_COUNT - written
+        );
+    }
+
+    Ok(())
+}
+
+/// The hash file of `prefix` in `out`, e.g. `out/ABCDE.txt`.
+fn prefix_file(out: &Path, prefix: u32) -> PathBuf {
+    out.join(format!("{}.txt", format_prefix(prefix)))
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use hibp_json_util::{format_prefix, parse_prefix};
+
+/// Modification time and size of a source file, used to detect when it changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stamp {
+    modified: u128,
+    len: u64,
+}
+
+impl Stamp {
+    pub fn of(path: &Path) -> std::io::Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+
+        Ok(Self {
+            modified,
+            len: meta.len(),
+        })
+    }
+}
+
+/// Source file stamps of the last generation, stored in the output directory.
+///
+/// The first line holds the generation settings, if those differ every prefix is considered
+/// changed because the existing outputs no longer match what would be generated.
+pub struct SourceState {
+    path: PathBuf,
+    settings: String,
+    stamps: Mutex<HashMap<u32, Stamp>>,
+}
+
+impl SourceState {
+    pub const FILE_NAME: &'static str = ".hibp-json-state";
+
+    pub fn load(dist: &Path, settings: String) -> Result<Self> {
+        let path = dist.join(Self::FILE_NAME);
+        let mut stamps = HashMap::new();
+
+        if path.exists() {
+            let mut lines = BufReader::new(File::open(&path)?).lines();
+            if lines.next().transpose()?.as_deref() == Some(settings.as_str()) {
+                for line in lines {
+                    if let Some((prefix, stamp)) = parse_line(&line?) {
+                        stamps.insert(prefix, stamp);
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            path,
+            settings,
+            stamps: Mutex::new(stamps),
+        })
+    }
+
+    /// If `prefix` was last generated from a source file with the same `stamp`.
+    pub fn is_unchanged(&self, prefix: u32, stamp: Stamp) -> bool {
+        self.stamps.lock().unwrap().get(&prefix) == Some(&stamp)
+    }
+
+    pub fn record(&self, prefix: u32, stamp: Stamp) {
+        self.stamps.lock().unwrap().insert(prefix, stamp);
+    }
+
+    /// Writes the state next to its final location and renames it into place.
+    pub fn save(self) -> Result<()> {
+        let mut stamps: Vec<_> = self.stamps.into_inner().unwrap().into_iter().collect();
+        stamps.sort_unstable_by_key(|(prefix, _)| *prefix);
+
+        let tmp = self.path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        writeln!(out, "{}", self.settings)?;
+        for (prefix, stamp) in stamps {
+            let prefix = format_prefix(prefix);
+            writeln!(out, "{prefix} {} {}", stamp.modified, stamp.len)?;
+        }
+        out.into_inner()?.sync_all()?;
+
+        std::fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u32, Stamp)> {
+    let mut parts = line.split(' ');
+    let prefix = parse_prefix(parts.next()?)?;
+    let modified = parts.next()?.parse().ok()?;
+    let len = parts.next()?.parse().ok()?;
+
+    Some((prefix, Stamp { modified, len }))
+}
@@ -0,0 +1,137 @@
+//! A static site around the generated files, so they can be dropped onto GitHub Pages or S3
+//! website hosting without running a server: a page checking passwords in the browser with
+//! k-anonymity, and an index of every directory of the tree.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use hibp_json_util::{directory_ends, prefix_path, PathTemplate, PREFIX_LEN};
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{dataset::Dataset, generate::json_path_with};
+
+const INDEX: &str = include_str!("static_site/index.html");
+const SCRIPT: &str = include_str!("static_site/hibp-json.js");
+const SCRIPT_NAME: &str = "hibp-json.js";
+const INDEX_NAME: &str = "index.html";
+
+/// What the script needs to know about the generated files.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Config<'a> {
+    /// Where the .json file of a prefix is, e.g. `{p0}/{p1}/{p2}/{p3}/{p4}.json`
+    template: String,
+    hash_field: &'a str,
+    count_field: &'a str,
+    entries: u64,
+    generated_at: u64,
+}
+
+/// The index of the directory of `prefix`, in a nested tree `depth` deep.
+fn directory_index(prefix: &str, depth: usize) -> String {
+    let children: Vec<String> = match child_width(prefix.len(), depth) {
+        Some(width) => (0..16_u32.pow(width as u32))
+            .map(|i| format!("{i:0width$X}/"))
+            .collect(),
+        None => {
+            let width = PREFIX_LEN - prefix.len();
+            (0..16_u32.pow(width as u32))
+                .map(|i| format!("{i:0width$X}.json"))
+                .collect()
+        }
+    };
+
+    let mut html = format!(
+        "<!doctype html>\n<html lang=\"en\">\n<head><meta charset=\"utf-8\"><title>{prefix}</title></head>\n\
+         <body>\n<h1>{prefix}</h1>\n<ul>\n<li><a href=\"../\">../</a></li>\n"
+    );
+    for child in children {
+        html.push_str(&format!("<li><a href=\"{child}\">{child}</a></li>\n"));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+/// How many characters name the subdirectories of a directory named by the first `len`
+/// characters of a prefix, or `None` when it holds the files.
+fn child_width(len: usize, depth: usize) -> Option<usize> {
+    directory_ends(depth)
+        .iter()
+        .find(|&&end| end > len)
+        .map(|end| end - len)
+}
+
+/// Writes the directory indexes of a nested tree `depth` deep into `dist`.
+fn write_directory_indexes(dist: &Path, depth: usize) -> Result<Vec<PathBuf>> {
+    let prefixes: Vec<String> = directory_ends(depth)
+        .iter()
+        .flat_map(|&len| (0..16_u32.pow(len as u32)).map(move |i| format!("{i:0len$X}")))
+        .collect();
+
+    prefixes
+        .into_par_iter()
+        .map(|prefix| {
+            let dir = dist.join(prefix_path(&prefix, depth));
+            // the tree isn't on disk when uploading
+            std::fs::create_dir_all(&dir)?;
+            let path = dir.join(INDEX_NAME);
+            std::fs::write(&path, directory_index(&prefix, depth))
+                .with_context(|| format!("unable to write {}", path.display()))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Writes the static site into `dist`, for files nested `depth` deep or laid out with
+/// `template`, and returns the paths of the files it wrote.
+///
+/// Directories are only indexed in nested trees, as a flat or templated one has no predictable
+/// directories to index, or only the one with every file.
+pub fn write(
+    dist: &Path,
+    depth: usize,
+    template: Option<&PathTemplate>,
+    hash_field: &str,
+    count_field: &str,
+    dataset: &Dataset,
+) -> Result<Vec<PathBuf>> {
+    let config = Config {
+        template: json_path_with(depth, template, ["{p0}", "{p1}", "{p2}", "{p3}", "{p4}"]),
+        hash_field,
+        count_field,
+        entries: dataset.entries,
+        generated_at: dataset.generated_at,
+    };
+    // the field names could otherwise end the script element
+    let config = serde_json::to_string(&config)?.replace("</", "<\\/");
+
+    let nested = template.is_none() && depth > 0;
+    let browse = match child_width(0, depth).filter(|_| nested) {
+        Some(width) => {
+            let links: String = (0..16_u32.pow(width as u32))
+                .map(|i| format!("<a href=\"{i:0width$X}/\">{i:0width$X}</a>\n"))
+                .collect();
+            format!("  <p>Browse the ranges: {links}</p>\n")
+        }
+        None => String::new(),
+    };
+
+    let index = dist.join(INDEX_NAME);
+    std::fs::write(
+        &index,
+        INDEX
+            .replace("{{CONFIG}}", &config)
+            .replace("{{BROWSE}}", &browse),
+    )
+    .with_context(|| format!("unable to write {}", index.display()))?;
+    let script = dist.join(SCRIPT_NAME);
+    std::fs::write(&script, SCRIPT)
+        .with_context(|| format!("unable to write {}", script.display()))?;
+
+    let mut written = vec![index, script];
+    if nested {
+        written.extend(write_directory_indexes(dist, depth)?);
+    }
+    Ok(written)
+}
@@ -0,0 +1,58 @@
+// Checks passwords against the ranges next to this page with k-anonymity: only the first 5
+// characters of the SHA-1 hash decide which range is downloaded, and the rest is compared
+// in the browser.
+
+async function sha1(text) {
+  const digest = await crypto.subtle.digest("SHA-1", new TextEncoder().encode(text));
+  return Array.from(new Uint8Array(digest), (b) => b.toString(16).padStart(2, "0"))
+    .join("")
+    .toUpperCase();
+}
+
+// The path of the range of `prefix`, like the generated files are laid out.
+function rangePath(prefix) {
+  return CONFIG.template
+    .replaceAll("{prefix}", prefix)
+    .replace(/\{p([0-4])\}/g, (_, i) => prefix[i]);
+}
+
+// How many times the password was seen in breaches, 0 if never.
+async function timesPwned(password) {
+  const hash = await sha1(password);
+  const prefix = hash.slice(0, 5);
+  const response = await fetch(rangePath(prefix));
+  if (response.status === 404) {
+    return 0;
+  }
+  if (!response.ok) {
+    throw new Error(`unable to download the range ${prefix}: ${response.status}`);
+  }
+
+  const entries = await response.json();
+  const entry = entries.find((e) => String(e[CONFIG.hashField]).toUpperCase() === hash);
+  return entry ? entry[CONFIG.countField] : 0;
+}
+
+const generated = new Date(CONFIG.generatedAt * 1000).toLocaleDateString();
+document.getElementById("about").textContent =
+  `${CONFIG.entries.toLocaleString()} hashes, generated ${generated}.`;
+
+document.getElementById("check").addEventListener("submit", async (event) => {
+  event.preventDefault();
+  const result = document.getElementById("result");
+  result.className = "";
+  result.textContent = "Checking...";
+
+  try {
+    const count = await timesPwned(document.getElementById("password").value);
+    if (count > 0) {
+      result.className = "pwned";
+      result.textContent = `This password was seen ${count.toLocaleString()} times in data breaches, don't use it.`;
+    } else {
+      result.className = "safe";
+      result.textContent = "This password wasn't found in any data breach.";
+    }
+  } catch (err) {
+    result.textContent = err.message;
+  }
+});
@@ -0,0 +1,284 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use hibp_json_util::PREFIX_LEN;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    dataset::FieldNames,
+    error::GenerateError,
+    fields::parse_entries,
+    find_all_hash_files, find_generated_files,
+    generate::{parse_lines, prefix_name, CountOverflow, HashType, ParseError, ParseOptions},
+    in_tier, input,
+};
+
+/// Counts are bucketed by their order of magnitude, the last bucket holds everything above.
+const BUCKETS: usize = 10;
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Path to existing hashes
+    #[arg(long, env = "HIBP_JSON_HASHES", default_value = "hashes")]
+    hashes: PathBuf,
+
+    /// Read the .json files of a generated output directory instead of the hashes
+    #[arg(long)]
+    dist: Option<PathBuf>,
+
+    /// Print the statistics as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+/// Aggregates of one or more prefixes, merged together across the parallel workers.
+#[derive(Debug, Default)]
+struct Stats {
+    prefixes: u64,
+    entries: u64,
+    occurrences: u64,
+    most_common: Option<Hit>,
+    smallest: Option<PrefixSize>,
+    largest: Option<PrefixSize>,
+    histogram: [u64; BUCKETS],
+}
+
+#[derive(Debug, Serialize)]
+struct Hit {
+    hash: String,
+    count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PrefixSize {
+    prefix: String,
+    entries: u64,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    prefixes: u64,
+    entries: u64,
+    /// Sum of all counts, how many times any of the passwords was seen in a breach
+    occurrences: u64,
+    most_common: Option<&'a Hit>,
+    entries_per_prefix: PerPrefix<'a>,
+    histogram: Vec<Bucket>,
+}
+
+#[derive(Serialize)]
+struct PerPrefix<'a> {
+    min: Option<&'a PrefixSize>,
+    avg: f64,
+    max: Option<&'a PrefixSize>,
+}
+
+#[derive(Serialize)]
+struct Bucket {
+    /// Lowest count in the bucket
+    from: u64,
+    /// Highest count in the bucket, none for the last one
+    to: Option<u64>,
+    entries: u64,
+}
+
+impl Stats {
+    fn add(&mut self, count: u64, hash: impl FnOnce() -> String) {
+        self.entries += 1;
+        self.occurrences = self.occurrences.saturating_add(count);
+        let bucket = count.checked_ilog10().unwrap_or(0) as usize;
+        self.histogram[bucket.min(BUCKETS - 1)] += 1;
+
+        if self
+            .most_common
+            .as_ref()
+            .is_none_or(|hit| count > hit.count)
+        {
+            self.most_common = Some(Hit {
+                hash: hash(),
+                count,
+            });
+        }
+    }
+
+    /// Marks everything added so far as the entries of `prefix`.
+    fn finish(mut self, prefix: String) -> Self {
+        self.prefixes = 1;
+        self.smallest = Some(PrefixSize {
+            prefix: prefix.clone(),
+            entries: self.entries,
+        });
+        self.largest = Some(PrefixSize {
+            prefix,
+            entries: self.entries,
+        });
+        self
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.prefixes += other.prefixes;
+        self.entries += other.entries;
+        self.occurrences = self.occurrences.saturating_add(other.occurrences);
+        for (bucket, entries) in self.histogram.iter_mut().zip(other.histogram) {
+            *bucket += entries;
+        }
+
+        // ties are broken by hash and prefix so the report doesn't depend on scheduling
+        self.most_common = match (self.most_common, other.most_common) {
+            (Some(a), Some(b)) if (b.count, &a.hash) > (a.count, &b.hash) => Some(b),
+            (a, b) => a.or(b),
+        };
+        self.smallest = match (self.smallest, other.smallest) {
+            (Some(a), Some(b)) if (b.entries, &b.prefix) < (a.entries, &a.prefix) => Some(b),
+            (a, b) => a.or(b),
+        };
+        self.largest = match (self.largest, other.largest) {
+            (Some(a), Some(b)) if (b.entries, &a.prefix) > (a.entries, &b.prefix) => Some(b),
+            (a, b) => a.or(b),
+        };
+
+        self
+    }
+
+    fn report(&self) -> Report<'_> {
+        let histogram = (0..BUCKETS)
+            .map(|i| Bucket {
+                from: 10u64.pow(i as u32),
+                to: (i + 1 < BUCKETS).then(|| 10u64.pow(i as u32 + 1) - 1),
+                entries: self.histogram[i],
+            })
+            .collect();
+
+        Report {
+            prefixes: self.prefixes,
+            entries: self.entries,
+            occurrences: self.occurrences,
+            most_common: self.most_common.as_ref(),
+            entries_per_prefix: PerPrefix {
+                min: self.smallest.as_ref(),
+                avg: self.entries as f64 / self.prefixes.max(1) as f64,
+                max: self.largest.as_ref(),
+            },
+            histogram,
+        }
+    }
+}
+
+impl Report<'_> {
+    fn print(&self) {
+        println!("prefixes     {}", self.prefixes);
+        println!("entries      {}", self.entries);
+        println!("occurrences  {}", self.occurrences);
+        if let Some(hit) = self.most_common {
+            println!("most common  {} ({})", hit.hash, hit.count);
+        }
+
+        let per_prefix = &self.entries_per_prefix;
+        if let (Some(min), Some(max)) = (per_prefix.min, per_prefix.max) {
+            println!();
+            println!("entries per prefix");
+            println!("  min  {:>10}  {}", min.entries, min.prefix);
+            println!("  avg  {:>10.1}", per_prefix.avg);
+            println!("  max  {:>10}  {}", max.entries, max.prefix);
+        }
+
+        println!();
+        println!("count distribution");
+        let widest = self.histogram.iter().map(|b| b.entries).max().unwrap_or(0);
+        for bucket in &self.histogram {
+            let range = match bucket.to {
+                Some(to) => format!("{}-{to}", bucket.from),
+                None => format!("{}+", bucket.from),
+            };
+            let bar = (bucket.entries * 40).checked_div(widest).unwrap_or(0) as usize;
+            println!("  {range:>21}  {:>12}  {}", bucket.entries, "█".repeat(bar));
+        }
+    }
+}
+
+/// Statistics of the hash file at `path`.
+fn hash_file(path: &Path) -> Result<Stats> {
+    let prefix = prefix_name(path)?;
+    let content = input::read_all(path).map_err(|source| GenerateError::Read {
+        path: path.into(),
+        source,
+    })?;
+
+    let mut stats = Stats::default();
+    let options = ParseOptions {
+        min_count: 0,
+        strict: false,
+        hash_type: HashType::Sha1,
+        hash_order: false,
+        lenient: false,
+        merge_duplicates: false,
+        count_overflow: CountOverflow::Error,
+    };
+    parse_lines(&content, options, |suffix, count| {
+        stats.add(count, || format!("{prefix}{suffix}"));
+        Ok::<_, std::convert::Infallible>(())
+    })
+    .map_err(|err| match err {
+        ParseError::Line(line, kind) => GenerateError::Parse {
+            path: path.into(),
+            line,
+            kind,
+        },
+        ParseError::Sink(never) => match never {},
+    })?;
+
+    Ok(stats.finish(prefix.to_uppercase()))
+}
+
+/// Statistics of the generated .json file at `path` within `dist`, whose fields are named
+/// `names`.
+fn json_file(dist: &Path, names: &FieldNames, path: &Path) -> Result<Stats> {
+    let content =
+        std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+    let entries = parse_entries(names, &content[..])
+        .with_context(|| format!("invalid json in {}", path.display()))?;
+
+    let mut stats = Stats::default();
+    for entry in &entries {
+        stats.add(entry.count, || entry.hash.to_string());
+    }
+
+    // nested layouts spread the prefix over directories, e.g. A/B/C/D/E.json
+    let prefix = match entries.first() {
+        Some(entry) if entry.hash.len() >= PREFIX_LEN => entry.hash[..PREFIX_LEN].to_uppercase(),
+        _ => path
+            .strip_prefix(dist)
+            .unwrap_or(path)
+            .with_extension("")
+            .to_string_lossy()
+            .replace(['/', '\\'], ""),
+    };
+    Ok(stats.finish(prefix))
+}
+
+pub fn run(args: &StatsArgs) -> Result<()> {
+    let stats = match &args.dist {
+        Some(dist) => {
+            let names = FieldNames::read(dist)?;
+            find_generated_files(dist, "json")?
+                .par_iter()
+                .filter(|path| !in_tier(dist, path))
+                .map(|path| json_file(dist, &names, path))
+                .try_reduce(Stats::default, |a, b| Ok(a.merge(b)))?
+        }
+        None => find_all_hash_files(&args.hashes)?
+            .par_iter()
+            .map(|path| hash_file(path))
+            .try_reduce(Stats::default, |a, b| Ok(a.merge(b)))?,
+    };
+
+    let report = stats.report();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        report.print();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Reads the hashes from stdin with `--hashes -`, so a downloader or a decompressor can be piped
+//! straight into the generator without writing the hash files to disk first.
+
+use std::{
+    io::{self, BufRead},
+    path::Path,
+    sync::mpsc,
+};
+
+use hibp_json_util::{format_prefix, parse_prefix, PREFIX_LEN};
+use indicatif::ProgressBar;
+use rayon::iter::{ParallelBridge, ParallelIterator};
+
+use crate::{
+    error::GenerateError,
+    generate::{Buffers, Generator, UTF8_BOM},
+};
+
+/// The `--hashes` that reads from stdin.
+pub const PATH: &str = "-";
+
+/// Whether `hashes` is [`PATH`].
+pub fn is_stdin(hashes: &Path) -> bool {
+    hashes.as_os_str() == PATH
+}
+
+/// Generates the prefixes of a stream of `PREFIX:SUFFIX:COUNT` or `HASH:COUNT` lines ordered by
+/// hash, skipping the ones `selected` rejects and the ones a resumed run already completed.
+/// Returns the amount of selected prefixes read.
+///
+/// The lines of a prefix are collected until the next one starts, so besides the prefixes being
+/// generated at most `read_ahead` are held in memory. Every prefix gets a virtual path like
+/// `-/ABCDE`, so that it's generated like a hash file of its own.
+pub fn run(
+    generator: &Generator,
+    reader: impl BufRead + Send,
+    read_ahead: usize,
+    selected: impl Fn(u32) -> bool + Sync,
+    bar: &ProgressBar,
+) -> Result<u64, GenerateError> {
+    let (tx, rx) = mpsc::sync_channel(read_ahead);
+    let selected = &selected;
+
+    std::thread::scope(|scope| {
+        let reading = scope.spawn(move || {
+            let mut read = 0;
+            split(Path::new(PATH), reader, |prefix, content| {
+                if !selected(prefix) {
+                    return true;
+                }
+                read += 1;
+                if generator
+                    .checkpoint
+                    .as_ref()
+                    .is_some_and(|c| c.is_done(prefix))
+                {
+                    return true;
+                }
+                let path = Path::new(PATH).join(format_prefix(prefix));
+                // the receiver is gone once the cpu stage stopped on an error
+                tx.send(generator.loaded(path, content)).is_ok()
+            })
+            .map(|()| read)
+        });
+
+        let result =
+            rx.into_iter()
+                .par_bridge()
+                .try_for_each_init(Buffers::default, |buffers, loaded| {
+                    let size = loaded.size();
+                    generator.process_loaded(&loaded, buffers)?;
+                    generator.totals.finish(size);
+                    bar.inc(size);
+                    Ok(())
+                });
+        bar.finish();
+
+        let read = reading.join().expect("the stdin reader panicked");
+        result.and(read)
+    })
+}
+
+/// Splits the lines of `reader` into the `SUFFIX:COUNT` lines of every prefix, passing them to
+/// `prefix` until it returns false. `path` is what the lines are read from, for the errors.
+pub fn split(
+    path: &Path,
+    mut reader: impl BufRead,
+    mut prefix: impl FnMut(u32, Vec<u8>) -> bool,
+) -> Result<(), GenerateError> {
+    let invalid = |message: String| GenerateError::Read {
+        path: path.into(),
+        source: io::Error::new(io::ErrorKind::InvalidData, message),
+    };
+
+    let mut line = Vec::new();
+    let mut lines = Vec::new();
+    let mut current = None;
+    for number in 1.. {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|source| GenerateError::Read {
+                path: path.into(),
+                source,
+            })?;
+        if read == 0 {
+            break;
+        }
+
+        let mut text = line.trim_ascii();
+        if number == 1 {
+            text = text.strip_prefix(UTF8_BOM).unwrap_or(text);
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (id, rest) = split_line(text).ok_or_else(|| {
+            invalid(format!(
+                "line {number}: expected a PREFIX:SUFFIX:COUNT or HASH:COUNT line"
+            ))
+        })?;
+        if current != Some(id) {
+            if let Some(previous) = current {
+                if previous > id {
+                    return Err(invalid(format!(
+                        "line {number}: {} comes after {}, the input must be ordered by hash",
+                        format_prefix(id),
+                        format_prefix(previous)
+                    )));
+                }
+                if !prefix(previous, std::mem::take(&mut lines)) {
+                    return Ok(());
+                }
+            }
+            current = Some(id);
+        }
+        lines.extend_from_slice(rest);
+        lines.extend_from_slice(b"\r\n");
+    }
+
+    if let Some(previous) = current {
+        prefix(previous, lines);
+    }
+    Ok(())
+}
+
+/// The prefix of a `PREFIX:SUFFIX:COUNT` or `HASH:COUNT` line and its `SUFFIX:COUNT` rest.
+fn split_line(line: &[u8]) -> Option<(u32, &[u8])> {
+    let prefix = std::str::from_utf8(line.get(..PREFIX_LEN)?).ok()?;
+    let rest = &line[PREFIX_LEN..];
+    Some((
+        parse_prefix(prefix)?,
+        rest.strip_prefix(b":").unwrap_or(rest),
+    ))
+}
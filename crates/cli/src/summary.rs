@@ -0,0 +1,62 @@
+//! Writes `summary.csv` with `--summary`, a row per prefix to spot anomalous prefixes and compare
+//! compression settings.
+
+use std::{io::Write, path::Path, sync::Mutex, time::Duration};
+
+use crate::{dataset::FormatBytes, generate::write_atomic};
+
+/// What generating a single prefix took and produced.
+pub struct Row {
+    pub prefix: String,
+    pub entries: u64,
+    /// Size of its hash file as it was read
+    pub raw: u64,
+    /// Size of its outputs, not counting the tiers
+    pub bytes: FormatBytes,
+    /// Time spent parsing, serializing, compressing, and writing it, not reading it
+    pub time: Duration,
+}
+
+/// The rows of every prefix generated by this run, collected by the workers.
+#[derive(Default)]
+pub struct Summary {
+    rows: Mutex<Vec<Row>>,
+}
+
+impl Summary {
+    pub const FILE_NAME: &'static str = "summary.csv";
+
+    pub fn record(&self, row: Row) {
+        self.rows.lock().unwrap().push(row);
+    }
+
+    /// Writes the rows ordered by prefix into `summary.csv` in `dist`. Returns the amount of rows.
+    pub fn write(self, dist: &Path) -> std::io::Result<usize> {
+        let mut rows = self.rows.into_inner().unwrap();
+        rows.sort_unstable_by(|a, b| a.prefix.cmp(&b.prefix));
+
+        write_atomic(&dist.join(Self::FILE_NAME), None, |file| {
+            writeln!(
+                file,
+                "prefix,entries,raw_bytes,json_bytes,gzip_bytes,brotli_bytes,dcb_bytes,time_ms"
+            )?;
+            for row in &rows {
+                writeln!(
+                    file,
+                    "{},{},{},{},{},{},{},{:.3}",
+                    row.prefix,
+                    row.entries,
+                    row.raw,
+                    row.bytes.json,
+                    row.bytes.gzip,
+                    row.bytes.brotli,
+                    row.bytes.dcb,
+                    row.time.as_secs_f64() * 1000.0
+                )?;
+            }
+            Ok(())
+        })?;
+
+        Ok(rows.len())
+    }
+}
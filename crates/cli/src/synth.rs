@@ -0,0 +1,83 @@
+use std::{collections::BTreeSet, io::Write, path::Path};
+
+use anyhow::{Context, Result};
+use hibp_json_util::{format_prefix, HEX, PREFIX_COUNT};
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use tracing::info;
+
+use crate::generate::write_atomic;
+
+#[derive(clap::Args, Debug)]
+pub struct SynthArgs {
+    /// How many prefixes to write hash files for, spread evenly over all of them
+    #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u32).range(1..=PREFIX_COUNT as i64))]
+    prefixes: u32,
+
+    /// How many hashes every hash file holds
+    #[arg(long, default_value_t = 50)]
+    entries: usize,
+
+    /// Seed of the random hashes and counts, the same seed always writes the same files
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Also generate the outputs of the fake hash files, with the generation flags given before
+    /// the subcommand
+    #[arg(long)]
+    pub generate: bool,
+}
+
+impl SynthArgs {
+    /// The prefixes to write hash files for.
+    fn prefixes(&self) -> impl Iterator<Item = u32> {
+        let step = PREFIX_COUNT / self.prefixes;
+        (0..self.prefixes).map(move |i| i * step)
+    }
+}
+
+/// Writes the hash file of `prefix` with `entries` random hashes to `hashes`, sorted and with
+/// CRLF line endings like the HIBP ones.
+fn write_prefix(hashes: &Path, prefix: u32, entries: usize, seed: u64) -> Result<()> {
+    let mut rng = fastrand::Rng::with_seed(seed ^ u64::from(prefix));
+    let mut suffixes = BTreeSet::new();
+    while suffixes.len() < entries {
+        let suffix: String = (0..35).map(|_| HEX[rng.usize(..HEX.len())]).collect();
+        suffixes.insert(suffix);
+    }
+
+    let path = hashes.join(format!("{}.txt", format_prefix(prefix)));
+    write_atomic(&path, None, |file| {
+        for suffix in &suffixes {
+            // mostly small counts with a long tail, like real passwords
+            let magnitude = rng.u32(0..24);
+            let count = rng.u64(1..=1 << magnitude);
+            write!(file, "{suffix}:{count}\r\n")?;
+        }
+        Ok(())
+    })
+    .with_context(|| format!("unable to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Writes a small tree of fake but valid hash files to `hashes`, for tests and demos that
+/// don't need the real dataset.
+pub fn run(args: &SynthArgs, hashes: &Path) -> Result<()> {
+    std::fs::create_dir_all(hashes)?;
+
+    let prefixes: Vec<u32> = args.prefixes().collect();
+    let total = prefixes.len() as u64;
+    prefixes
+        .into_par_iter()
+        .progress_count(total)
+        .try_for_each(|prefix| write_prefix(hashes, prefix, args.entries, args.seed))?;
+
+    info!(
+        "Wrote {total} fake hash files with {} hashes each to {}",
+        args.entries,
+        hashes.display()
+    );
+
+    Ok(())
+}
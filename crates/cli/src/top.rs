@@ -0,0 +1,141 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    convert::Infallible,
+    io::{stdout, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    error::GenerateError,
+    find_all_hash_files,
+    generate::{parse_lines, prefix_name, CountOverflow, HashType, ParseError, ParseOptions},
+    hash_buf::HashBuf,
+    input, Password,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct TopArgs {
+    /// Path to existing hashes
+    #[arg(long, env = "HIBP_JSON_HASHES", default_value = "hashes")]
+    hashes: PathBuf,
+
+    /// How many hashes to report
+    #[arg(short, long = "count", default_value_t = 1000)]
+    n: usize,
+
+    /// Print the hashes as a JSON array instead of HASH:COUNT lines
+    #[arg(long)]
+    json: bool,
+}
+
+/// The `n` highest count hashes seen so far.
+///
+/// A min-heap on count, where equal counts evict the highest hash first so that ties are
+/// resolved the same way no matter how the prefixes were split across workers.
+struct Top {
+    n: usize,
+    heap: BinaryHeap<Reverse<(u64, Reverse<HashBuf>)>>,
+}
+
+impl Top {
+    fn new(n: usize) -> Self {
+        Self {
+            n,
+            heap: BinaryHeap::with_capacity(n + 1),
+        }
+    }
+
+    /// Whether `count` could still make it in, to avoid building hashes that won't.
+    fn admits(&self, count: u64) -> bool {
+        self.heap.len() < self.n
+            || self
+                .heap
+                .peek()
+                .is_some_and(|Reverse((lowest, _))| count >= *lowest)
+    }
+
+    fn push(&mut self, count: u64, hash: HashBuf) {
+        self.heap.push(Reverse((count, Reverse(hash))));
+        if self.heap.len() > self.n {
+            self.heap.pop();
+        }
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        for Reverse((count, Reverse(hash))) in other.heap {
+            if self.admits(count) {
+                self.push(count, hash);
+            }
+        }
+        self
+    }
+
+    /// The hashes from highest to lowest count.
+    fn into_sorted(self) -> Vec<Password> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((count, Reverse(hash)))| Password { hash, count })
+            .collect()
+    }
+}
+
+/// Adds the hashes of the hash file at `path` to `top`.
+fn scan(path: &Path, mut top: Top) -> Result<Top, GenerateError> {
+    let prefix = prefix_name(path)?.to_uppercase();
+    let content = input::read_all(path).map_err(|source| GenerateError::Read {
+        path: path.into(),
+        source,
+    })?;
+
+    let options = ParseOptions {
+        min_count: 0,
+        strict: false,
+        hash_type: HashType::Sha1,
+        hash_order: false,
+        lenient: false,
+        merge_duplicates: false,
+        count_overflow: CountOverflow::Error,
+    };
+    parse_lines(&content, options, |suffix, count| {
+        if top.admits(count) {
+            top.push(count, HashBuf::new(&prefix, suffix));
+        }
+        Ok::<_, Infallible>(())
+    })
+    .map_err(|err| match err {
+        ParseError::Line(line, kind) => GenerateError::Parse {
+            path: path.into(),
+            line,
+            kind,
+        },
+        ParseError::Sink(never) => match never {},
+    })?;
+
+    Ok(top)
+}
+
+pub fn run(args: &TopArgs) -> Result<()> {
+    let top = find_all_hash_files(&args.hashes)?
+        .par_iter()
+        .try_fold(|| Top::new(args.n), |top, path| scan(path, top))
+        .try_reduce(|| Top::new(args.n), |a, b| Ok(a.merge(b)))?;
+    let passwords = top.into_sorted();
+
+    let mut out = BufWriter::new(stdout().lock());
+    if args.json {
+        serde_json::to_writer(&mut out, &passwords)?;
+        writeln!(out)?;
+    } else {
+        for password in &passwords {
+            writeln!(out, "{}:{}", password.hash, password.count)?;
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
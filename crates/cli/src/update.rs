@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use hibp_json_util::{format_prefix, parse_prefix, PREFIX_COUNT};
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use tracing::info;
+
+use crate::{
+    generate::{write_atomic, HashType},
+    http::Agent,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct UpdateArgs {
+    /// The range API to download the hash files from
+    #[arg(long, default_value = "https://api.pwnedpasswords.com/range/")]
+    api: String,
+}
+
+/// What the API last responded with for a range, sent back to only download it if it changed.
+#[derive(Debug, Clone)]
+struct Validator {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Validators of every downloaded range, stored in the output directory.
+struct Validators {
+    path: PathBuf,
+    ranges: Mutex<HashMap<u32, Validator>>,
+}
+
+impl Validators {
+    const FILE_NAME: &'static str = ".hibp-json-etags";
+
+    fn load(dist: &Path) -> Result<Self> {
+        let path = dist.join(Self::FILE_NAME);
+        let mut ranges = HashMap::new();
+
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                if let Some((prefix, validator)) = parse_line(&line?) {
+                    ranges.insert(prefix, validator);
+                }
+            }
+        }
+
+        Ok(Self {
+            path,
+            ranges: Mutex::new(ranges),
+        })
+    }
+
+    fn get(&self, prefix: u32) -> Option<Validator> {
+        self.ranges.lock().unwrap().get(&prefix).cloned()
+    }
+
+    fn record(&self, prefix: u32, validator: Validator) {
+        self.ranges.lock().unwrap().insert(prefix, validator);
+    }
+
+    /// Writes the validators next to their final location and renames them into place.
+    fn save(self) -> Result<()> {
+        let mut ranges: Vec<_> = self.ranges.into_inner().unwrap().into_iter().collect();
+        ranges.sort_unstable_by_key(|(prefix, _)| *prefix);
+
+        let tmp = self.path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        for (prefix, validator) in ranges {
+            let prefix = format_prefix(prefix);
+            let etag = validator.etag.unwrap_or_default();
+            let last_modified = validator.last_modified.unwrap_or_default();
+            // Last-Modified is full of spaces
+            writeln!(out, "{prefix}\t{etag}\t{last_modified}")?;
+        }
+        out.into_inner()?.sync_all()?;
+
+        std::fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(u32, Validator)> {
+    let mut parts = line.split('\t');
+    let prefix = parse_prefix(parts.next()?)?;
+    let mut part = || parts.next().filter(|p| !p.is_empty()).map(String::from);
+    let etag = part();
+    let last_modified = part();
+
+    Some((
+        prefix,
+        Validator {
+            etag,
+            last_modified,
+        },
+    ))
+}
+
+/// Downloads the range of `prefix` into `hashes` unless it's unchanged since the last download.
+/// Returns whether it was downloaded.
+fn download(
+    agent: &Agent,
+    args: &UpdateArgs,
+    hashes: &Path,
+    validators: &Validators,
+    hash_type: HashType,
+    prefix: u32,
+) -> Result<bool> {
+    let name = format_prefix(prefix);
+    let path = hashes.join(format!("{name}.txt"));
+    let url = match hash_type {
+        HashType::Sha1 => format!("{}{name}", args.api),
+        HashType::Ntlm => format!("{}{name}?mode=ntlm", args.api),
+    };
+
+    let last = validators.get(prefix).filter(|_| path.exists());
+    let mut headers = Vec::new();
+    if let Some(etag) = last.as_ref().and_then(|last| last.etag.as_deref()) {
+        headers.push(("If-None-Match", etag));
+    }
+    if let Some(modified) = last.as_ref().and_then(|last| last.last_modified.as_deref()) {
+        headers.push(("If-Modified-Since", modified));
+    }
+
+    let response = agent
+        .get(&url, &headers)
+        .with_context(|| format!("unable to download {url}"))?;
+    if response.status == 304 {
+        return Ok(false);
+    }
+
+    let validator = Validator {
+        etag: response.etag,
+        last_modified: response.last_modified,
+    };
+    let content = response.body;
+
+    write_atomic(&path, None, |file| file.write_all(&content))
+        .with_context(|| format!("unable to write {}", path.display()))?;
+    validators.record(prefix, validator);
+
+    Ok(true)
+}
+
+/// Downloads every `selected` range of the hashes of `hash_type` that changed since the last
+/// update into `hashes`.
+pub fn run(
+    args: &UpdateArgs,
+    hashes: &Path,
+    dist: &Path,
+    hash_type: HashType,
+    selected: impl Fn(u32) -> bool + Sync,
+) -> Result<()> {
+    std::fs::create_dir_all(hashes)?;
+    std::fs::create_dir_all(dist)?;
+
+    let validators = Validators::load(dist)?;
+    let agent = Agent::default();
+    let prefixes: Vec<u32> = (0..PREFIX_COUNT).filter(|p| selected(*p)).collect();
+    info!(
+        "Checking {} ranges at {} for changes",
+        prefixes.len(),
+        args.api
+    );
+
+    let total = prefixes.len() as u64;
+    let downloaded = prefixes
+        .into_par_iter()
+        .progress_count(total)
+        .map(|prefix| download(&agent, args, hashes, &validators, hash_type, prefix).map(u64::from))
+        .try_reduce(|| 0, |a, b| Ok(a + b));
+    // keep what was downloaded before a failure, so it isn't downloaded again
+    validators.save()?;
+    let downloaded = downloaded?;
+
+    info!(
+        "Downloaded {downloaded} changed ranges into {}",
+        hashes.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,322 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use hibp_json_util::{format_prefix, PathTemplate, PREFIX_COUNT};
+use indicatif::ParallelProgressIterator;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use tracing::{error, info};
+
+use crate::{
+    compress,
+    dataset::FieldNames,
+    fields::parse_entries,
+    find_all_hash_files,
+    generate::{json_path, parse_hash_file, prefix_name, CountOverflow, HashType, ParseOptions},
+    http::Agent,
+    input, keep_top, output_depth, with_suffix, Layout, Password,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Path to existing hashes
+    #[arg(long, env = "HIBP_JSON_HASHES", default_value = "hashes")]
+    hashes: PathBuf,
+
+    /// Path to the generated files
+    #[arg(long, env = "HIBP_JSON_OUT", default_value = "dist")]
+    dist: PathBuf,
+
+    /// The --min-count the files were generated with
+    #[arg(long, default_value_t = 0)]
+    min_count: u64,
+
+    /// The --top the files were generated with
+    #[arg(long)]
+    top: Option<usize>,
+
+    /// The --depth the files were generated with
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u8).range(0..=4))]
+    depth: u8,
+
+    /// The --layout the files were generated with
+    #[arg(long, value_enum, default_value_t = Layout::Nested)]
+    layout: Layout,
+
+    /// The --path-template the files were generated with
+    #[arg(long, conflicts_with_all = ["depth", "layout"])]
+    path_template: Option<PathTemplate>,
+
+    /// Compare a random sample of prefixes against the HIBP API instead of the hash files
+    #[arg(long)]
+    remote: bool,
+
+    /// How many random prefixes to compare with --remote
+    #[arg(long, default_value_t = 1000, requires = "remote")]
+    sample: usize,
+
+    /// The range API to compare against with --remote
+    #[arg(
+        long,
+        default_value = "https://api.pwnedpasswords.com/range/",
+        requires = "remote"
+    )]
+    api: String,
+}
+
+/// A generated format of a prefix that doesn't match its hash file.
+struct Problem {
+    path: PathBuf,
+    kind: ProblemKind,
+}
+
+enum ProblemKind {
+    /// None of the formats were generated for the prefix
+    Missing,
+    Unreadable(anyhow::Error),
+    Mismatch {
+        missing: usize,
+        extra: usize,
+        wrong_count: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = self.path.display();
+        match &self.kind {
+            ProblemKind::Missing => write!(f, "{path}: no generated files"),
+            ProblemKind::Unreadable(err) => write!(f, "{path}: {err:#}"),
+            ProblemKind::Mismatch {
+                missing,
+                extra,
+                wrong_count,
+            } => write!(
+                f,
+                "{path}: {missing} missing, {extra} extra, and {wrong_count} wrong count hashes"
+            ),
+        }
+    }
+}
+
+/// Reads the generated file at `path` with fields named `names`, decompressing it based on its
+/// extension.
+fn read_generated(path: &Path, names: &FieldNames) -> Result<Vec<Password>> {
+    let file = BufReader::new(File::open(path)?);
+    let reader: Box<dyn Read> = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => Box::new(compress::gzip_decoder(file)),
+        Some("br") => Box::new(compress::brotli_decoder(file)),
+        Some("zst") => Box::new(zstd::Decoder::with_buffer(file)?),
+        _ => Box::new(file),
+    };
+
+    Ok(parse_entries(names, reader)?)
+}
+
+/// Compares `actual` to `expected`, both sorted by hash.
+fn compare(expected: &[Password], actual: &[Password]) -> Option<ProblemKind> {
+    let (mut missing, mut extra, mut wrong_count) = (0, 0, 0);
+    let (mut expected, mut actual) = (expected.iter().peekable(), actual.iter().peekable());
+    loop {
+        match (expected.peek(), actual.peek()) {
+            (Some(e), Some(a)) if e.hash == a.hash => {
+                wrong_count += usize::from(e.count != a.count);
+                expected.next();
+                actual.next();
+            }
+            (Some(e), Some(a)) if e.hash < a.hash => {
+                missing += 1;
+                expected.next();
+            }
+            (Some(_), None) => {
+                missing += 1;
+                expected.next();
+            }
+            (_, Some(_)) => {
+                extra += 1;
+                actual.next();
+            }
+            (None, None) => break,
+        }
+    }
+
+    (missing + extra + wrong_count > 0).then_some(ProblemKind::Mismatch {
+        missing,
+        extra,
+        wrong_count,
+    })
+}
+
+/// Parses the `SUFFIX:COUNT` lines of `prefix` into what its generated files should hold.
+fn expected(args: &VerifyArgs, prefix: &str, content: &[u8]) -> Result<Vec<Password>> {
+    let mut expected = Vec::new();
+    let options = ParseOptions {
+        min_count: args.min_count,
+        strict: false,
+        hash_type: HashType::Sha1,
+        hash_order: false,
+        lenient: false,
+        merge_duplicates: false,
+        count_overflow: CountOverflow::Error,
+    };
+    parse_hash_file(prefix, content, options, &mut expected)
+        .map_err(|(line, kind)| anyhow::anyhow!("line {line}: {kind}"))?;
+
+    if let Some(top) = args.top {
+        keep_top(&mut expected, top);
+    }
+    for password in &mut expected {
+        password.hash.make_ascii_uppercase();
+    }
+    expected.sort_unstable_by_key(|password| password.hash);
+
+    Ok(expected)
+}
+
+/// Checks every generated format of the hash file at `path` against it.
+fn verify_prefix(args: &VerifyArgs, depth: usize, names: &FieldNames, path: &Path) -> Vec<Problem> {
+    let parsed = prefix_name(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|prefix| {
+            let content = input::read_all(path)?;
+            // hash files may be named in lowercase, the outputs never are
+            Ok((
+                prefix.to_ascii_uppercase(),
+                expected(args, prefix, &content)?,
+            ))
+        });
+
+    match parsed {
+        Ok((prefix, expected)) => check_outputs(args, depth, names, &prefix, &expected),
+        Err(err) => vec![Problem {
+            path: path.into(),
+            kind: ProblemKind::Unreadable(err),
+        }],
+    }
+}
+
+/// Checks a random prefix against the range API.
+fn verify_remote(
+    agent: &Agent,
+    args: &VerifyArgs,
+    depth: usize,
+    names: &FieldNames,
+    prefix: &str,
+) -> Vec<Problem> {
+    let fetched = agent
+        .get(&format!("{}{prefix}", args.api), &[])
+        .and_then(|response| expected(args, prefix, &response.body));
+
+    match fetched {
+        Ok(expected) => check_outputs(args, depth, names, prefix, &expected),
+        Err(err) => vec![Problem {
+            path: format!("{}{prefix}", args.api).into(),
+            kind: ProblemKind::Unreadable(err),
+        }],
+    }
+}
+
+/// Checks every generated format of `prefix`, whose fields are named `names`, against the
+/// `expected` passwords.
+fn check_outputs(
+    args: &VerifyArgs,
+    depth: usize,
+    names: &FieldNames,
+    prefix: &str,
+    expected: &[Password],
+) -> Vec<Problem> {
+    let problem = |path: &Path, kind| Problem {
+        path: path.into(),
+        kind,
+    };
+
+    let json = json_path(&args.dist, depth, args.path_template.as_ref(), prefix);
+    let outputs: Vec<_> = [
+        json.clone(),
+        with_suffix(&json, ".gz"),
+        with_suffix(&json, ".br"),
+        with_suffix(&json, ".zst"),
+    ]
+    .into_iter()
+    .filter(|path| path.exists())
+    .collect();
+    if outputs.is_empty() {
+        return vec![problem(&json, ProblemKind::Missing)];
+    }
+
+    outputs
+        .into_iter()
+        .filter_map(|path| {
+            let kind = match read_generated(&path, names) {
+                Ok(mut actual) => {
+                    // generated with either --case
+                    for password in &mut actual {
+                        password.hash.make_ascii_uppercase();
+                    }
+                    actual.sort_unstable_by_key(|password| password.hash);
+                    compare(expected, &actual)?
+                }
+                Err(err) => ProblemKind::Unreadable(err),
+            };
+            Some(problem(&path, kind))
+        })
+        .collect()
+}
+
+/// `count` distinct random prefixes, in order.
+fn sample_prefixes(count: usize) -> Vec<String> {
+    let count = count.min(PREFIX_COUNT as usize);
+    let mut prefixes = BTreeSet::new();
+    while prefixes.len() < count {
+        prefixes.insert(fastrand::u32(..PREFIX_COUNT));
+    }
+
+    prefixes.into_iter().map(format_prefix).collect()
+}
+
+pub fn run(args: &VerifyArgs) -> Result<()> {
+    let depth = output_depth(args.layout, args.depth, args.path_template.is_some());
+    let names = FieldNames::read(&args.dist)?;
+    let (total, mut problems) = if args.remote {
+        let prefixes = sample_prefixes(args.sample);
+        let agent = Agent::default();
+        info!(
+            "Comparing {} random prefixes against {}",
+            prefixes.len(),
+            args.api
+        );
+        let problems: Vec<_> = prefixes
+            .par_iter()
+            .progress_count(prefixes.len() as u64)
+            .flat_map_iter(|prefix| verify_remote(&agent, args, depth, &names, prefix))
+            .collect();
+        (prefixes.len(), problems)
+    } else {
+        let paths = find_all_hash_files(&args.hashes)?;
+        let problems: Vec<_> = paths
+            .par_iter()
+            .progress_count(paths.len() as u64)
+            .flat_map_iter(|path| verify_prefix(args, depth, &names, path))
+            .collect();
+        (paths.len(), problems)
+    };
+    problems.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if problems.is_empty() {
+        info!("Verified all {total} prefixes in {}", args.dist.display());
+        return Ok(());
+    }
+
+    for problem in &problems {
+        error!("{problem}");
+    }
+    anyhow::bail!(
+        "found {} problems verifying {total} prefixes",
+        problems.len()
+    )
+}
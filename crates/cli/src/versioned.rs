@@ -0,0 +1,56 @@
+//! Generates into a directory per version with `--version-label`, switching the `current` link to
+//! it only once the run succeeded, so a server following the link never sees a half-generated
+//! dataset.
+
+use std::{io, path::Path};
+
+use anyhow::Result;
+use hibp_json_util::CURRENT_LINK;
+
+/// Checks that `label` names a single directory next to the `current` link.
+pub fn check_label(label: &str) -> Result<()> {
+    anyhow::ensure!(
+        !label.is_empty()
+            && !matches!(label, "." | ".." | CURRENT_LINK)
+            && !label.starts_with('.')
+            && !label.contains(['/', '\\']),
+        "--version-label must be a plain directory name other than {CURRENT_LINK}, found {label:?}"
+    );
+    Ok(())
+}
+
+/// Points the `current` link in `versions` at the `label` directory next to it, replacing the
+/// link of the previous version in a single rename.
+pub fn switch_current(versions: &Path, label: &str) -> io::Result<()> {
+    // hidden, so it's never mistaken for a version
+    let tmp = versions.join(format!(".{CURRENT_LINK}.tmp"));
+    match std::fs::remove_file(&tmp) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    // relative, so the versions can be moved or mounted elsewhere
+    imp::symlink(Path::new(label), &tmp)?;
+    std::fs::rename(&tmp, versions.join(CURRENT_LINK))
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{io, path::Path};
+
+    pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, link)
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::{io, path::Path};
+
+    pub fn symlink(_target: &Path, _link: &Path) -> io::Result<()> {
+        // replacing a directory link isn't atomic on Windows
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--version-label is only supported on unix",
+        ))
+    }
+}
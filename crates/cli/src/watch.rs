@@ -0,0 +1,82 @@
+//! Keeps the outputs current by regenerating them whenever hash files change, for mirrors
+//! that trickle-update ranges.
+
+pub use imp::run;
+
+#[cfg(not(target_os = "wasi"))]
+mod imp {
+    use std::{path::Path, sync::mpsc, time::Duration};
+
+    use anyhow::{Context, Result};
+    use notify::{EventKind, RecursiveMode, Watcher};
+    use tracing::{error, info};
+
+    use crate::generate::prefix_name;
+
+    /// How long to wait for more changes after one, so a sync replacing many hash files is
+    /// regenerated in one go rather than file by file.
+    const DEBOUNCE: Duration = Duration::from_secs(2);
+
+    /// Whether `event` changed a file named after a prefix.
+    fn changes_hash_file(event: &notify::Event) -> bool {
+        matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) && event.paths.iter().any(|path| prefix_name(path).is_ok())
+    }
+
+    /// Runs `generate`, then again every time hash files in `hashes` change, until it's killed.
+    ///
+    /// Only the first run stops watching when it fails, as the outputs of a later one are retried
+    /// on the next change.
+    pub fn run(hashes: &Path, mut generate: impl FnMut() -> Result<()>) -> Result<()> {
+        let (sender, changes) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                // the receiver only goes away when watching stops
+                let _ = sender.send(event);
+            })?;
+        // watched before the first run, so changes made during it aren't missed
+        watcher
+            .watch(hashes, RecursiveMode::Recursive)
+            .with_context(|| format!("unable to watch {}", hashes.display()))?;
+
+        generate()?;
+        loop {
+            info!("Watching {} for changes", hashes.display());
+            let mut changed = false;
+            let mut event = changes.recv()?;
+            loop {
+                match event {
+                    Ok(event) => changed |= changes_hash_file(&event),
+                    Err(err) => error!("Unable to watch {}: {err}", hashes.display()),
+                }
+                match changes.recv_timeout(DEBOUNCE) {
+                    Ok(next) => event = next,
+                    Err(_) => break,
+                }
+            }
+
+            if changed {
+                info!("Hash files changed, regenerating their outputs");
+                if let Err(err) = generate() {
+                    error!("Regenerating failed, retrying on the next change: {err:?}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "wasi")]
+mod imp {
+    use std::path::Path;
+
+    use anyhow::Result;
+
+    pub fn run(hashes: &Path, _generate: impl FnMut() -> Result<()>) -> Result<()> {
+        anyhow::bail!(
+            "unable to watch {}, --watch isn't supported on wasi",
+            hashes.display()
+        )
+    }
+}
@@ -0,0 +1,105 @@
+//! Decrypts files generated with `--encrypt` before serving them.
+
+use std::{io::Read, path::Path};
+
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, KeyInit, Nonce,
+};
+use anyhow::Context;
+
+/// Size of the nonce every AES-GCM encrypted file starts with.
+const NONCE_LEN: usize = 12;
+
+/// The key the served files are decrypted with, from a `DECRYPT` setting like
+/// `age:<identity file>` or `aes-gcm:<keyfile>`.
+pub enum Decryption {
+    Age(Vec<age::x25519::Identity>),
+    /// Boxed to keep the enum small, the cipher holds its round keys
+    AesGcm(Box<Aes256Gcm>),
+}
+
+impl std::fmt::Debug for Decryption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Age(_) => f.write_str("Age"),
+            Self::AesGcm(_) => f.write_str("AesGcm"),
+        }
+    }
+}
+
+impl Decryption {
+    /// Reads the identity file or keyfile `spec` points to.
+    pub fn load(spec: &str) -> anyhow::Result<Self> {
+        let (scheme, path) = spec
+            .split_once(':')
+            .context("DECRYPT must be age:<identity file> or aes-gcm:<keyfile>")?;
+        let path = Path::new(path);
+        let content =
+            std::fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+
+        match scheme {
+            "age" => {
+                let identities = String::from_utf8(content)?
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| line.starts_with("AGE-SECRET-KEY-"))
+                    .map(|line| line.parse().map_err(anyhow::Error::msg))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                anyhow::ensure!(
+                    !identities.is_empty(),
+                    "{} holds no age identities",
+                    path.display()
+                );
+                Ok(Self::Age(identities))
+            }
+            "aes-gcm" => {
+                let cipher = Aes256Gcm::new_from_slice(&content).map_err(|_| {
+                    anyhow::anyhow!("{} must hold exactly 32 bytes", path.display())
+                })?;
+                Ok(Self::AesGcm(Box::new(cipher)))
+            }
+            other => anyhow::bail!("DECRYPT must be age or aes-gcm, found {other}"),
+        }
+    }
+
+    /// The scheme recorded as `encryption` in the `dataset.json` of the files this decrypts.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Age(_) => "age",
+            Self::AesGcm(_) => "aes-gcm",
+        }
+    }
+
+    /// Decrypts the content of a file generated with the matching `--encrypt`. AES-GCM files
+    /// are bound to their path `relative` to the dataset, like `0/0/0/0/0.json.br`, so a
+    /// file moved to another prefix fails to decrypt.
+    pub fn decrypt(&self, data: &[u8], relative: &str) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Age(identities) => {
+                let age::Decryptor::Recipients(decryptor) = age::Decryptor::new(data)? else {
+                    anyhow::bail!("the file is encrypted with a passphrase");
+                };
+                let mut reader = decryptor.decrypt(
+                    identities
+                        .iter()
+                        .map(|identity| identity as &dyn age::Identity),
+                )?;
+                let mut plain = Vec::new();
+                reader.read_to_end(&mut plain)?;
+                Ok(plain)
+            }
+            Self::AesGcm(cipher) => {
+                anyhow::ensure!(data.len() >= NONCE_LEN, "the file is truncated");
+                let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+                let payload = Payload {
+                    msg: ciphertext,
+                    aad: relative.as_bytes(),
+                };
+                cipher
+                    .decrypt(Nonce::from_slice(nonce), payload)
+                    .map_err(|_| anyhow::anyhow!("the file doesn't decrypt with the keyfile"))
+            }
+        }
+    }
+}
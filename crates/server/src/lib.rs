@@ -1,46 +1,388 @@
 use std::{
+    collections::BTreeMap,
     net::{IpAddr, SocketAddr},
     path::PathBuf,
-    sync::Arc,
+    sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use axum::{
-    body::StreamBody,
-    extract::{Path, State},
+    body::{Bytes, StreamBody},
+    extract::{Path, Query, State},
     http::{
         header::{self, ToStrError, ACCEPT_ENCODING},
-        HeaderMap, HeaderValue, StatusCode,
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
     },
     response::{Html, IntoResponse},
-    routing::get,
-    Router, Server,
+    routing::{get, post},
+    Json, Router, Server,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hibp_json_util::{prefix_path, CURRENT_LINK, MAX_DEPTH, PREFIX_LEN};
 use serde::de::{self, Deserialize};
+use sha2::{Digest, Sha256};
 use tokio_util::io::ReaderStream;
 
+use crate::decrypt::Decryption;
+
+mod decrypt;
+
+/// How often the `current` link of the roots is checked for another version.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 pub struct AppState {
-    root: PathBuf,
+    depth: usize,
+    /// Decrypts the files generated with `--encrypt` before serving them
+    decryption: Option<Decryption>,
+    sha1: Root,
+    /// The NTLM dataset at `NTLM_ROOT`, served with `?mode=ntlm` or under `/ntlm/`
+    ntlm: Option<Root>,
+}
+
+/// The hash type a request asks for, with `?mode=ntlm` like the HIBP range API.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Sha1,
+    Ntlm,
+}
+
+impl Mode {
+    /// Length of the hex hashes of the mode.
+    fn hash_len(self) -> usize {
+        match self {
+            Mode::Sha1 => 40,
+            Mode::Ntlm => 32,
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ModeQuery {
+    #[serde(default)]
+    mode: Mode,
+}
+
+/// A directory of generated files, along with the dataset being served from it.
+#[derive(Debug)]
+pub struct Root {
+    path: PathBuf,
+    served: RwLock<Arc<Served>>,
+}
+
+/// The dataset being served, from the root or the version its `current` link points at.
+#[derive(Debug)]
+pub struct Served {
+    dir: PathBuf,
+    /// Target of the `current` link written with `--version-label`, if the root has one
+    version: Option<PathBuf>,
+    dataset: Option<String>,
+    /// Names of the fields of the entries, recorded in `dataset.json`
+    fields: Fields,
+    /// How the files are encrypted at rest, recorded in `dataset.json`
+    encryption: Option<String>,
+    dictionary: Option<Dictionary>,
     json: bool,
     brotli: bool,
     gzip: bool,
 }
 
+impl Served {
+    /// Loads the dataset in `root`, following its `current` link if it has one.
+    fn load(root: &std::path::Path, depth: usize) -> Self {
+        let version = std::fs::read_link(root.join(CURRENT_LINK)).ok();
+        let dir = match &version {
+            Some(version) => root.join(version),
+            None => root.to_owned(),
+        };
+
+        let dataset = std::fs::read_to_string(dir.join("dataset.json")).ok();
+        // single prefixes may lack a compressed variant, so the formats of the dataset are
+        // preferred over probing the files of the first prefix
+        let parsed = dataset
+            .as_deref()
+            .and_then(|dataset| serde_json::from_str::<DatasetFormats>(dataset).ok());
+        let fields = parsed
+            .as_ref()
+            .map(|dataset| dataset.fields.clone())
+            .unwrap_or_default();
+        let encryption = parsed
+            .as_ref()
+            .and_then(|dataset| dataset.encryption.clone());
+        let formats = parsed.map(|dataset| dataset.formats);
+        let probe = dir.join(prefix_path("00000", depth));
+        let has = |format: &str, extension: &str| match &formats {
+            Some(formats) => formats.iter().any(|f| f == format),
+            None => probe.with_extension(extension).exists(),
+        };
+        let json = has("json", "json");
+        let brotli = has("brotli", "json.br");
+        let gzip = has("gzip", "json.gz");
+        let dictionary = std::fs::read(dir.join("dictionary.bin"))
+            .ok()
+            .filter(|_| has("dcb", "json.dcb"))
+            .map(Dictionary::new);
+
+        Self {
+            dir,
+            version,
+            dataset,
+            fields,
+            encryption,
+            dictionary,
+            json,
+            brotli,
+            gzip,
+        }
+    }
+
+    /// Checks that `decryption` is the scheme the files were encrypted with according to
+    /// `dataset.json`, so that a missing or wrong `DECRYPT` fails at startup rather than on
+    /// every request.
+    fn check_decryption(&self, decryption: Option<&Decryption>) -> anyhow::Result<()> {
+        let path = self.dir.join("dataset.json");
+        match (&self.encryption, decryption) {
+            (Some(encryption), None) => anyhow::bail!(
+                "{} records {encryption} encryption, set DECRYPT to decrypt the files",
+                path.display()
+            ),
+            (Some(encryption), Some(decryption)) if encryption != decryption.name() => {
+                anyhow::bail!(
+                    "{} records {encryption} encryption but DECRYPT is {}",
+                    path.display(),
+                    decryption.name()
+                )
+            }
+            // without a dataset.json there's nothing to tell whether the files are encrypted
+            (None, Some(decryption)) if self.dataset.is_some() => anyhow::bail!(
+                "{} records no encryption but DECRYPT is {}",
+                path.display(),
+                decryption.name()
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Root {
+    fn new(path: PathBuf, depth: usize) -> Self {
+        let served = Served::load(&path, depth);
+        Self {
+            path,
+            served: RwLock::new(Arc::new(served)),
+        }
+    }
+
+    /// The dataset to serve a request from, a single complete version even while the `current`
+    /// link is switched.
+    fn served(&self) -> Arc<Served> {
+        self.served.read().unwrap().clone()
+    }
+
+    /// Reloads the dataset once the `current` link points at another version, including when
+    /// the link only appeared since it was loaded. It blocks on the filesystem, so it's called
+    /// periodically off the request path.
+    fn refresh(&self, depth: usize) {
+        let served = self.served();
+        let version = std::fs::read_link(self.path.join(CURRENT_LINK)).ok();
+        if version.is_none() || version == served.version {
+            return;
+        }
+
+        let reloaded = Arc::new(Served::load(&self.path, depth));
+        tracing::info!("switched to {}", reloaded.dir.display());
+        *self.served.write().unwrap() = reloaded;
+    }
+}
+
+impl AppState {
+    /// The dataset of the hash type `mode` to serve a request from.
+    fn served(&self, mode: Mode) -> Result<Arc<Served>, (StatusCode, String)> {
+        match mode {
+            Mode::Sha1 => Ok(self.sha1.served()),
+            Mode::Ntlm => match &self.ntlm {
+                Some(ntlm) => Ok(ntlm.served()),
+                None => Err((
+                    StatusCode::NOT_FOUND,
+                    "no NTLM dataset is served, set NTLM_ROOT".to_owned(),
+                )),
+            },
+        }
+    }
+
+    /// Switches every root to the version its `current` link points at.
+    fn refresh(&self) {
+        self.sha1.refresh(self.depth);
+        if let Some(ntlm) = &self.ntlm {
+            ntlm.refresh(self.depth);
+        }
+    }
+}
+
+/// The shared brotli dictionary the `.dcb` files were compressed with.
+#[derive(Debug)]
+pub struct Dictionary {
+    content: Vec<u8>,
+    /// SHA-256 as the structured field clients send in `Available-Dictionary`
+    hash: String,
+}
+
+impl Dictionary {
+    pub fn new(content: Vec<u8>) -> Self {
+        let hash = format!(":{}:", STANDARD.encode(Sha256::digest(&content)));
+        Self { content, hash }
+    }
+}
+
 pub fn var_or_else(env: &str, default: &str) -> String {
     std::env::var(env).unwrap_or_else(|_| default.into())
 }
 
+/// The `[server]` section of `hibp-json.toml`, each setting is overridden by its env var.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    root: Option<PathBuf>,
+    ntlm_root: Option<PathBuf>,
+    host: Option<IpAddr>,
+    port: Option<u16>,
+    depth: Option<usize>,
+    layout: Option<String>,
+    decrypt: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    server: Config,
+}
+
+/// The part of `dataset.json` the server needs, the formats that were generated, the names
+/// of the fields and how the files are encrypted.
+#[derive(Debug, serde::Deserialize)]
+struct DatasetFormats {
+    formats: Vec<String>,
+    #[serde(default)]
+    fields: Fields,
+    #[serde(default)]
+    encryption: Option<String>,
+}
+
+/// The names of the hash and count fields of the entries, renamed with --hash-field and
+/// --count-field.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+struct Fields {
+    hash: String,
+    count: String,
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Self {
+            hash: "hash".into(),
+            count: "count".into(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config at `CONFIG`, or `hibp-json.toml` in the working directory if it exists.
+    pub fn load() -> anyhow::Result<Self> {
+        let path = match std::env::var_os("CONFIG") {
+            Some(path) => PathBuf::from(path),
+            None if std::path::Path::new("hibp-json.toml").exists() => "hibp-json.toml".into(),
+            None => return Ok(Self::default()),
+        };
+
+        let content = std::fs::read_to_string(&path)?;
+        let file: ConfigFile = toml::from_str(&content)?;
+        Ok(file.server)
+    }
+}
+
+/// Reads the `env` var, falling back to the `config` value and then to `default`.
+pub fn setting(env: &str, config: Option<impl ToString>, default: &str) -> String {
+    std::env::var(env)
+        .ok()
+        .or_else(|| config.map(|value| value.to_string()))
+        .unwrap_or_else(|| default.into())
+}
+
 #[allow(clippy::unused_async)]
 pub async fn index() -> Html<&'static str> {
     Html(include_str!("../index.html"))
 }
 
+/// Serves the `dataset.json` written by the generator, describing the dataset being served.
+#[allow(clippy::unused_async)]
+pub async fn info(
+    Query(query): Query<ModeQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    match &state.served(query.mode)?.dataset {
+        Some(dataset) => Ok((
+            [(header::CONTENT_TYPE, "application/json")],
+            dataset.clone(),
+        )),
+        None => Err((StatusCode::NOT_FOUND, "dataset.json not found".to_owned())),
+    }
+}
+
+/// Serves the shared brotli dictionary, telling dictionary-aware clients to use it for every
+/// prefix.
+#[allow(clippy::unused_async)]
+pub async fn dictionary(
+    Query(query): Query<ModeQuery>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    match &state.served(query.mode)?.dictionary {
+        Some(dictionary) => Ok((
+            [
+                (header::CONTENT_TYPE, "application/octet-stream"),
+                (
+                    HeaderName::from_static("use-as-dictionary"),
+                    r#"match="/*""#,
+                ),
+            ],
+            dictionary.content.clone(),
+        )),
+        None => Err((StatusCode::NOT_FOUND, "dictionary.bin not found".to_owned())),
+    }
+}
+
 pub async fn hash5(
+    Path(hash5): Path<Hash5>,
+    Query(query): Query<ModeQuery>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    prefix(query.mode, &hash5, &state, &headers).await
+}
+
+/// Serves the prefix of the NTLM dataset, like `/ABCDE?mode=ntlm`.
+pub async fn ntlm(
     Path(hash5): Path<Hash5>,
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
 ) -> impl IntoResponse {
-    let accepted = match get_accepted_encodings(&headers).map_err(|_| StatusCode::BAD_REQUEST) {
+    prefix(Mode::Ntlm, &hash5, &state, &headers).await
+}
+
+async fn prefix(
+    mode: Mode,
+    hash5: &Hash5,
+    state: &AppState,
+    headers: &HeaderMap,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let served = state.served(mode)?;
+    if wants_padding(headers) {
+        return padded_json(state, &served, hash5, mode).await;
+    }
+
+    let accepted = match get_accepted_encodings(headers).map_err(|_| StatusCode::BAD_REQUEST) {
         Ok(accepted) => accepted,
         Err(err) => {
             return Err((
@@ -50,34 +392,352 @@ pub async fn hash5(
         }
     };
 
-    let mut path = state.root.join(hash5.inner);
-    let mut headers = HeaderMap::new();
+    // only clients holding the same dictionary can decode the .dcb files
+    let available = headers
+        .get("available-dictionary")
+        .and_then(|value| value.to_str().ok());
+    let dcb = accepted.dcb
+        && served
+            .dictionary
+            .as_ref()
+            .is_some_and(|dictionary| available == Some(dictionary.hash.as_str()));
 
-    if state.brotli && accepted.brotli {
-        path.set_extension("json.br");
-        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("br"));
-    } else if state.gzip && accepted.gzip {
-        path.set_extension("json.gz");
-        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
-    } else if state.json {
-        path.set_extension("json");
+    // in order of preference, with their Content-Encoding
+    let mut variants = Vec::with_capacity(4);
+    if dcb {
+        variants.push(("json.dcb", Some("dcb")));
+    }
+    if served.brotli && accepted.brotli {
+        variants.push(("json.br", Some("br")));
+    }
+    if served.gzip && accepted.gzip {
+        variants.push(("json.gz", Some("gzip")));
+    }
+    if served.json {
+        variants.push(("json", None));
     }
 
-    let body = match tokio::fs::File::open(path)
-        .await
-        .map(ReaderStream::new)
-        .map(StreamBody::new)
-    {
-        Ok(body) => body,
+    // compressed variants no smaller than the .json are left out with --skip-larger-compressed,
+    // so the next one is tried when a prefix doesn't have one
+    let base = served.dir.join(prefix_path(&hash5.inner, state.depth));
+    let mut opened = Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+    for (extension, encoding) in variants {
+        let path = base.with_extension(extension);
+        opened = tokio::fs::File::open(&path)
+            .await
+            .map(|file| (path, extension, file, encoding));
+        if !matches!(&opened, Err(err) if err.kind() == std::io::ErrorKind::NotFound) {
+            break;
+        }
+    }
+    let (path, extension, file, encoding) = match opened {
+        Ok(opened) => opened,
         Err(err) => return Err((StatusCode::NOT_FOUND, format!("File not found: {err}"))),
     };
 
+    let mut headers = HeaderMap::new();
+    if let Some(encoding) = encoding {
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+    }
     headers.insert(
         header::CONTENT_TYPE,
         HeaderValue::from_static("application/json"),
     );
 
-    Ok((headers, body))
+    // encrypted files are decrypted whole, they're compressed and small
+    if let Some(decryption) = &state.decryption {
+        drop(file);
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(err) => return Err((StatusCode::NOT_FOUND, format!("File not found: {err}"))),
+        };
+        let relative = format!("{}.{extension}", prefix_path(&hash5.inner, state.depth));
+        return match decryption.decrypt(&data, &relative) {
+            Ok(plain) => Ok((headers, Bytes::from(plain)).into_response()),
+            Err(err) => Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("unable to decrypt {}: {err:#}", hash5.inner),
+            )),
+        };
+    }
+
+    let body = StreamBody::new(ReaderStream::new(file));
+    Ok((headers, body).into_response())
+}
+
+/// An entry of the `.json` files, with any further fields like the rank ignored.
+#[derive(Debug)]
+struct Entry {
+    hash: String,
+    count: u64,
+}
+
+impl Entry {
+    /// Takes the entry out of a JSON object whose fields are named like `fields`.
+    fn from_json(entry: &serde_json::Value, fields: &Fields) -> Option<Self> {
+        Some(Self {
+            hash: entry.get(&fields.hash)?.as_str()?.to_owned(),
+            count: entry.get(&fields.count)?.as_u64()?,
+        })
+    }
+}
+
+/// Whether the request asks for padding with `Add-Padding: true`, like the HIBP range API.
+fn wants_padding(headers: &HeaderMap) -> bool {
+    headers
+        .get("add-padding")
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"true"))
+}
+
+/// Fake hashes of zero-count entries to append to the `entries` of `prefix`, so the size of a
+/// response doesn't tell which prefix was queried. Like HIBP, the response ends up with a random
+/// 800 to 1,000 entries, and prefixes already holding more get up to 200 more. They're shaped
+/// like the `sample` hash of the prefix, so they can't be told apart by their length or case,
+/// or else like the hashes of `mode`.
+fn padding(prefix: &str, entries: usize, sample: Option<&str>, mode: Mode) -> Vec<String> {
+    let count = match fastrand::usize(800..=1000).checked_sub(entries) {
+        Some(count) if count > 0 => count,
+        _ => fastrand::usize(..=200),
+    };
+    let (len, lowercase) = sample.map_or((mode.hash_len(), false), |hash| {
+        (hash.len(), hash.bytes().any(|b| b.is_ascii_lowercase()))
+    });
+    let digits: &[u8] = if lowercase {
+        b"0123456789abcdef"
+    } else {
+        b"0123456789ABCDEF"
+    };
+
+    (0..count)
+        .map(|_| {
+            let mut hash = prefix.to_owned();
+            if lowercase {
+                hash.make_ascii_lowercase();
+            }
+            hash.extend((prefix.len()..len).map(|_| char::from(digits[fastrand::usize(..16)])));
+            hash
+        })
+        .collect()
+}
+
+/// Reads the entries of the prefix from its `.json` file, or else decompresses its `.gz` or
+/// `.br` file, decrypting it first when the files are encrypted.
+async fn read_json<T: serde::de::DeserializeOwned>(
+    state: &AppState,
+    served: &Served,
+    prefix: &str,
+) -> Result<T, (StatusCode, String)> {
+    let mut variants = Vec::with_capacity(3);
+    if served.json {
+        variants.push("json");
+    }
+    if served.gzip {
+        variants.push("json.gz");
+    }
+    if served.brotli {
+        variants.push("json.br");
+    }
+
+    // like for the files served as they are, a prefix may lack a compressed variant
+    let base = served.dir.join(prefix_path(prefix, state.depth));
+    let mut read = Err(std::io::Error::from(std::io::ErrorKind::NotFound));
+    for extension in variants {
+        let path = base.with_extension(extension);
+        read = tokio::fs::read(&path)
+            .await
+            .map(|data| (path, extension, data));
+        if !matches!(&read, Err(err) if err.kind() == std::io::ErrorKind::NotFound) {
+            break;
+        }
+    }
+    let (path, extension, mut data) = match read {
+        Ok(read) => read,
+        Err(err) => return Err((StatusCode::NOT_FOUND, format!("File not found: {err}"))),
+    };
+    if let Some(decryption) = &state.decryption {
+        let relative = format!("{}.{extension}", prefix_path(prefix, state.depth));
+        data = decryption.decrypt(&data, &relative).map_err(|err| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("unable to decrypt {prefix}: {err:#}"),
+            )
+        })?;
+    }
+
+    let parsed = match extension {
+        "json.gz" => serde_json::from_reader(flate2::read::MultiGzDecoder::new(&data[..])),
+        "json.br" => serde_json::from_reader(brotli::Decompressor::new(&data[..], 4096)),
+        _ => serde_json::from_slice(&data),
+    };
+    parsed.map_err(|err| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unable to parse {}: {err}", path.display()),
+        )
+    })
+}
+
+/// Reads the entries of the prefix, see [`read_json`].
+async fn read_entries(
+    state: &AppState,
+    served: &Served,
+    prefix: &str,
+) -> Result<Vec<Entry>, (StatusCode, String)> {
+    let entries: Vec<serde_json::Value> = read_json(state, served, prefix).await?;
+    entries
+        .iter()
+        .map(|entry| Entry::from_json(entry, &served.fields))
+        .collect::<Option<_>>()
+        .ok_or_else(|| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!(
+                    "an entry of {prefix} lacks a `{}` or `{}`",
+                    served.fields.hash, served.fields.count
+                ),
+            )
+        })
+}
+
+/// Looks up a single SHA-1, for services that want an answer in one call rather than the
+/// entries of its prefix. Hashes missing from the prefix are answered with `pwned: false`.
+pub async fn check(
+    Path(hash): Path<FullHash>,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let served = state.served(Mode::Sha1)?;
+    let prefix = &hash.inner[..PREFIX_LEN];
+    let entries = read_entries(&state, &served, prefix).await?;
+    // the files hold lowercase hashes when generated with --case lower
+    let count = entries
+        .iter()
+        .find(|entry| entry.hash.eq_ignore_ascii_case(&hash.inner))
+        .map_or(0, |entry| entry.count);
+
+    Ok(Json(
+        serde_json::json!({ "pwned": count > 0, "count": count }),
+    ))
+}
+
+/// Most hashes `POST /check` takes at a time.
+const MAX_BULK_CHECK: usize = 10_000;
+
+/// The answer of `POST /check` for one of its hashes.
+#[derive(Debug, serde::Serialize)]
+pub struct Checked {
+    hash: String,
+    pwned: bool,
+    count: u64,
+}
+
+/// Looks up up to [`MAX_BULK_CHECK`] SHA-1s at a time, answering in the order they were sent.
+/// The lookups are grouped by prefix, so the file of every prefix is only read once.
+pub async fn check_bulk(
+    State(state): State<Arc<AppState>>,
+    Json(hashes): Json<Vec<FullHash>>,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    if hashes.len() > MAX_BULK_CHECK {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "at most {MAX_BULK_CHECK} hashes can be checked at a time, found {}",
+                hashes.len()
+            ),
+        ));
+    }
+
+    let served = state.served(Mode::Sha1)?;
+    let mut prefixes: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        prefixes
+            .entry(&hash.inner[..PREFIX_LEN])
+            .or_default()
+            .push(i);
+    }
+
+    let mut counts = vec![0; hashes.len()];
+    for (prefix, indices) in prefixes {
+        let entries = read_entries(&state, &served, prefix).await?;
+        for i in indices {
+            counts[i] = entries
+                .iter()
+                .find(|entry| entry.hash.eq_ignore_ascii_case(&hashes[i].inner))
+                .map_or(0, |entry| entry.count);
+        }
+    }
+
+    let checked: Vec<Checked> = hashes
+        .into_iter()
+        .zip(counts)
+        .map(|(hash, count)| Checked {
+            hash: hash.inner,
+            pwned: count > 0,
+            count,
+        })
+        .collect();
+    Ok(Json(checked))
+}
+
+/// Serves the prefix as the `SUFFIX:COUNT` lines of the HIBP range API, so that existing
+/// Pwned Passwords clients can be pointed at this server unchanged.
+pub async fn range(
+    Path(hash5): Path<Hash5>,
+    Query(query): Query<ModeQuery>,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let served = state.served(query.mode)?;
+    let mut entries = read_entries(&state, &served, &hash5.inner).await?;
+    if wants_padding(&headers) {
+        let sample = entries.first().map(|entry| entry.hash.as_str());
+        let padding = padding(&hash5.inner, entries.len(), sample, query.mode);
+        entries.extend(padding.into_iter().map(|hash| Entry { hash, count: 0 }));
+    }
+
+    // uppercase and separated by CRLF like the HIBP responses, whatever --case was used
+    let mut body = String::with_capacity(entries.len() * 44);
+    for entry in &entries {
+        let suffix = entry.hash.get(hash5.inner.len()..).unwrap_or_default();
+        if !body.is_empty() {
+            body.push_str("\r\n");
+        }
+        body.push_str(&suffix.to_ascii_uppercase());
+        body.push(':');
+        body.push_str(&entry.count.to_string());
+    }
+
+    Ok(([(header::CONTENT_TYPE, "text/plain")], body))
+}
+
+/// Serves the `.json` of the prefix with the zero-count entries of [`padding`] appended. It's
+/// built for every request, so it's neither compressed nor streamed.
+async fn padded_json(
+    state: &AppState,
+    served: &Served,
+    hash5: &Hash5,
+    mode: Mode,
+) -> Result<axum::response::Response, (StatusCode, String)> {
+    let mut entries: Vec<serde_json::Value> = read_json(state, served, &hash5.inner).await?;
+    let fields = &served.fields;
+    let sample = entries
+        .first()
+        .and_then(|entry| entry.get(&fields.hash))
+        .and_then(serde_json::Value::as_str);
+    let padding = padding(&hash5.inner, entries.len(), sample, mode);
+    entries.extend(padding.into_iter().map(|hash| {
+        let mut entry = serde_json::Map::new();
+        entry.insert(fields.hash.clone(), hash.into());
+        entry.insert(fields.count.clone(), 0.into());
+        serde_json::Value::Object(entry)
+    }));
+
+    let body = serde_json::to_vec(&entries).map_err(|err| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("unable to serialize {}: {err}", hash5.inner),
+        )
+    })?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], body).into_response())
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -93,6 +753,7 @@ pub enum Error {
 pub struct AcceptedEncodings {
     brotli: bool,
     gzip: bool,
+    dcb: bool,
 }
 
 pub fn get_accepted_encodings(headers: &HeaderMap) -> Result<AcceptedEncodings, Error> {
@@ -107,6 +768,7 @@ pub fn get_accepted_encodings(headers: &HeaderMap) -> Result<AcceptedEncodings,
             match item.split(";q=").next().ok_or(Error::InvalidFormat)? {
                 "br" => acc.brotli = true,
                 "gzip" => acc.gzip = true,
+                "dcb" => acc.dcb = true,
                 _ => (),
             }
 
@@ -117,39 +779,102 @@ pub fn get_accepted_encodings(headers: &HeaderMap) -> Result<AcceptedEncodings,
 pub async fn run() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
-    let root = PathBuf::from(var_or_else("ROOT", ""));
-    let json = root.join("0/0/0/0/0.json").exists();
-    let brotli = root.join("0/0/0/0/0.json.br").exists();
-    let gzip = root.join("0/0/0/0/0.json.gz").exists();
-
-    let state = AppState {
-        root,
-        json,
-        brotli,
-        gzip,
+    let config = Config::load()?;
+    let root = PathBuf::from(setting(
+        "ROOT",
+        config.root.as_ref().map(|root| root.display()),
+        "",
+    ));
+    let depth: usize = match setting("LAYOUT", config.layout, "nested").as_str() {
+        "nested" => setting("DEPTH", config.depth, "4").parse()?,
+        "flat" => 0,
+        other => anyhow::bail!("LAYOUT must be nested or flat, found {other}"),
     };
+    anyhow::ensure!(
+        depth <= MAX_DEPTH,
+        "DEPTH must be between 0 and {MAX_DEPTH}"
+    );
 
-    let host: IpAddr = var_or_else("HOST", "127.0.0.1").parse()?;
-    let port: u16 = var_or_else("PORT", "8080").parse()?;
-    let address = SocketAddr::new(host, port);
+    let sha1 = Root::new(root, depth);
+    // not named ntlm, which would shadow its handler
+    let ntlm_root = std::env::var_os("NTLM_ROOT")
+        .map(PathBuf::from)
+        .or(config.ntlm_root)
+        .map(|root| Root::new(root, depth));
+    let served = sha1.served();
+    let decryption = std::env::var("DECRYPT")
+        .ok()
+        .or(config.decrypt)
+        .map(|spec| Decryption::load(&spec))
+        .transpose()?;
+    served.check_decryption(decryption.as_ref())?;
 
     println!(
-        "brotli: {} | gzip: {} | json: {}",
-        state.brotli, state.gzip, state.json
+        "brotli: {} | gzip: {} | json: {} | dcb: {} | depth: {} | decrypt: {}",
+        served.brotli,
+        served.gzip,
+        served.json,
+        served.dictionary.is_some(),
+        depth,
+        decryption.is_some()
     );
+    if let Some(version) = &served.version {
+        println!("following {CURRENT_LINK}, serving {}", version.display());
+    }
+
+    if let Some(ntlm) = &ntlm_root {
+        let served = ntlm.served();
+        served.check_decryption(decryption.as_ref())?;
+        println!(
+            "ntlm root: {} | brotli: {} | gzip: {} | json: {}",
+            ntlm.path.display(),
+            served.brotli,
+            served.gzip,
+            served.json
+        );
+    }
+
+    let state = Arc::new(AppState {
+        depth,
+        decryption,
+        sha1,
+        ntlm: ntlm_root,
+    });
+
+    let refreshed = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let state = refreshed.clone();
+            if let Err(err) = tokio::task::spawn_blocking(move || state.refresh()).await {
+                tracing::error!("unable to follow {CURRENT_LINK}: {err}");
+            }
+        }
+    });
 
-    if state.root.as_os_str().is_empty() {
+    let host: IpAddr = setting("HOST", config.host, "127.0.0.1").parse()?;
+    let port: u16 = setting("PORT", config.port, "8080").parse()?;
+    let address = SocketAddr::new(host, port);
+
+    if state.sha1.path.as_os_str().is_empty() {
         println!("using current working directory as root");
     } else {
-        println!("root: {}", state.root.display());
+        println!("root: {}", state.sha1.path.display());
     }
 
     println!("starting server at http://{address}/");
 
     let app = Router::new()
         .route("/", get(index))
+        .route("/info", get(info))
+        .route("/dictionary", get(dictionary))
+        .route("/range/:hash5", get(range))
+        .route("/check", post(check_bulk))
+        .route("/check/:hash", get(check))
+        .route("/ntlm/:hash5", get(ntlm))
         .route("/:hash5", get(hash5))
-        .with_state(Arc::new(state));
+        .with_state(state);
 
     Server::bind(&address)
         .serve(app.into_make_service())
@@ -160,7 +885,7 @@ pub async fn run() -> anyhow::Result<()> {
 }
 
 pub struct Hash5 {
-    inner: PathBuf,
+    inner: String,
 }
 
 pub fn hex(byte: u8) -> &'static str {
@@ -206,12 +931,236 @@ impl<'de> Deserialize<'de> for Hash5 {
             }
         }
 
-        let path = std::path::Path::new(hex(raw[0]));
-        let path = path.join(hex(raw[1]));
-        let path = path.join(hex(raw[2]));
-        let path = path.join(hex(raw[3]));
-        let path = path.join(hex(raw[4]));
+        let inner = raw.iter().map(|byte| hex(*byte)).collect();
+
+        Ok(Hash5 { inner })
+    }
+}
+
+/// A whole hex SHA-1, uppercased.
+pub struct FullHash {
+    inner: String,
+}
+
+impl<'de> Deserialize<'de> for FullHash {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let raw = <&str>::deserialize(deserializer)?;
+        let raw = raw.as_bytes();
+
+        if raw.len() != 40 {
+            return Err(de::Error::invalid_length(raw.len(), &"40"));
+        }
+
+        for byte in raw {
+            if !byte.is_ascii_hexdigit() {
+                return Err(de::Error::invalid_value(
+                    de::Unexpected::Char(*byte as char),
+                    &"ascii hex character value",
+                ));
+            }
+        }
+
+        let inner = raw.iter().map(|byte| hex(*byte)).collect();
+
+        Ok(FullHash { inner })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::HttpBody;
+
+    use super::*;
+
+    const PWNED: &str = "ABCDE0018A45C4D1DEF81644B54AB7F969B88D65";
+    const UNSEEN: &str = "ABCDEFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF";
+
+    /// Serves a flat dataset holding `entries` as the `ABCDE` prefix, with the `dataset.json`
+    /// `dataset`.
+    fn state(name: &str, dataset: &str, entries: &str) -> (PathBuf, Arc<AppState>) {
+        let dir =
+            std::env::temp_dir().join(format!("hibp-json-server-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("dataset.json"), dataset).unwrap();
+        std::fs::write(dir.join("ABCDE.json"), entries).unwrap();
+
+        let state = AppState {
+            depth: 0,
+            decryption: None,
+            sha1: Root::new(dir.clone(), 0),
+            ntlm: None,
+        };
+        (dir, Arc::new(state))
+    }
+
+    fn full_hash(hash: &str) -> FullHash {
+        FullHash {
+            inner: hash.to_owned(),
+        }
+    }
+
+    async fn json(response: axum::response::Response) -> serde_json::Value {
+        let data = response.into_body().data().await.unwrap().unwrap();
+        serde_json::from_slice(&data).unwrap()
+    }
+
+    #[test]
+    fn padding_sizes_and_shape() {
+        let sample = PWNED.to_ascii_lowercase();
+        let padded = padding("ABCDE", 10, Some(&sample), Mode::Sha1);
+        assert!((790..=990).contains(&padded.len()));
+        for hash in &padded {
+            assert_eq!(hash.len(), 40);
+            assert!(hash.starts_with("abcde"));
+            assert!(!hash.bytes().any(|b| b.is_ascii_uppercase()));
+        }
+
+        assert!(padding("ABCDE", 1_200, None, Mode::Sha1).len() <= 200);
+        let ntlm = padding("ABCDE", 0, None, Mode::Ntlm);
+        assert!(ntlm
+            .iter()
+            .all(|hash| hash.len() == 32 && hash.starts_with("ABCDE")));
+    }
+
+    #[tokio::test]
+    async fn check_single_hash() {
+        let entries = format!(r#"[{{"hash":"{}","count":3}}]"#, PWNED.to_ascii_lowercase());
+        let (dir, state) = state("check", r#"{"formats":["json"]}"#, &entries);
+
+        let found = check(Path(full_hash(PWNED)), State(state.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            json(found.into_response()).await,
+            serde_json::json!({ "pwned": true, "count": 3 })
+        );
+        let missing = check(Path(full_hash(UNSEEN)), State(state)).await.unwrap();
+        assert_eq!(
+            json(missing.into_response()).await,
+            serde_json::json!({ "pwned": false, "count": 0 })
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn check_bulk_in_order() {
+        let dataset = r#"{"formats":["json"],"fields":{"hash":"h","count":"c"}}"#;
+        let entries = format!(r#"[{{"h":"{PWNED}","c":3}}]"#);
+        let (dir, state) = state("check-bulk", dataset, &entries);
+
+        let hashes = vec![full_hash(PWNED), full_hash(UNSEEN), full_hash(PWNED)];
+        let checked = check_bulk(State(state.clone()), Json(hashes))
+            .await
+            .unwrap();
+        assert_eq!(
+            json(checked.into_response()).await,
+            serde_json::json!([
+                { "hash": PWNED, "pwned": true, "count": 3 },
+                { "hash": UNSEEN, "pwned": false, "count": 0 },
+                { "hash": PWNED, "pwned": true, "count": 3 },
+            ])
+        );
+
+        let too_many = (0..=MAX_BULK_CHECK).map(|_| full_hash(PWNED)).collect();
+        let Err((status, _)) = check_bulk(State(state), Json(too_many)).await else {
+            panic!("more than {MAX_BULK_CHECK} hashes were checked");
+        };
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follows_a_current_link_created_later() {
+        let (dir, state) = state("refresh", r#"{"formats":["json"]}"#, "[]");
+        assert!(state.sha1.served().version.is_none());
+
+        std::fs::create_dir(dir.join("v1")).unwrap();
+        std::fs::write(dir.join("v1/dataset.json"), r#"{"formats":["json"]}"#).unwrap();
+        std::os::unix::fs::symlink("v1", dir.join(CURRENT_LINK)).unwrap();
+        state.refresh();
+        let served = state.sha1.served();
+        assert_eq!(served.version.as_deref(), Some(std::path::Path::new("v1")));
+        assert_eq!(served.dir, dir.join("v1"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// The text the range handler answers the `ABCDE` prefix with.
+    async fn range_text(state: Arc<AppState>, headers: HeaderMap) -> String {
+        let hash5 = Path(Hash5 {
+            inner: "ABCDE".to_owned(),
+        });
+        let query = Query(ModeQuery { mode: Mode::Sha1 });
+        let response = range(hash5, query, State(state), headers)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+        let data = response.into_body().data().await.unwrap().unwrap();
+        String::from_utf8(data.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn range_as_hibp_text() {
+        let entries = format!(
+            r#"[{{"hash":"{}","count":3}},{{"hash":"{UNSEEN}","count":1}}]"#,
+            PWNED.to_ascii_lowercase()
+        );
+        let (dir, state) = state("range", r#"{"formats":["json"]}"#, &entries);
+
+        // uppercase suffixes whatever the case of the files
+        assert_eq!(
+            range_text(state, HeaderMap::new()).await,
+            format!("{}:3\r\n{}:1", &PWNED[5..], &UNSEEN[5..])
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn range_padding() {
+        let entries = format!(r#"[{{"hash":"{PWNED}","count":3}}]"#);
+        let (dir, state) = state("range-padding", r#"{"formats":["json"]}"#, &entries);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Add-Padding", "true".parse().unwrap());
+        let text = range_text(state, headers).await;
+        let lines: Vec<_> = text.split("\r\n").collect();
+        assert!((800..=1000).contains(&lines.len()));
+        assert_eq!(lines[0], format!("{}:3", &PWNED[5..]));
+        assert!(lines[1..]
+            .iter()
+            .all(|line| line.len() == 37 && line.ends_with(":0")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn looks_up_grouped_directories() {
+        let (dir, _) = state("depth", r#"{"formats":["json"]}"#, "[]");
+        std::fs::create_dir(dir.join("AB")).unwrap();
+        let entries = format!(r#"[{{"hash":"{PWNED}","count":3}}]"#);
+        std::fs::write(dir.join("AB/CDE.json"), entries).unwrap();
+        let state = Arc::new(AppState {
+            depth: 2,
+            decryption: None,
+            sha1: Root::new(dir.clone(), 2),
+            ntlm: None,
+        });
+
+        let found = check(Path(full_hash(PWNED)), State(state)).await.unwrap();
+        assert_eq!(
+            json(found.into_response()).await,
+            serde_json::json!({ "pwned": true, "count": 3 })
+        );
 
-        Ok(Hash5 { inner: path })
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,3 +1,64 @@
+mod range;
+mod shard;
+mod template;
+
+pub use range::{format_prefix, parse_prefix, PrefixRange, RangeError, PREFIX_COUNT};
+pub use shard::{Shard, ShardError};
+pub use template::{PathTemplate, TemplateError};
+
+/// Every hex character a prefix may contain, in ascending order.
+#[rustfmt::skip]
+pub const HEX: [&str; 16] = [
+    "0", "1", "2", "3", "4", "5", "6", "7",
+    "8", "9", "A", "B", "C", "D", "E", "F",
+];
+
+/// Length of the hash prefixes that HIBP ranges are keyed by.
+pub const PREFIX_LEN: usize = 5;
+
+/// The deepest directory nesting supported, one directory per prefix character but the last.
+pub const MAX_DEPTH: usize = PREFIX_LEN - 1;
+
+/// Name of the link in the output directory pointing at the version being served, written with
+/// `--version-label`.
+pub const CURRENT_LINK: &str = "current";
+
+/// The lengths of the leading parts of a prefix that name the directories of a layout `depth`
+/// deep. At the full depth of 4 every character names a directory, `A/B/C/D/E`, while a
+/// shallower layout names a single directory after the first `depth` characters, e.g. `AB/CDE`
+/// for 2.
+pub fn directory_ends(depth: usize) -> &'static [usize] {
+    match depth.min(MAX_DEPTH) {
+        0 => &[],
+        1 => &[1],
+        2 => &[2],
+        3 => &[3],
+        _ => &[1, 2, 3, 4],
+    }
+}
+
+/// Builds the relative path (without extension) of a prefix in a layout `depth` deep, see
+/// [`directory_ends`]. A shorter `prefix` results in the path of its directory.
+///
+/// A depth of 4 results in `A/B/C/D/E` while a depth of 2 results in `AB/CDE`.
+pub fn prefix_path(prefix: &str, depth: usize) -> String {
+    let ends = directory_ends(depth);
+    let mut path = String::with_capacity(PREFIX_LEN + ends.len());
+    for (i, c) in prefix.chars().enumerate() {
+        if ends.contains(&i) {
+            path.push('/');
+        }
+        path.push(c);
+    }
+
+    path
+}
+
+/// Amount of directories a layout with `depth` nested directories contains at its deepest level.
+pub fn directory_count(depth: usize) -> usize {
+    16_usize.pow(depth.min(MAX_DEPTH) as u32)
+}
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
@@ -11,4 +72,57 @@ mod tests {
         let result = add(2, 2);
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn prefix_path_depths() {
+        assert_eq!(prefix_path("ABCDE", 0), "ABCDE");
+        assert_eq!(prefix_path("ABCDE", 1), "A/BCDE");
+        assert_eq!(prefix_path("ABCDE", 2), "AB/CDE");
+        assert_eq!(prefix_path("ABCDE", 3), "ABC/DE");
+        assert_eq!(prefix_path("ABCDE", 4), "A/B/C/D/E");
+        // the directories themselves
+        assert_eq!(prefix_path("AB", 2), "AB");
+        assert_eq!(prefix_path("ABC", 4), "A/B/C");
+    }
+
+    #[test]
+    fn path_template() {
+        let template: PathTemplate = "{p0}{p1}/{prefix}.json".parse().unwrap();
+        assert_eq!(template.render("ABCDE"), "AB/ABCDE.json");
+        let template: PathTemplate = "{p0}{p1}/{p2}{p3}{p4}.json".parse().unwrap();
+        assert_eq!(template.render("ABCDE"), "AB/CDE.json");
+        assert!("{p0}/{p5}".parse::<PathTemplate>().is_err());
+        assert!("{p0}{p1}.json".parse::<PathTemplate>().is_err());
+    }
+
+    #[test]
+    fn prefix_range() {
+        let range: PrefixRange = "00000..=0FFFF".parse().unwrap();
+        assert_eq!(range.len(), 65_536);
+        assert!(range.contains(0x0FFFF));
+        assert!(!range.contains(0x10000));
+
+        let range: PrefixRange = "00000..10000".parse().unwrap();
+        assert_eq!(range.len(), 65_536);
+        assert!("00000-0FFFF".parse::<PrefixRange>().is_err());
+    }
+
+    #[test]
+    fn reversed_prefix_range() {
+        let parse = |s: &str| s.parse::<PrefixRange>();
+        assert_eq!(parse("0FFFF..=00000"), Err(RangeError::Empty));
+        assert_eq!(parse("10000..00000"), Err(RangeError::Empty));
+        assert_eq!(parse("10000..10000"), Err(RangeError::Empty));
+        assert_eq!(parse("10000..=10000").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn shards_are_disjoint() {
+        let shards: Vec<Shard> = ["1/3", "2/3", "3/3"].map(|s| s.parse().unwrap()).into();
+        for prefix in 0..1000 {
+            assert_eq!(shards.iter().filter(|s| s.contains(prefix)).count(), 1);
+        }
+        assert!("0/3".parse::<Shard>().is_err());
+        assert!("4/3".parse::<Shard>().is_err());
+    }
 }
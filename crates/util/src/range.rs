@@ -0,0 +1,91 @@
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+use crate::PREFIX_LEN;
+
+/// Total amount of distinct prefixes, HIBP has a range for every single one of them.
+pub const PREFIX_COUNT: u32 = 16_u32.pow(PREFIX_LEN as u32);
+
+/// Parses a 5 character hex prefix into its numeric value.
+pub fn parse_prefix(prefix: &str) -> Option<u32> {
+    if prefix.len() != PREFIX_LEN || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(prefix, 16).ok()
+}
+
+/// Formats a numeric prefix as 5 uppercase hex characters.
+pub fn format_prefix(prefix: u32) -> String {
+    format!("{prefix:05X}")
+}
+
+/// An inclusive range of prefixes such as `00000..=0FFFF` or the exclusive `00000..10000`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrefixRange(RangeInclusive<u32>);
+
+impl PrefixRange {
+    pub fn contains(&self, prefix: u32) -> bool {
+        self.0.contains(&prefix)
+    }
+
+    /// Amount of prefixes in the range.
+    pub fn len(&self) -> u32 {
+        if self.0.is_empty() {
+            0
+        } else {
+            self.0.end() - self.0.start() + 1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for PrefixRange {
+    type Err = RangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |p: &str| parse_prefix(p).ok_or_else(|| RangeError::Prefix(p.into()));
+
+        let (start, end) = if let Some((start, end)) = s.split_once("..=") {
+            (parse(start)?, parse(end)?)
+        } else if let Some((start, end)) = s.split_once("..") {
+            let (start, end) = (parse(start)?, parse(end)?);
+            (start, end.checked_sub(1).ok_or(RangeError::Empty)?)
+        } else {
+            return Err(RangeError::Format);
+        };
+
+        // a reversed range would silently select nothing
+        if start > end {
+            return Err(RangeError::Empty);
+        }
+        Ok(Self(start..=end))
+    }
+}
+
+impl fmt::Display for PrefixRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:05X}..={:05X}", self.0.start(), self.0.end())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeError {
+    Format,
+    Prefix(String),
+    Empty,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Format => f.write_str("expected a range like 00000..=0FFFF"),
+            Self::Prefix(p) => write!(f, "`{p}` is not a 5 character hex prefix"),
+            Self::Empty => f.write_str("range is empty"),
+        }
+    }
+}
+
+impl std::error::Error for RangeError {}
@@ -0,0 +1,57 @@
+use std::{fmt, str::FromStr};
+
+/// Shard `index` of `count`, written as `index/count` where `index` starts at 1.
+///
+/// Prefixes are assigned round-robin so every shard gets a similar amount of work.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    index: u32,
+    count: u32,
+}
+
+impl Shard {
+    pub fn contains(self, prefix: u32) -> bool {
+        prefix % self.count == self.index - 1
+    }
+}
+
+impl FromStr for Shard {
+    type Err = ShardError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s.split_once('/').ok_or(ShardError::Format)?;
+        let index = index.parse().map_err(|_| ShardError::Format)?;
+        let count = count.parse().map_err(|_| ShardError::Format)?;
+
+        if index == 0 || index > count {
+            return Err(ShardError::OutOfBounds { index, count });
+        }
+
+        Ok(Self { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShardError {
+    Format,
+    OutOfBounds { index: u32, count: u32 },
+}
+
+impl fmt::Display for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Format => f.write_str("expected a shard like 1/4"),
+            Self::OutOfBounds { index, count } => {
+                write!(f, "shard {index} must be between 1 and {count}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShardError {}
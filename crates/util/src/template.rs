@@ -0,0 +1,105 @@
+use std::{fmt, str::FromStr};
+
+use crate::PREFIX_LEN;
+
+/// An output path template such as `{p0}{p1}/{prefix}.json`.
+///
+/// `{prefix}` is replaced by the whole prefix and `{p0}` through `{p4}` by its individual characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Prefix,
+    Char(usize),
+}
+
+impl PathTemplate {
+    /// Renders the template for a 5 character `prefix`.
+    pub fn render(&self, prefix: &str) -> String {
+        self.parts
+            .iter()
+            .fold(String::with_capacity(32), |mut acc, part| {
+                match part {
+                    Part::Literal(s) => acc.push_str(s),
+                    Part::Prefix => acc.push_str(prefix),
+                    Part::Char(i) => acc.push_str(&prefix[*i..=*i]),
+                }
+                acc
+            })
+    }
+
+    /// Renders the template with `prefix` and `chars` standing in for the prefix and its
+    /// characters, e.g. the capture groups of a web server's rewrite rule.
+    pub fn render_with(&self, prefix: &str, chars: [&str; PREFIX_LEN]) -> String {
+        self.parts.iter().fold(String::new(), |mut acc, part| {
+            match part {
+                Part::Literal(s) => acc.push_str(s),
+                Part::Prefix => acc.push_str(prefix),
+                Part::Char(i) => acc.push_str(chars[*i]),
+            }
+            acc
+        })
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].into()));
+            }
+
+            let end = rest[start..].find('}').ok_or(TemplateError::Unclosed)?;
+            let name = &rest[start + 1..start + end];
+            parts.push(match name {
+                "prefix" => Part::Prefix,
+                _ => match name.strip_prefix('p').and_then(|i| i.parse().ok()) {
+                    Some(i) if i < PREFIX_LEN => Part::Char(i),
+                    _ => return Err(TemplateError::Unknown(name.into())),
+                },
+            });
+
+            rest = &rest[start + end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.into()));
+        }
+
+        if !parts.iter().any(|p| matches!(p, Part::Prefix))
+            && !(0..PREFIX_LEN).all(|i| parts.contains(&Part::Char(i)))
+        {
+            return Err(TemplateError::Ambiguous);
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    Unclosed,
+    Unknown(String),
+    Ambiguous,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unclosed => f.write_str("template has an unclosed `{`"),
+            Self::Unknown(name) => write!(f, "unknown template placeholder `{{{name}}}`"),
+            Self::Ambiguous => f.write_str("template must contain {prefix} or all of {p0} to {p4}"),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}